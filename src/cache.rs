@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::{self, BoxFuture};
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use crate::packet::message::DnsMessage;
+use crate::packet::qname::Qname;
+use crate::packet::query_type::QueryType;
+use crate::packet::record::DnsRecord;
+
+/// Responses are cached per question.
+pub type CacheKey = (Qname, QueryType);
+
+/// Layout version of cache files, files of any other are not loaded.
+const CACHE_FILE_VERSION: u32 = 1;
+
+/// Response as handed out by a [`CacheBackend`].
+#[derive(Debug, Clone)]
+pub struct CachedEntry {
+    /// The response with the TTLs it was stored with.
+    pub response: DnsMessage,
+    /// How long ago it was stored.
+    pub age: Duration,
+    /// How often it was handed out before, 0 if the backend doesn't count.
+    pub hits: u32,
+}
+
+/// Storage of cached responses.
+///
+/// The in-memory [`MemoryCache`] is used by default, a store shared by
+/// several servers can take its place. TTLs are taken care of by the
+/// server, a backend only has to keep an entry for the `ttl` it is put with
+/// and report its age. Entries reported as popular are refreshed before
+/// they expire.
+pub trait CacheBackend: Send + Sync {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, Option<CachedEntry>>;
+
+    fn put<'a>(&'a self, key: CacheKey, response: DnsMessage, ttl: Duration) -> BoxFuture<'a, ()>;
+
+    /// Every entry which hasn't expired yet, for saving the cache to a
+    /// file. Stores outliving the server needn't be saved, so by default
+    /// there are none.
+    fn entries(&self) -> BoxFuture<'_, Vec<(CacheKey, CachedEntry)>> {
+        Box::pin(future::ready(Vec::new()))
+    }
+}
+
+struct MemoryEntry {
+    response: DnsMessage,
+    inserted: Instant,
+    expires: Instant,
+    hits: u32,
+}
+
+/// Cache backend in a `HashMap` of the process.
+#[derive(Default)]
+pub struct MemoryCache {
+    entries: Mutex<HashMap<CacheKey, MemoryEntry>>,
+}
+
+impl CacheBackend for MemoryCache {
+    fn get<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, Option<CachedEntry>> {
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+
+        let entry = match entries.get_mut(key) {
+            Some(entry) if entry.expires > now => {
+                let cached = CachedEntry {
+                    response: entry.response.clone(),
+                    age: now - entry.inserted,
+                    hits: entry.hits,
+                };
+                entry.hits = entry.hits.saturating_add(1);
+                Some(cached)
+            }
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        };
+        Box::pin(future::ready(entry))
+    }
+
+    fn put<'a>(&'a self, key: CacheKey, response: DnsMessage, ttl: Duration) -> BoxFuture<'a, ()> {
+        let now = Instant::now();
+        self.entries.lock().unwrap().insert(
+            key,
+            MemoryEntry {
+                response,
+                inserted: now,
+                expires: now + ttl,
+                hits: 0,
+            },
+        );
+        Box::pin(future::ready(()))
+    }
+
+    fn entries(&self) -> BoxFuture<'_, Vec<(CacheKey, CachedEntry)>> {
+        let now = Instant::now();
+        let entries = self
+            .entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, entry)| entry.expires > now)
+            .map(|(key, entry)| {
+                let cached = CachedEntry {
+                    response: entry.response.clone(),
+                    age: now - entry.inserted,
+                    hits: entry.hits,
+                };
+                (key.clone(), cached)
+            })
+            .collect();
+        Box::pin(future::ready(entries))
+    }
+}
+
+/// Cached response, with what is needed to decide on refreshing it early.
+pub(crate) struct CacheHit {
+    /// The response with the TTLs left.
+    pub response: DnsMessage,
+    /// How often the entry was handed out before.
+    pub hits: u32,
+    /// Whether less than a tenth of the lifetime it was stored with is left.
+    pub expiring: bool,
+}
+
+/// Cache of recursively resolved responses, kept in a [`CacheBackend`].
+pub(crate) struct Cache {
+    backend: Box<dyn CacheBackend>,
+}
+
+impl Cache {
+    /// Cache in memory.
+    pub fn new() -> Self {
+        Self::with_backend(Box::<MemoryCache>::default())
+    }
+
+    pub fn with_backend(backend: Box<dyn CacheBackend>) -> Self {
+        Self { backend }
+    }
+
+    /// Get a cached response with the TTLs of its records reduced by the
+    /// time it has spent in the cache, `None` if it has already expired.
+    pub async fn get(&self, qname: &Qname, qtype: QueryType) -> Option<CacheHit> {
+        let entry = self.backend.get(&(qname.clone(), qtype)).await?;
+        let lifetime = u64::from(cache_ttl(&entry.response)?);
+        let left = lifetime.saturating_sub(entry.age.as_secs());
+
+        Some(CacheHit {
+            response: remaining_lifetime(&entry.response, entry.age.as_secs())?,
+            hits: entry.hits,
+            expiring: left * 10 < lifetime,
+        })
+    }
+
+    /// Store a response for as long as its shortest lived record is valid,
+    /// or a negative response for as long as its SOA allows.
+    ///
+    /// Responses without records carry no TTL and are not cached.
+    pub async fn insert(&self, qname: &Qname, qtype: QueryType, response: &DnsMessage) {
+        let Some(ttl) = cache_ttl(response) else {
+            return;
+        };
+
+        self.backend
+            .put(
+                (qname.clone(), qtype),
+                response.clone(),
+                Duration::from_secs(ttl.into()),
+            )
+            .await;
+    }
+
+    /// Write the entries which haven't expired to `path`, replacing the
+    /// file as a whole. Returns how many were written.
+    pub async fn save(&self, path: &Path) -> io::Result<usize> {
+        let file = self.to_file(SystemTime::now()).await;
+        let json = serde_json::to_vec(&file).map_err(io::Error::other)?;
+
+        // A crash while writing leaves the previous file intact
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, json).await?;
+        tokio::fs::rename(&partial, path).await?;
+        Ok(file.entries.len())
+    }
+
+    /// Insert the entries saved to `path` which haven't expired since, with
+    /// the time passed taken off their TTLs. Returns how many were loaded.
+    pub async fn load(&self, path: &Path) -> io::Result<usize> {
+        let json = tokio::fs::read(path).await?;
+        let file = CacheFile::parse(&json)?;
+        Ok(self.restore(file, SystemTime::now()).await)
+    }
+
+    async fn to_file(&self, now: SystemTime) -> CacheFile {
+        let saved_at = unix_secs(now);
+        let entries = self
+            .backend
+            .entries()
+            .await
+            .into_iter()
+            .filter_map(|((qname, qtype), entry)| {
+                let lifetime = u64::from(cache_ttl(&entry.response)?);
+                let left = lifetime.saturating_sub(entry.age.as_secs());
+                let response = remaining_lifetime(&entry.response, entry.age.as_secs())?;
+                Some(CacheFileEntry {
+                    name: qname.to_string(),
+                    qtype: qtype.to_string(),
+                    expires_at: saved_at + left,
+                    response: response.to_bytes(),
+                })
+            })
+            .collect();
+
+        CacheFile {
+            version: CACHE_FILE_VERSION,
+            saved_at,
+            entries,
+        }
+    }
+
+    async fn restore(&self, file: CacheFile, now: SystemTime) -> usize {
+        let now = unix_secs(now);
+        let elapsed = now.saturating_sub(file.saved_at);
+
+        let mut loaded = 0;
+        for entry in file.entries {
+            if entry.expires_at <= now {
+                continue;
+            }
+            let (Ok(qname), Ok(qtype), Ok(response)) = (
+                Qname::try_from(entry.name.as_str()),
+                entry.qtype.parse::<QueryType>(),
+                DnsMessage::from_bytes(&entry.response),
+            ) else {
+                continue;
+            };
+            let Some(response) = remaining_lifetime(&response, elapsed) else {
+                continue;
+            };
+
+            self.insert(&qname, qtype, &response).await;
+            loaded += 1;
+        }
+        loaded
+    }
+}
+
+/// Cache saved to a file, as JSON.
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    /// Seconds since the Unix epoch, the TTLs are what was left then.
+    saved_at: u64,
+    entries: Vec<CacheFileEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheFileEntry {
+    name: String,
+    #[serde(rename = "type")]
+    qtype: String,
+    /// Seconds since the Unix epoch.
+    expires_at: u64,
+    /// The response in wire format.
+    response: Vec<u8>,
+}
+
+impl CacheFile {
+    /// Parse `json`, if it is of the current version.
+    fn parse(json: &[u8]) -> io::Result<Self> {
+        #[derive(Deserialize)]
+        struct Version {
+            version: u32,
+        }
+
+        let invalid = |err| io::Error::new(io::ErrorKind::InvalidData, err);
+        let Version { version } = serde_json::from_slice(json).map_err(invalid)?;
+        if version != CACHE_FILE_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported cache file version {version}"),
+            ));
+        }
+        serde_json::from_slice(json).map_err(invalid)
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// How long `response` may be cached for, `None` if not at all.
+fn cache_ttl(response: &DnsMessage) -> Option<u32> {
+    let negative_ttl = if response.answers.is_empty() {
+        response.negative_ttl()
+    } else {
+        None
+    };
+    let ttl = negative_ttl.or_else(|| {
+        response
+            .answers
+            .iter()
+            .chain(response.authorities.iter())
+            .chain(response.resources.iter())
+            .filter(|record| record.qtype() != QueryType::Opt)
+            .map(DnsRecord::ttl)
+            .min()
+    })?;
+
+    (ttl > 0).then_some(ttl)
+}
+
+/// Copy of `response` with `elapsed` seconds taken off every TTL, `None` if
+/// a record would be left without any.
+fn remaining_lifetime(response: &DnsMessage, elapsed: u64) -> Option<DnsMessage> {
+    let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+    let age = |records: &[DnsRecord]| {
+        records
+            .iter()
+            .map(|record| match record {
+                DnsRecord::Opt { .. } => Some(record.clone()),
+                _ => match record.ttl().saturating_sub(elapsed) {
+                    0 => None,
+                    ttl => Some(record.with_ttl(ttl)),
+                },
+            })
+            .collect::<Option<Vec<_>>>()
+    };
+
+    Some(DnsMessage {
+        header: response.header.clone(),
+        questions: response.questions.clone(),
+        answers: age(&response.answers)?,
+        authorities: age(&response.authorities)?,
+        resources: age(&response.resources)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use futures::FutureExt;
+
+    use super::{Cache, CacheFile};
+    use crate::packet::qname::Qname;
+    use crate::packet::query_type::QueryType;
+    use crate::testing::{make_reply, parse_message};
+
+    #[tokio::test(start_paused = true)]
+    async fn ttl_shrinks_while_cached() {
+        // Its A record has a TTL of 60
+        let response = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        let cache = Cache::new();
+        cache.insert(&qname, QueryType::A, &response).await;
+
+        let ttl = |cache: &Cache| {
+            let hit = cache.get(&qname, QueryType::A).now_or_never().unwrap();
+            hit.map(|hit| hit.response.answers[0].ttl())
+        };
+        assert_eq!(ttl(&cache), Some(60));
+
+        tokio::time::advance(Duration::from_millis(20_500)).await;
+        assert_eq!(ttl(&cache), Some(40));
+
+        tokio::time::advance(Duration::from_secs(39)).await;
+        assert_eq!(ttl(&cache), Some(1));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(ttl(&cache), None);
+    }
+
+    #[tokio::test]
+    async fn saved_entries_are_loaded_until_they_expire() {
+        let fresh = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let mut short = parse_message(&make_reply(2, "example.org", Ipv4Addr::new(5, 6, 7, 8)));
+        short.answers[0] = short.answers[0].with_ttl(5);
+
+        let com = Qname::try_from("example.com").unwrap();
+        let org = Qname::try_from("example.org").unwrap();
+        let cache = Cache::new();
+        cache.insert(&com, QueryType::A, &fresh).await;
+        cache.insert(&org, QueryType::A, &short).await;
+
+        let saved_at = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let json = serde_json::to_vec(&cache.to_file(saved_at).await).unwrap();
+
+        // Restarted 10 seconds later
+        let cache = Cache::new();
+        let file = CacheFile::parse(&json).unwrap();
+        let loaded = cache
+            .restore(file, saved_at + Duration::from_secs(10))
+            .await;
+        assert_eq!(loaded, 1);
+
+        let hit = cache.get(&com, QueryType::A).await.unwrap();
+        assert_eq!(hit.response.answers[0].ttl(), 50);
+        assert!(cache.get(&org, QueryType::A).await.is_none());
+    }
+
+    #[test]
+    fn other_cache_file_version_is_rejected() {
+        let json = br#"{"version": 2, "saved_at": 0, "entries": []}"#;
+        let err = CacheFile::parse(json).err().unwrap();
+        assert_eq!(err.to_string(), "unsupported cache file version 2");
+    }
+}
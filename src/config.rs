@@ -0,0 +1,203 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use ipnet::IpNet;
+
+use crate::packet::qname::Qname;
+use crate::packet::query_type::QueryType;
+use crate::policy::BlockAction;
+use crate::resolver::ROOT_SERVERS;
+use crate::zone::Zone;
+
+/// How cache misses are resolved.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum ResolveMode {
+    /// Walk the delegations down from `root_servers`.
+    #[default]
+    Recursive,
+    /// Forward to this DNS-over-HTTPS endpoint, e.g.
+    /// `https://cloudflare-dns.com/dns-query`.
+    DnsOverHttps(String),
+    /// Forward to the DNS-over-TLS server at `addr`, checking its certificate
+    /// against `server_name` if given, or else against the address.
+    DnsOverTls {
+        addr: SocketAddr,
+        server_name: Option<String>,
+    },
+}
+
+/// Runtime settings of the server.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Address client queries are received on.
+    pub listen_addr: SocketAddr,
+    pub resolve_mode: ResolveMode,
+    /// Nameservers recursion starts at, IPv4 or IPv6. The first hop is picked
+    /// at random unless `stable_answer_order` is set.
+    pub root_servers: Vec<IpAddr>,
+    /// Port nameservers are queried on, only ever not 53 for testing.
+    pub nameserver_port: u16,
+    /// How long to wait for a nameserver to answer a single query.
+    pub query_timeout: Duration,
+    /// How many times a query is resent to an unresponsive nameserver.
+    pub max_retries: usize,
+    /// How long resolving a question recursively may take in total, across
+    /// all the nameservers asked along the way.
+    ///
+    /// An unresponsive nameserver takes up `query_timeout` times one plus
+    /// `max_retries`, which has to be well below this for other nameservers
+    /// to get a chance.
+    pub resolve_timeout: Duration,
+    /// Idle sockets kept around for queries to nameservers, per address
+    /// family.
+    pub outbound_sockets: usize,
+    /// How many candidate nameservers are queried at once, the first
+    /// response wins.
+    pub parallel_queries: usize,
+    /// How deep resolving the names of nameservers may nest.
+    pub max_recursion_depth: usize,
+    /// Consecutive failures after which a nameserver is skipped for a while.
+    pub upstream_failure_threshold: u32,
+    /// How long a failing nameserver is skipped at first.
+    pub upstream_backoff: Duration,
+    /// Upper bound of the doubling cooldown of a failing nameserver.
+    pub upstream_max_backoff: Duration,
+    /// Always pick the first candidate nameserver instead of a random one,
+    /// making resolution reproducible. Transaction IDs stay random.
+    pub stable_answer_order: bool,
+    /// Rotate the order of A and AAAA record sets by one with every
+    /// response, so clients taking the first address spread their load.
+    pub round_robin_answers: bool,
+    /// Responses with an A or AAAA answer in one of these networks are
+    /// replaced according to `blocked_answer_action`.
+    pub blocked_answer_nets: Vec<IpNet>,
+    pub blocked_answer_action: BlockAction,
+    /// Whether cache misses are resolved recursively, otherwise they are refused.
+    pub allow_recursion: bool,
+    /// Answer from the cache even when recursion is not allowed.
+    pub serve_cache_without_recursion: bool,
+    /// Answer to `version.bind` CHAOS TXT queries, refused if `None`.
+    pub server_version: Option<String>,
+    /// Answer to `hostname.bind` CHAOS TXT queries, refused if `None`.
+    pub server_hostname: Option<String>,
+    /// Records answered authoritatively before looking at the cache.
+    pub zone: Zone,
+    /// Query types which are never resolved recursively, even when recursion
+    /// is allowed. Cached answers for them are still served.
+    pub refused_qtypes: Vec<QueryType>,
+    /// Names at or below these are refused, even if cached or in the local
+    /// zone.
+    pub refused_zones: Vec<Qname>,
+    /// Answer ANY queries with a single synthesized HINFO record as allowed
+    /// by RFC 8482, instead of resolving them.
+    pub minimal_any_responses: bool,
+    /// Leave out authority and additional records not needed to make use of
+    /// the answer, instead of relaying them all.
+    pub minimal_responses: bool,
+    /// Resolve `.local` names over multicast DNS instead of recursively.
+    pub mdns: bool,
+    /// Queries per second accepted from a single client address, anything
+    /// beyond is dropped without a response. Zero disables the limit.
+    pub rate_limit: u32,
+    /// How many queries a client may send at once before `rate_limit` applies.
+    pub rate_limit_burst: u32,
+    /// Cached responses handed out at least this many times are refreshed
+    /// in the background once less than a tenth of their TTL is left, so
+    /// popular names never drop out of the cache. `None` disables it.
+    pub prefetch_hits: Option<u32>,
+    /// Largest response sent over UDP, to clients advertising at least as
+    /// much with EDNS. Others get 512 bytes at most, as does everyone if this
+    /// is set lower than that.
+    pub max_udp_response_size: usize,
+    /// Most records copied into each section of a response, whatever the
+    /// nameserver sent. Responses cut short have the TC bit set.
+    pub max_section_records: usize,
+    /// Bounds the TTLs of records from nameservers are raised or lowered to,
+    /// before they are cached and relayed. `max_ttl` wins if they overlap.
+    pub min_ttl: u32,
+    pub max_ttl: u32,
+    /// Maximum number of cache-miss queries resolved recursively at the same time.
+    pub max_recursions: usize,
+    /// How long a cache-miss query waits for a free recursion slot before it
+    /// is answered with SERVFAIL. Zero fails immediately.
+    pub recursion_wait: Duration,
+    /// How long a TCP client may take to send a whole query, or be idle
+    /// between queries, before its connection is closed.
+    pub tcp_timeout: Duration,
+    /// Maximum number of TCP connections served at the same time. Further
+    /// ones wait to be accepted until another closes.
+    pub max_tcp_connections: usize,
+    /// Attach a TXT record to the additional section of every response
+    /// describing how each question was answered. Only meant for debugging.
+    pub echo_diagnostics: bool,
+    /// Clients never set TC on a query, so by default such a query is
+    /// answered with FORMERR. Setting this resolves it as if TC was clear.
+    pub ignore_truncated_queries: bool,
+    /// The reserved Z bit has to be clear (RFC 1035), but a query with it
+    /// set is answered as if it was by default. Setting this answers such a
+    /// query with FORMERR instead. Responses always have it clear.
+    pub reject_z_queries: bool,
+    /// Randomize the case of the letters in queries to nameservers and only
+    /// accept responses echoing it exactly. Off by default, as a few servers
+    /// answer with the name lowercased.
+    pub randomize_qname_case: bool,
+    /// How long queries still being answered are waited for on shutdown.
+    pub shutdown_timeout: Duration,
+    /// How often query statistics are logged. Zero disables logging them.
+    pub stats_log_interval: Duration,
+    /// Send DNS cookies to nameservers and discard responses echoing a
+    /// different one.
+    pub dns_cookies: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            listen_addr: SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 2053),
+            resolve_mode: ResolveMode::Recursive,
+            root_servers: ROOT_SERVERS.to_vec(),
+            nameserver_port: 53,
+            query_timeout: Duration::from_secs(1),
+            max_retries: 1,
+            resolve_timeout: Duration::from_secs(5),
+            outbound_sockets: 32,
+            parallel_queries: 3,
+            max_recursion_depth: 16,
+            upstream_failure_threshold: 3,
+            upstream_backoff: Duration::from_secs(5),
+            upstream_max_backoff: Duration::from_secs(300),
+            stable_answer_order: false,
+            round_robin_answers: false,
+            blocked_answer_nets: Vec::new(),
+            blocked_answer_action: BlockAction::NxDomain,
+            allow_recursion: true,
+            serve_cache_without_recursion: false,
+            server_version: Some(concat!("custom-dns-server ", env!("CARGO_PKG_VERSION")).into()),
+            server_hostname: None,
+            zone: Zone::default(),
+            refused_qtypes: Vec::new(),
+            refused_zones: Vec::new(),
+            minimal_any_responses: false,
+            minimal_responses: false,
+            mdns: false,
+            rate_limit: 100,
+            rate_limit_burst: 200,
+            prefetch_hits: Some(3),
+            max_udp_response_size: 1232,
+            max_section_records: 100,
+            min_ttl: 0,
+            max_ttl: 604800,
+            max_recursions: 64,
+            recursion_wait: Duration::from_millis(200),
+            tcp_timeout: Duration::from_secs(10),
+            max_tcp_connections: 256,
+            echo_diagnostics: false,
+            ignore_truncated_queries: false,
+            reject_z_queries: false,
+            randomize_qname_case: false,
+            shutdown_timeout: Duration::from_secs(5),
+            stats_log_interval: Duration::ZERO,
+            dns_cookies: true,
+        }
+    }
+}
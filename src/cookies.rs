@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use thiserror::Error;
+
+use crate::packet::edns::{EdnsOption, CLIENT_COOKIE_LEN};
+use crate::packet::message::DnsMessage;
+
+/// Option code of DNS cookies, as found in an unknown option.
+const COOKIE: u16 = 10;
+
+#[derive(Debug, Error)]
+pub enum CookieError {
+    #[error("malformed cookie in response")]
+    Malformed,
+    #[error("response cookie does not match the query")]
+    Mismatch,
+    #[error("response has no cookie though the server gave one before")]
+    Missing,
+}
+
+struct Cookies {
+    client: [u8; CLIENT_COOKIE_LEN],
+    server: Vec<u8>,
+    /// Whether the server answered without a cookie before ever giving
+    /// one, so it doesn't support them.
+    ignored: bool,
+}
+
+/// DNS cookies (RFC 7873) of the nameservers queried so far.
+///
+/// Every nameserver gets a random client cookie of its own, so it can't be
+/// used to track the resolver across servers. The server cookie it returns
+/// is sent back with every following query. A server which answers without
+/// any cookie doesn't support them and gets none from then on.
+pub(crate) struct CookieJar {
+    servers: Mutex<HashMap<IpAddr, Cookies>>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        Self {
+            servers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Cookie option to send with a query to `server`, `None` if it is known
+    /// not to support cookies.
+    pub fn option_for(&self, server: IpAddr) -> Option<EdnsOption> {
+        let mut servers = self.servers.lock().unwrap();
+        let cookies = servers.entry(server).or_insert_with(|| Cookies {
+            client: rand::random(),
+            server: Vec::new(),
+            ignored: false,
+        });
+        if cookies.ignored {
+            return None;
+        }
+
+        Some(EdnsOption::Cookie {
+            client: cookies.client,
+            server: cookies.server.clone(),
+        })
+    }
+
+    /// Remember the server cookie of a `response` from `server`, or that it
+    /// doesn't support cookies if there is none and it never gave one.
+    pub fn update(&self, server: IpAddr, response: &DnsMessage) {
+        let server_cookie = response
+            .edns_options()
+            .iter()
+            .find_map(|option| match option {
+                EdnsOption::Cookie { server, .. } => Some(server),
+                _ => None,
+            });
+
+        let mut servers = self.servers.lock().unwrap();
+        let Some(cookies) = servers.get_mut(&server) else {
+            return;
+        };
+        match server_cookie {
+            None if cookies.server.is_empty() => cookies.ignored = true,
+            Some(server_cookie) if !server_cookie.is_empty() => {
+                cookies.server.clone_from(server_cookie)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Check that `response` echoes the client cookie among the `sent` options.
+///
+/// A response without any cookie is fine, most servers don't support them.
+/// A server which gave us a server cookie does, though, so its responses
+/// have to carry a cookie (RFC 7873, section 5.3).
+pub(crate) fn check_echo(sent: &[EdnsOption], response: &DnsMessage) -> Result<(), CookieError> {
+    let Some((sent, server_cookie)) = sent.iter().find_map(|option| match option {
+        EdnsOption::Cookie { client, server } => Some((client, server)),
+        _ => None,
+    }) else {
+        return Ok(());
+    };
+
+    let mut echoed = false;
+    for option in response.edns_options() {
+        match option {
+            EdnsOption::Cookie { client, .. } if client != sent => {
+                return Err(CookieError::Mismatch)
+            }
+            EdnsOption::Cookie { .. } => echoed = true,
+            EdnsOption::Unknown { code: COOKIE, .. } => return Err(CookieError::Malformed),
+            _ => {}
+        }
+    }
+
+    if !echoed && !server_cookie.is_empty() {
+        return Err(CookieError::Missing);
+    }
+    Ok(())
+}
@@ -0,0 +1,206 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use reqwest::StatusCode;
+use thiserror::Error;
+use tracing::debug;
+
+use crate::packet::message::DnsMessage;
+use crate::resolver::is_same_question;
+use crate::transport::Transport;
+
+/// Media type of DNS messages in requests and responses.
+const DNS_MESSAGE: &str = "application/dns-message";
+
+#[derive(Debug, Error)]
+pub enum DohError {
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("server answered with HTTP status {0}")]
+    Status(StatusCode),
+    #[error("malformed response")]
+    Malformed,
+    #[error("response question does not match the query")]
+    QuestionMismatch,
+}
+
+/// DNS-over-HTTPS client (RFC 8484), sending queries as POST requests.
+pub struct DohClient {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl DohClient {
+    /// Client of the endpoint at `url`, failing if the HTTP client can't be
+    /// set up, e.g. for lack of a TLS backend.
+    pub fn new(url: impl Into<String>, timeout: Duration) -> Result<Self, DohError> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+
+        Ok(Self {
+            client,
+            url: url.into(),
+        })
+    }
+
+    /// Send `request` and return the response as is.
+    pub async fn query(&self, request: &DnsMessage) -> Result<DnsMessage, DohError> {
+        let body = request.to_bytes();
+
+        let response = self
+            .client
+            .post(&self.url)
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .header(ACCEPT, DNS_MESSAGE)
+            .body(body)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(DohError::Status(response.status()));
+        }
+
+        let body = response.bytes().await?;
+        DnsMessage::from_bytes(&body).map_err(|_| DohError::Malformed)
+    }
+
+    /// Send `request` with ID 0, which makes responses cacheable by HTTP
+    /// caches, and hand the response back with the ID of the request.
+    async fn exchange(&self, request: &DnsMessage) -> Result<DnsMessage, DohError> {
+        debug!(url = %self.url, "forwarding query over HTTPS");
+
+        let mut packet = request.clone();
+        packet.header.id = 0;
+        let mut response = self.query(&packet).await?;
+        if response.header.id != 0 || !is_same_question(&response.questions, &request.questions) {
+            return Err(DohError::QuestionMismatch);
+        }
+        response.header.id = request.header.id;
+        Ok(response)
+    }
+}
+
+/// The server is addressed by the URL of the client, so the address asked
+/// is ignored.
+impl Transport for DohClient {
+    fn query<'a>(
+        &'a self,
+        request: &'a DnsMessage,
+        _server: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<DnsMessage>> {
+        Box::pin(async move { Ok(self.exchange(request).await?) })
+    }
+}
+
+impl From<DohError> for io::Error {
+    fn from(err: DohError) -> Self {
+        match err {
+            DohError::Http(err) if err.is_timeout() => io::Error::new(io::ErrorKind::TimedOut, err),
+            DohError::Http(err) => io::Error::other(err),
+            DohError::Status(_) => io::Error::other(err),
+            DohError::Malformed | DohError::QuestionMismatch => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::{DohClient, DohError};
+    use crate::packet::message::DnsMessage;
+    use crate::packet::qname::Qname;
+    use crate::packet::query_type::QueryType;
+    use crate::resolver::{lookup, DnssecFlags};
+    use crate::testing::{make_reply, parse_message, query};
+
+    /// Answer a single HTTP request with `status`, and the reply to the DNS
+    /// query in its body. Returns the URL and the received query.
+    async fn mock_endpoint(status: &'static str) -> (String, tokio::task::JoinHandle<DnsMessage>) {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+
+        let endpoint = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+
+            let mut request = Vec::new();
+            let mut buf = [0u8; 1024];
+            let (head_len, body_len) = loop {
+                let len = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..len]);
+
+                let Some(end) = request.windows(4).position(|x| x == b"\r\n\r\n") else {
+                    continue;
+                };
+                let head = String::from_utf8_lossy(&request[..end]).to_ascii_lowercase();
+                assert!(head.starts_with("post /dns-query "));
+                assert!(head.contains("content-type: application/dns-message"));
+                let body_len = head
+                    .lines()
+                    .find_map(|x| x.strip_prefix("content-length: "))
+                    .unwrap()
+                    .parse::<usize>()
+                    .unwrap();
+                break (end + 4, body_len);
+            };
+            while request.len() < head_len + body_len {
+                let len = stream.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..len]);
+            }
+
+            let query = parse_message(&request[head_len..]);
+            let body = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            let head = format!(
+                "HTTP/1.1 {status}\r\ncontent-type: application/dns-message\r\n\
+                 content-length: {}\r\nconnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(head.as_bytes()).await.unwrap();
+            stream.write_all(&body).await.unwrap();
+            query
+        });
+
+        (url, endpoint)
+    }
+
+    #[tokio::test]
+    async fn query_round_trip() {
+        let (url, endpoint) = mock_endpoint("200 OK").await;
+        let client = DohClient::new(url, Duration::from_secs(5)).unwrap();
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = lookup(
+            &client,
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::UNSPECIFIED.into(), 443),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let query = endpoint.await.unwrap();
+        assert_eq!(query.header.id, 0);
+        assert!(query.header.flags.recursion_desired);
+        assert_eq!(query.questions[0].name, qname);
+        assert_eq!(query.questions[0].qtype, QueryType::A);
+    }
+
+    #[tokio::test]
+    async fn http_error_status() {
+        let (url, _) = mock_endpoint("503 Service Unavailable").await;
+        let client = DohClient::new(url, Duration::from_secs(5)).unwrap();
+
+        let result = client.query(&query("example.com", QueryType::A)).await;
+        assert!(matches!(result, Err(DohError::Status(status)) if status.as_u16() == 503));
+    }
+}
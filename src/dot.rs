@@ -0,0 +1,286 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tracing::debug;
+
+use crate::packet::message::DnsMessage;
+use crate::resolver::is_same_question;
+use crate::transport::{exchange_framed, Transport};
+
+/// Port DNS-over-TLS servers listen on.
+pub const DOT_PORT: u16 = 853;
+
+#[derive(Debug, Error)]
+pub enum DotError {
+    /// Connecting, the TLS handshake, including the certificate check, or
+    /// the exchange itself failed.
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("server did not respond")]
+    Timeout,
+    #[error("malformed response")]
+    Malformed,
+    #[error("response does not match the query")]
+    QuestionMismatch,
+    #[error("invalid TLS server name {0:?}")]
+    ServerName(String),
+}
+
+/// DNS-over-TLS client (RFC 7858), sending every query over a connection of
+/// its own with the TCP framing.
+///
+/// The server certificate has to chain up to the web PKI roots and be issued
+/// for the pinned server name, or for the server address without one.
+pub struct DotClient {
+    connector: TlsConnector,
+    addr: SocketAddr,
+    server_name: ServerName<'static>,
+    timeout: Duration,
+}
+
+impl DotClient {
+    /// Client of the server at `addr`, failing if `server_name` isn't a
+    /// valid DNS name.
+    pub fn new(
+        addr: SocketAddr,
+        server_name: Option<&str>,
+        timeout: Duration,
+    ) -> Result<Self, DotError> {
+        let roots = RootCertStore {
+            roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+        };
+        Self::with_roots(addr, server_name, timeout, roots)
+    }
+
+    fn with_roots(
+        addr: SocketAddr,
+        server_name: Option<&str>,
+        timeout: Duration,
+        roots: RootCertStore,
+    ) -> Result<Self, DotError> {
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let server_name = match server_name {
+            Some(name) => ServerName::try_from(name.to_string())
+                .map_err(|_| DotError::ServerName(name.to_string()))?,
+            None => addr.ip().into(),
+        };
+
+        Ok(Self {
+            connector: TlsConnector::from(Arc::new(config)),
+            addr,
+            server_name,
+            timeout,
+        })
+    }
+
+    /// Send `request` and return the response as is.
+    pub async fn query(&self, request: &DnsMessage) -> Result<DnsMessage, DotError> {
+        self.query_at(request, self.addr).await
+    }
+
+    async fn query_at(
+        &self,
+        request: &DnsMessage,
+        addr: SocketAddr,
+    ) -> Result<DnsMessage, DotError> {
+        let exchange = async {
+            let stream = TcpStream::connect(addr).await?;
+            let mut stream = self
+                .connector
+                .connect(self.server_name.clone(), stream)
+                .await?;
+            exchange_framed(&mut stream, &request.to_bytes()).await
+        };
+        let response = timeout(self.timeout, exchange)
+            .await
+            .map_err(|_| DotError::Timeout)??;
+
+        DnsMessage::from_bytes(&response).map_err(|_| DotError::Malformed)
+    }
+}
+
+/// Connects to the server asked instead of the one the client was made for,
+/// its certificate still has to be issued for the same name.
+impl Transport for DotClient {
+    fn query<'a>(
+        &'a self,
+        request: &'a DnsMessage,
+        server: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<DnsMessage>> {
+        Box::pin(async move {
+            debug!(%server, "forwarding query over TLS");
+            let response = self.query_at(request, server).await?;
+            if response.header.id != request.header.id
+                || !is_same_question(&response.questions, &request.questions)
+            {
+                return Err(DotError::QuestionMismatch.into());
+            }
+            Ok(response)
+        })
+    }
+}
+
+impl From<DotError> for io::Error {
+    fn from(err: DotError) -> Self {
+        match err {
+            DotError::Io(err) => err,
+            DotError::Timeout => io::Error::new(io::ErrorKind::TimedOut, err),
+            DotError::Malformed | DotError::QuestionMismatch => {
+                io::Error::new(io::ErrorKind::InvalidData, err)
+            }
+            DotError::ServerName(_) => io::Error::new(io::ErrorKind::InvalidInput, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+    use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+    use tokio_rustls::TlsAcceptor;
+
+    use super::{DotClient, DotError};
+    use crate::packet::message::DnsMessage;
+    use crate::packet::qname::Qname;
+    use crate::packet::query_type::QueryType;
+    use crate::resolver::{lookup, DnssecFlags};
+    use crate::testing::{make_reply, parse_message, query};
+
+    /// Certificate of `dns.test`, issued by the test CA.
+    const CERT: &[u8] = include_bytes!("../test_data/dot_cert.der");
+    const KEY: &[u8] = include_bytes!("../test_data/dot_key.der");
+    const CA: &[u8] = include_bytes!("../test_data/dot_ca.der");
+
+    /// Answer a single query over TLS. Returns the server address and the
+    /// received query.
+    async fn mock_server() -> (SocketAddr, tokio::task::JoinHandle<DnsMessage>) {
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(CERT)],
+                PrivateKeyDer::Pkcs8(KEY.into()),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(stream).await.unwrap();
+
+            let len = stream.read_u16().await.unwrap();
+            let mut buf = vec![0u8; len.into()];
+            stream.read_exact(&mut buf).await.unwrap();
+            let query = parse_message(&buf);
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stream.write_u16(reply.len() as u16).await.unwrap();
+            stream.write_all(&reply).await.unwrap();
+            stream.shutdown().await.unwrap();
+            query
+        });
+
+        (addr, server)
+    }
+
+    fn test_roots() -> RootCertStore {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from(CA)).unwrap();
+        roots
+    }
+
+    #[tokio::test]
+    async fn query_round_trip() {
+        let (addr, server) = mock_server().await;
+        let client =
+            DotClient::with_roots(addr, Some("dns.test"), Duration::from_secs(5), test_roots())
+                .unwrap();
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = lookup(
+            &client,
+            &qname,
+            QueryType::A,
+            (addr.ip(), addr.port()),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let query = server.await.unwrap();
+        assert_eq!(query.header.id, response.header.id);
+        assert!(query.header.flags.recursion_desired);
+        assert_eq!(query.questions[0].name, qname);
+    }
+
+    #[tokio::test]
+    async fn client_serves_as_transport() {
+        let (addr, _) = mock_server().await;
+        // Made for some other server, the one asked is connected to
+        let client = DotClient::with_roots(
+            (Ipv4Addr::LOCALHOST, 1).into(),
+            Some("dns.test"),
+            Duration::from_secs(5),
+            test_roots(),
+        )
+        .unwrap();
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = lookup(
+            &client,
+            &qname,
+            QueryType::A,
+            (addr.ip(), addr.port()),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[test]
+    fn invalid_server_name_is_rejected() {
+        let addr = (Ipv4Addr::LOCALHOST, 853).into();
+        for name in ["", "not a name!"] {
+            let result = DotClient::new(addr, Some(name), Duration::from_secs(5));
+            assert!(matches!(result, Err(DotError::ServerName(_))), "{name:?}");
+        }
+        assert!(DotClient::new(addr, None, Duration::from_secs(5)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn certificate_for_other_name_is_rejected() {
+        let (addr, _) = mock_server().await;
+        let client = DotClient::with_roots(
+            addr,
+            Some("other.test"),
+            Duration::from_secs(5),
+            test_roots(),
+        )
+        .unwrap();
+
+        let result = client.query(&query("example.com", QueryType::A)).await;
+        assert!(matches!(result, Err(DotError::Io(_))), "{result:?}");
+    }
+}
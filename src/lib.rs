@@ -0,0 +1,24 @@
+pub mod cache;
+pub mod config;
+mod cookies;
+pub mod doh;
+pub mod dot;
+pub mod listener;
+pub mod mdns;
+pub mod packet;
+pub mod policy;
+mod prefetch;
+pub mod querylog;
+mod ratelimit;
+mod resolver;
+pub mod server;
+mod socket_pool;
+pub mod stats;
+#[cfg(test)]
+mod testing;
+pub mod transport;
+mod upstream;
+pub mod zone;
+
+pub use config::{Config, ResolveMode};
+pub use resolver::{Resolver, ROOT_SERVERS};
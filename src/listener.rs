@@ -0,0 +1,222 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+
+use tokio::net::UdpSocket;
+
+/// UDP socket serving client queries.
+///
+/// On a multi-homed host the kernel picks the source address of a reply
+/// by routing, which is not necessarily the address the query was sent to,
+/// and clients behind some NATs drop such replies. So the listener remembers
+/// the destination address of every query and sends the reply from it.
+pub struct UdpListener {
+    socket: UdpSocket,
+}
+
+impl UdpListener {
+    pub async fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+
+        #[cfg(target_os = "linux")]
+        pktinfo::enable(&socket, addr)?;
+
+        Ok(Self { socket })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Receive a datagram.
+    ///
+    /// Returns its length, the sender and the local address it was sent to,
+    /// if the platform reports one.
+    pub async fn recv_from(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        #[cfg(target_os = "linux")]
+        return self
+            .socket
+            .async_io(tokio::io::Interest::READABLE, || {
+                pktinfo::recv_from(&self.socket, buf)
+            })
+            .await;
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let (len, src) = self.socket.recv_from(buf).await?;
+            Ok((len, src, None))
+        }
+    }
+
+    /// Send a datagram to `target` from the `source` local address.
+    pub async fn send_to(
+        &self,
+        buf: &[u8],
+        target: SocketAddr,
+        source: Option<IpAddr>,
+    ) -> io::Result<usize> {
+        #[cfg(target_os = "linux")]
+        return self
+            .socket
+            .async_io(tokio::io::Interest::WRITABLE, || {
+                pktinfo::send_to(&self.socket, buf, target, source)
+            })
+            .await;
+
+        #[cfg(not(target_os = "linux"))]
+        {
+            let _ = source;
+            self.socket.send_to(buf, target).await
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+mod pktinfo {
+    use std::io::{self, IoSlice, IoSliceMut};
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+    use std::os::fd::AsRawFd;
+
+    use nix::cmsg_space;
+    use nix::libc;
+    use nix::sys::socket::{
+        recvmsg, sendmsg, setsockopt, sockopt, ControlMessage, ControlMessageOwned, MsgFlags,
+        SockaddrStorage,
+    };
+    use tokio::net::UdpSocket;
+
+    pub fn enable(socket: &UdpSocket, addr: SocketAddr) -> io::Result<()> {
+        match addr {
+            SocketAddr::V4(_) => setsockopt(socket, sockopt::Ipv4PacketInfo, &true)?,
+            SocketAddr::V6(_) => setsockopt(socket, sockopt::Ipv6RecvPacketInfo, &true)?,
+        }
+        Ok(())
+    }
+
+    pub fn recv_from(
+        socket: &UdpSocket,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, SocketAddr, Option<IpAddr>)> {
+        let mut iov = [IoSliceMut::new(buf)];
+        let mut cmsg_buf = cmsg_space!(libc::in_pktinfo, libc::in6_pktinfo);
+
+        let msg = recvmsg::<SockaddrStorage>(
+            socket.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsg_buf),
+            MsgFlags::empty(),
+        )?;
+
+        let src = msg
+            .address
+            .as_ref()
+            .and_then(|addr| {
+                if let Some(addr) = addr.as_sockaddr_in() {
+                    Some(SocketAddr::V4(SocketAddrV4::from(*addr)))
+                } else {
+                    addr.as_sockaddr_in6()
+                        .map(|addr| SocketAddr::V6(SocketAddrV6::from(*addr)))
+                }
+            })
+            .ok_or_else(|| io::Error::other("datagram without source address"))?;
+
+        let dst = msg.cmsgs()?.find_map(|cmsg| match cmsg {
+            ControlMessageOwned::Ipv4PacketInfo(info) => Some(IpAddr::V4(Ipv4Addr::from(
+                u32::from_be(info.ipi_addr.s_addr),
+            ))),
+            ControlMessageOwned::Ipv6PacketInfo(info) => {
+                Some(IpAddr::V6(Ipv6Addr::from(info.ipi6_addr.s6_addr)))
+            }
+            _ => None,
+        });
+
+        Ok((msg.bytes, src, dst))
+    }
+
+    pub fn send_to(
+        socket: &UdpSocket,
+        buf: &[u8],
+        target: SocketAddr,
+        source: Option<IpAddr>,
+    ) -> io::Result<usize> {
+        let iov = [IoSlice::new(buf)];
+        let target = SockaddrStorage::from(target);
+        let fd = socket.as_raw_fd();
+
+        // Replies to multicast or broadcast queries go out from the
+        // interface's own address.
+        let source = source.filter(|ip| match ip {
+            IpAddr::V4(ip) => !ip.is_multicast() && !ip.is_broadcast(),
+            IpAddr::V6(ip) => !ip.is_multicast(),
+        });
+
+        let sent = match source {
+            Some(IpAddr::V4(ip)) => {
+                let info = libc::in_pktinfo {
+                    ipi_ifindex: 0,
+                    ipi_spec_dst: libc::in_addr {
+                        s_addr: u32::from(ip).to_be(),
+                    },
+                    ipi_addr: libc::in_addr { s_addr: 0 },
+                };
+                let cmsgs = [ControlMessage::Ipv4PacketInfo(&info)];
+                sendmsg(fd, &iov, &cmsgs, MsgFlags::empty(), Some(&target))?
+            }
+            Some(IpAddr::V6(ip)) => {
+                let info = libc::in6_pktinfo {
+                    ipi6_addr: libc::in6_addr {
+                        s6_addr: ip.octets(),
+                    },
+                    ipi6_ifindex: 0,
+                };
+                let cmsgs = [ControlMessage::Ipv6PacketInfo(&info)];
+                sendmsg(fd, &iov, &cmsgs, MsgFlags::empty(), Some(&target))?
+            }
+            None => sendmsg(fd, &iov, &[], MsgFlags::empty(), Some(&target))?,
+        };
+
+        Ok(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use tokio::net::UdpSocket;
+
+    use super::UdpListener;
+
+    #[tokio::test]
+    async fn reply_comes_from_query_destination() {
+        let listener = UdpListener::bind("0.0.0.0:0".parse().unwrap())
+            .await
+            .unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Any 127.0.0.0/8 address is local, but routing would answer a
+        // client on 127.0.0.1 from 127.0.0.1.
+        let server_addr = SocketAddr::new(Ipv4Addr::new(127, 0, 0, 2).into(), port);
+        let client = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        client.send_to(b"query", server_addr).await.unwrap();
+
+        let mut buf = [0u8; 16];
+        let (len, src, dst) = listener.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"query");
+        assert_eq!(src, client.local_addr().unwrap());
+
+        if cfg!(target_os = "linux") {
+            assert_eq!(dst, Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))));
+        }
+
+        listener.send_to(b"reply", src, dst).await.unwrap();
+
+        let (len, from) = client.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"reply");
+        if cfg!(target_os = "linux") {
+            assert_eq!(from, server_addr);
+        }
+    }
+}
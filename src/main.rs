@@ -1,176 +1,236 @@
 use std::io;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+use custom_dns_server::dot::DOT_PORT;
+use custom_dns_server::packet::qname::Qname;
+use custom_dns_server::packet::query_type::QueryType;
+use custom_dns_server::querylog::JsonFileLogger;
+use custom_dns_server::server::{serve_tcp, serve_until, Server};
+use custom_dns_server::zone::Zone;
+use custom_dns_server::{Config, ResolveMode, Resolver};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+/// Recursive DNS server.
+///
+/// Every option can also be given by its environment variable.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Address to receive queries on
+    #[arg(long, env = "DNS_LISTEN", default_value = "0.0.0.0:2053")]
+    listen: SocketAddr,
+    /// Milliseconds to wait for a nameserver to answer a single query
+    #[arg(long, env = "DNS_TIMEOUT_MS", global = true)]
+    timeout_ms: Option<u64>,
+    /// How many times a query is resent to an unresponsive nameserver
+    #[arg(long, env = "DNS_RETRIES", global = true)]
+    retries: Option<usize>,
+    /// Forward cache misses to this DNS-over-HTTPS endpoint instead of
+    /// resolving them recursively
+    #[arg(long, env = "DNS_DOH")]
+    doh: Option<String>,
+    /// Forward cache misses to this DNS-over-TLS server instead, port 853
+    /// unless given
+    #[arg(long, env = "DNS_DOT", conflicts_with = "doh", value_parser = parse_dot_addr)]
+    dot: Option<SocketAddr>,
+    /// Name the certificate of the DNS-over-TLS server has to be issued for,
+    /// its address by default
+    #[arg(long, env = "DNS_DOT_NAME", requires = "dot", value_parser = parse_tls_name)]
+    dot_name: Option<String>,
+    /// Start recursion at this nameserver instead of the root ones, may be
+    /// given several times
+    #[arg(long = "root", env = "DNS_ROOTS", value_delimiter = ',', global = true)]
+    roots: Vec<IpAddr>,
+    /// Port to query nameservers on
+    #[arg(long, env = "DNS_NAMESERVER_PORT", hide = true, global = true)]
+    nameserver_port: Option<u16>,
+    /// Log query statistics every this many seconds
+    #[arg(long, env = "DNS_STATS_INTERVAL")]
+    stats_interval: Option<u64>,
+    /// Append a JSON line per answered query to this file
+    #[arg(long, env = "DNS_QUERY_LOG")]
+    query_log: Option<PathBuf>,
+    /// Save the cache to this file on shutdown and load it back on startup
+    #[arg(long, env = "DNS_CACHE_FILE")]
+    cache_file: Option<PathBuf>,
+    /// Zone file with local records
+    zone: Option<PathBuf>,
+}
 
-use cookie_factory::gen_simple;
-use tokio::net::UdpSocket;
-
-mod packet;
-
-use packet::byte_buffer::{ByteBuffer, MAX_DNS_MSG_SIZE};
-use packet::message::DnsMessage;
-use packet::qname::Qname;
-use packet::query_type::QueryType;
-use packet::question::DnsQuestion;
-use packet::ResultCode;
-
-async fn lookup(
-    qname: &Qname,
-    qtype: QueryType,
-    server: (Ipv4Addr, u16),
-) -> io::Result<DnsMessage> {
-    let socket = UdpSocket::bind(("0.0.0.0", 0)).await?;
-
-    let mut packet = DnsMessage::new();
-    packet.header.id = 6666;
-    packet.header.questions = 1;
-    packet.header.flags.recursion_desired = true;
-    packet.questions.push(DnsQuestion {
-        name: qname.clone(),
-        qtype,
-    });
-
-    let req_buffer = gen_simple(packet.serialize(), Vec::new()).unwrap();
-
-    socket.send_to(&req_buffer, server).await?;
-
-    let mut res_buffer = [0u8; MAX_DNS_MSG_SIZE];
-    let (len, _) = socket.recv_from(&mut res_buffer).await?;
-    let res_buffer = &res_buffer[..len];
-
-    let (_, packet) = DnsMessage::parse(res_buffer, &ByteBuffer::new(res_buffer)).unwrap();
-    Ok(packet)
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Answer queries, the default without a command
+    Serve,
+    /// Resolve a name recursively, print the response and exit
+    Resolve {
+        /// Name to look up
+        name: Qname,
+        /// Record type to ask for
+        #[arg(default_value = "A")]
+        qtype: QueryType,
+    },
 }
 
-const ROOT_SERVERS: [&str; 13] = [
-    "198.41.0.4",
-    "199.9.14.201",
-    "192.33.4.12",
-    "199.7.91.13",
-    "192.203.230.10",
-    "192.5.5.241",
-    "192.112.36.4",
-    "198.97.190.53",
-    "192.36.148.17",
-    "192.58.128.30",
-    "193.0.14.129",
-    "199.7.83.42",
-    "202.12.27.33",
-];
-
-async fn recursive_lookup(qname: &Qname, qtype: QueryType) -> io::Result<DnsMessage> {
-    use rand::seq::SliceRandom;
-    let mut ns = ROOT_SERVERS
-        .choose(&mut rand::thread_rng())
-        .unwrap()
-        .parse::<Ipv4Addr>()
-        .unwrap();
-
-    loop {
-        println!("attempting to lookup {qtype:?} entry for {qname} on ns {ns}");
-
-        let ns_copy = ns;
-        let server = (ns_copy, 53);
-        let response = lookup(qname, qtype, server).await?;
-
-        if !response.answers.is_empty() && response.header.flags.rescode == ResultCode::NoError {
-            return Ok(response);
+impl Args {
+    fn into_config(self) -> io::Result<Config> {
+        let mut config = Config {
+            listen_addr: self.listen,
+            ..Config::default()
+        };
+        if let Some(timeout) = self.timeout_ms {
+            config.query_timeout = Duration::from_millis(timeout);
         }
-
-        if response.header.flags.rescode == ResultCode::NxDomain {
-            return Ok(response);
+        if let Some(retries) = self.retries {
+            config.max_retries = retries;
         }
-
-        if let Some(new_ns) = response.get_resolved_ns(qname) {
-            ns = new_ns;
-            continue;
+        if let Some(url) = self.doh {
+            config.resolve_mode = ResolveMode::DnsOverHttps(url);
         }
-
-        let new_ns_name = match response.get_unresolved_ns(qname) {
-            Some(x) => x,
-            None => return Ok(response),
-        };
-
-        let recursive_response = Box::pin(recursive_lookup(new_ns_name, QueryType::A)).await?;
-
-        if let Some(new_ns) = recursive_response.get_random_a() {
-            ns = new_ns;
-        } else {
-            return Ok(response);
+        if let Some(addr) = self.dot {
+            config.resolve_mode = ResolveMode::DnsOverTls {
+                addr,
+                server_name: self.dot_name,
+            };
+        }
+        if !self.roots.is_empty() {
+            config.root_servers = self.roots;
+        }
+        if let Some(port) = self.nameserver_port {
+            config.nameserver_port = port;
         }
+        if let Some(interval) = self.stats_interval {
+            config.stats_log_interval = Duration::from_secs(interval);
+        }
+        if let Some(path) = self.zone {
+            config.zone = Zone::load(path).map_err(io::Error::other)?;
+        }
+        Ok(config)
     }
 }
 
-async fn handle_query(msg_buf: [u8; MAX_DNS_MSG_SIZE], len: usize) -> io::Result<Vec<u8>> {
-    let msg_buf = &msg_buf[..len];
-    let byte_buffer = ByteBuffer::new(msg_buf);
+fn parse_dot_addr(value: &str) -> Result<SocketAddr, String> {
+    value
+        .parse()
+        .or_else(|_| {
+            value
+                .parse()
+                .map(|ip: IpAddr| SocketAddr::new(ip, DOT_PORT))
+        })
+        .map_err(|_| format!("{value:?} is no IP address with an optional port"))
+}
 
-    let (_, mut request) = DnsMessage::parse(msg_buf, &byte_buffer).unwrap();
+fn parse_tls_name(value: &str) -> Result<String, String> {
+    ServerName::try_from(value)
+        .map(|_| value.to_string())
+        .map_err(|err| err.to_string())
+}
 
-    let mut packet = DnsMessage::new();
-    packet.header.id = request.header.id;
-    packet.header.flags.recursion_desired = true;
-    packet.header.flags.recursion_available = true;
-    packet.header.flags.response = true;
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    // Log level is taken from RUST_LOG, e.g. RUST_LOG=custom_dns_server=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    let mut args = Args::parse();
+    let command = args.command.take();
+    let query_log = args.query_log.take();
+    let cache_file = args.cache_file.take();
+    let config = args.into_config()?;
+    if let Some(Command::Resolve { name, qtype }) = command {
+        return resolve_once(config, &name, qtype).await;
+    }
 
-    if let Some(question) = request.questions.pop() {
-        println!("Received query: {:?}", question);
+    let stats_log_interval = config.stats_log_interval;
+    let mut server = Server::new(config).map_err(io::Error::other)?;
+    if let Some(path) = query_log {
+        server = server.with_query_log(Arc::new(JsonFileLogger::open(path).await?));
+    }
+    if let Some(path) = &cache_file {
+        match server.load_cache(path).await {
+            Ok(loaded) => info!("loaded {loaded} cached responses from {}", path.display()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warn!("not loading cache from {}: {err}", path.display()),
+        }
+    }
+    let server = Arc::new(server);
+    let socket = Arc::new(server.bind().await?);
+    let tcp_listener = server.bind_tcp().await?;
 
-        if let Ok(result) = recursive_lookup(&question.name, question.qtype).await {
-            packet.questions.push(question);
-            packet.header.flags.rescode = result.header.flags.rescode;
+    info!("Starting DNS server on {}", socket.local_addr()?);
 
-            for rec in result.answers {
-                println!("Answer: {:?}", rec);
-                packet.answers.push(rec);
-            }
-            for rec in result.authorities {
-                println!("Authority: {:?}", rec);
-                packet.authorities.push(rec);
-            }
-            for rec in result.resources {
-                println!("Resource: {:?}", rec);
-                packet.resources.push(rec);
+    tokio::spawn(serve_tcp(tcp_listener, server.clone()));
+
+    if !stats_log_interval.is_zero() {
+        let server = server.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(stats_log_interval);
+            // The first tick is immediate, with nothing to report yet
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                info!("stats: {}", server.stats());
             }
-        } else {
-            packet.header.flags.rescode = ResultCode::ServFail;
-        }
-    } else {
-        packet.header.flags.rescode = ResultCode::FormErr;
+        });
     }
 
-    packet.update_header();
-    let res_buffer = gen_simple(packet.serialize(), Vec::new()).unwrap();
+    serve_until(socket, server.clone(), shutdown_signal()).await?;
 
-    Ok(res_buffer)
+    if let Some(path) = cache_file {
+        match server.save_cache(&path).await {
+            Ok(saved) => info!("saved {saved} cached responses to {}", path.display()),
+            Err(err) => error!("failed to save cache to {}: {err}", path.display()),
+        }
+    }
+    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> io::Result<()> {
-    let local_address = SocketAddr::new("0.0.0.0".parse().unwrap(), 2053);
-    let socket = UdpSocket::bind(("0.0.0.0", 2053)).await?;
-    let socket = Arc::new(socket);
-
-    println!("Starting DNS server on {local_address}");
-
-    loop {
-        let mut msg_buf = [0u8; MAX_DNS_MSG_SIZE];
+/// Resolve `name` like the server would on a cache miss and print the
+/// response like dig does.
+async fn resolve_once(config: Config, name: &Qname, qtype: QueryType) -> io::Result<()> {
+    let response = Resolver::new(config).resolve(name, qtype).await?;
+    print!("{response}");
+    Ok(())
+}
 
-        let (len, src) = socket.recv_from(&mut msg_buf).await?;
+/// Wait for Ctrl-C, or SIGTERM as sent by process supervisors.
+async fn shutdown_signal() {
+    let interrupt = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            error!("failed to listen for SIGINT: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
 
-        let socket = socket.clone();
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
 
-        tokio::spawn(async move {
-            match handle_query(msg_buf, len).await {
-                Ok(result) => {
-                    if let Err(err) = socket.send_to(&result, src).await {
-                        println!("failed to send result to {src}: {err}");
-                    }
-                },
-                Err(err) => {
-                    println!("error during handling request: {err}");
-                },
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
             }
-        });
+            Err(err) => {
+                error!("failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => info!("received SIGINT"),
+        _ = terminate => info!("received SIGTERM"),
     }
 }
@@ -0,0 +1,131 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::packet::byte_buffer::MAX_DNS_MSG_SIZE;
+use crate::packet::message::DnsMessage;
+use crate::packet::qname::Qname;
+use crate::packet::query_type::QueryType;
+use crate::resolver::is_same_question;
+
+/// Multicast DNS groups of RFC 6762.
+pub const MDNS_GROUPS: [SocketAddr; 2] = [
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353)),
+    SocketAddr::V6(SocketAddrV6::new(
+        Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 0xfb),
+        5353,
+        0,
+        0,
+    )),
+];
+
+/// Whether `name` is in the `.local` domain reserved for multicast DNS.
+pub fn is_local(name: &Qname) -> bool {
+    name.to_string()
+        .rsplit('.')
+        .next()
+        .is_some_and(|label| label.eq_ignore_ascii_case("local"))
+}
+
+/// Ask all `groups` at once and take the first response together with the
+/// address of the device which sent it.
+pub(crate) async fn lookup(
+    config: &Config,
+    qname: &Qname,
+    qtype: QueryType,
+    groups: &[SocketAddr],
+) -> io::Result<(DnsMessage, IpAddr)> {
+    let queries = groups
+        .iter()
+        .map(|&group| Box::pin(query_group(config, qname, qtype, group)))
+        .collect::<Vec<_>>();
+
+    if queries.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no mDNS group to query",
+        ));
+    }
+
+    let (answer, _) = futures::future::select_ok(queries).await?;
+    Ok(answer)
+}
+
+/// Send a one-shot query to `group`. It comes from an ephemeral port, so
+/// responders answer by unicast with the ID and question echoed back, like a
+/// nameserver would (RFC 6762, section 6.7).
+async fn query_group(
+    config: &Config,
+    qname: &Qname,
+    qtype: QueryType,
+    group: SocketAddr,
+) -> io::Result<(DnsMessage, IpAddr)> {
+    let local: IpAddr = match group {
+        SocketAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        SocketAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    let socket = UdpSocket::bind((local, 0)).await?;
+
+    let packet = DnsMessage::builder().question(qname.clone(), qtype).build();
+
+    let req_buffer = packet.to_bytes();
+
+    let mut res_buffer = [0u8; MAX_DNS_MSG_SIZE];
+
+    for _ in 0..=config.max_retries {
+        debug!(%group, "looking up {qtype} entry for {qname} over mDNS");
+        socket.send_to(&req_buffer, group).await?;
+
+        let deadline = Instant::now() + config.query_timeout;
+
+        // Responders answer from their own address rather than the group's,
+        // so only the ID and the question tell whether it's our answer.
+        loop {
+            let (len, src) = match timeout_at(deadline, socket.recv_from(&mut res_buffer)).await {
+                Ok(received) => received?,
+                Err(_) => break,
+            };
+
+            let res_buffer = &res_buffer[..len];
+            let response = match DnsMessage::from_bytes(res_buffer) {
+                Ok(response) => response,
+                Err(_) => continue,
+            };
+
+            if response.header.id == packet.header.id
+                && is_same_question(&response.questions, &packet.questions)
+            {
+                return Ok((response, src.ip()));
+            }
+        }
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "no mDNS responder answered",
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_local;
+    use crate::packet::qname::Qname;
+
+    #[test]
+    fn local_names() {
+        for (name, local) in [
+            ("printer.local", true),
+            ("Printer.LOCAL", true),
+            ("local", true),
+            ("local.example.com", false),
+            ("example.com", false),
+            (".", false),
+        ] {
+            assert_eq!(is_local(&Qname::try_from(name).unwrap()), local, "{name}");
+        }
+    }
+}
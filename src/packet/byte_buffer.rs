@@ -1,19 +1,22 @@
 use thiserror::Error;
 
 use super::{
-    qname::{Qname, QnameError},
+    qname::{Qname, QnameError, MAX_QNAME_LEN},
     parse::{Input, ParseError, ParseResult},
 };
 
 pub const MAX_DNS_MSG_SIZE: usize = 512;
-const MAX_JUMPS: usize = 5;
+/// Default number of compression pointers followed while reading a qname.
+pub const MAX_JUMPS: usize = 5;
 
 #[derive(Debug, Error)]
 pub enum ByteBufferError {
     #[error("attempt to read out of buffer bounds: {index} >= {buf_len}")]
     BoundError { buf_len: usize, index: usize },
-    #[error("jump limit exceeded ({}) during qname unpacking", MAX_JUMPS)]
+    #[error("jump limit exceeded during qname unpacking")]
     JumpLimitExceeded,
+    #[error("compression pointer at {pos} to {offset} does not point backwards")]
+    ForwardPointer { pos: usize, offset: usize },
     #[error("invalid qname: {0}")]
     QnameError(#[from] QnameError),
 }
@@ -27,11 +30,17 @@ impl<I> From<(I, ByteBufferError)> for ParseError<I> {
 /// Immutable DNS message buffer to read packed qname with jumping around the buffer.
 pub struct ByteBuffer<'a> {
     buf: &'a [u8],
+    max_jumps: usize,
 }
 
 impl<'a> ByteBuffer<'a> {
     pub fn new(buf: &'a [u8]) -> Self {
-        Self { buf }
+        Self::with_max_jumps(buf, MAX_JUMPS)
+    }
+
+    /// Create a buffer following at most `max_jumps` compression pointers per qname.
+    pub fn with_max_jumps(buf: &'a [u8], max_jumps: usize) -> Self {
+        Self { buf, max_jumps }
     }
 
     /// Get length of underlying byte buffer
@@ -39,6 +48,10 @@ impl<'a> ByteBuffer<'a> {
         self.buf.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
     /// Get single byte try_from buffer with boundary check.
     fn peek_u8(&self, pos: usize) -> Result<u8, ByteBufferError> {
         if pos >= self.buf.len() {
@@ -71,18 +84,10 @@ impl<'a> ByteBuffer<'a> {
             let mut jumps_performed = 0;
             let mut qnames = Vec::new();
             let mut consumed: usize = 0;
+            // Length of the name on the wire, starting with the root label
+            let mut name_len: usize = 1;
 
             loop {
-                // DNS packets are untrusted data, so we need to be paranoid. Someone
-                // can craft a packet with a cycle in the jump instructions. This guards
-                // against such packets.
-                if jumps_performed > MAX_JUMPS {
-                    return Err(nom::Err::Failure(ParseError::ByteBuffer((
-                        i,
-                        ByteBufferError::JumpLimitExceeded,
-                    ))));
-                }
-
                 // Assume that `pos` is pointing to the start of the qname.
                 let len = self
                     .peek_u8(pos)
@@ -91,13 +96,32 @@ impl<'a> ByteBuffer<'a> {
                 // If `len` has the two most significant bits set, it represents a
                 // jump to some other offset in the packet.
                 if (len & 0xC0) == 0xC0 {
+                    // DNS packets are untrusted data, so we need to be paranoid. Someone
+                    // can craft a packet with a cycle in the jump instructions. This guards
+                    // against such packets.
+                    if jumps_performed >= self.max_jumps {
+                        return Err(nom::Err::Failure(ParseError::ByteBuffer((
+                            i,
+                            ByteBufferError::JumpLimitExceeded,
+                        ))));
+                    }
+
                     // Read another byte and calculate next position try_from 14 bits.
                     let b2 = self
                         .peek_u8(pos + 1)
                         .map_err(|e| nom::Err::Failure(ParseError::ByteBuffer((i, e))))?
                         as u16;
-                    let offset = (((len ^ 0xC0) as u16) << 8) | b2;
-                    pos = offset as usize;
+                    let offset = ((((len ^ 0xC0) as u16) << 8) | b2) as usize;
+
+                    // Pointers may only refer to a prior occurrence of a name
+                    // (RFC 1035, section 4.1.4), which also rules out cycles.
+                    if offset >= pos {
+                        return Err(nom::Err::Failure(ParseError::ByteBuffer((
+                            i,
+                            ByteBufferError::ForwardPointer { pos, offset },
+                        ))));
+                    }
+                    pos = offset;
 
                     // Update current reading position in
                     if jumps_performed == 0 {
@@ -120,6 +144,16 @@ impl<'a> ByteBuffer<'a> {
                         break;
                     }
 
+                    // Give up as soon as the name gets too long, instead of
+                    // collecting labels up to the jump limit first.
+                    name_len += len as usize + 1;
+                    if name_len > MAX_QNAME_LEN {
+                        return Err(nom::Err::Failure(ParseError::ByteBuffer((
+                            i,
+                            ByteBufferError::QnameError(QnameError::BadTotalLen),
+                        ))));
+                    }
+
                     // Move a single byte forward to move past the length byte.
                     pos += 1;
 
@@ -127,7 +161,7 @@ impl<'a> ByteBuffer<'a> {
                     let byte_str = self
                         .peek_range(pos, len as usize)
                         .map_err(|e| nom::Err::Failure(ParseError::ByteBuffer((i, e))))?;
-                    let string = String::from_utf8_lossy(byte_str).to_lowercase();
+                    let string = String::from_utf8_lossy(byte_str).into_owned();
                     qnames.push(string);
 
                     // Move forward for the length of the label.
@@ -140,7 +174,7 @@ impl<'a> ByteBuffer<'a> {
 
             Ok((
                 &i[consumed..],
-                Qname::try_from(qnames)
+                Qname::from_wire_labels(qnames)
                     .map_err(|e| nom::Err::Failure(ParseError::Qname((i, e))))?,
             ))
         }
@@ -151,8 +185,11 @@ impl<'a> ByteBuffer<'a> {
 mod test {
     use std::path::Path;
 
-    use super::ByteBuffer;
-    use crate::packet::qname::Qname;
+    use super::{ByteBuffer, ByteBufferError, MAX_JUMPS};
+    use crate::packet::{
+        parse::ParseError,
+        qname::{Qname, QnameError},
+    };
 
     const DNS_HEADER_LEN: usize = 12;
 
@@ -205,4 +242,100 @@ mod test {
         let (_, qname) = buffer.read_qname()(i).unwrap();
         assert_eq!(qname, Qname::try_from("b.gtld-servers.net").unwrap());
     }
+
+    /// Buffer with `com` at the start followed by a chain of `len` pointers,
+    /// each pointing to the previous one.
+    fn pointer_chain(len: usize) -> Vec<u8> {
+        let mut data = vec![3, b'c', b'o', b'm', 0];
+        let mut target = 0;
+        for _ in 0..len {
+            let pos = data.len();
+            data.extend([0xC0, target as u8]);
+            target = pos;
+        }
+        data
+    }
+
+    fn is_jump_limit_error<T>(result: Result<T, nom::Err<ParseError<&[u8]>>>) -> bool {
+        matches!(
+            result,
+            Err(nom::Err::Failure(ParseError::ByteBuffer((
+                _,
+                ByteBufferError::JumpLimitExceeded
+            ))))
+        )
+    }
+
+    #[test]
+    fn jump_limit_is_exact() {
+        for (max_jumps, data) in [(MAX_JUMPS, pointer_chain(MAX_JUMPS)), (2, pointer_chain(2))] {
+            let buffer = ByteBuffer::with_max_jumps(&data, max_jumps);
+            let (i, qname) = buffer.read_qname()(&data[data.len() - 2..]).unwrap();
+            assert_eq!(qname, Qname::try_from("com").unwrap());
+            assert!(i.is_empty());
+        }
+
+        for (max_jumps, data) in [
+            (MAX_JUMPS, pointer_chain(MAX_JUMPS + 1)),
+            (2, pointer_chain(3)),
+        ] {
+            let buffer = ByteBuffer::with_max_jumps(&data, max_jumps);
+            assert!(is_jump_limit_error(buffer.read_qname()(
+                &data[data.len() - 2..]
+            )));
+        }
+    }
+
+    #[test]
+    fn pointer_cycle_is_rejected() {
+        let data = [0xC0, 0x00];
+        let buffer = ByteBuffer::new(&data);
+
+        assert!(matches!(
+            buffer.read_qname()(&data),
+            Err(nom::Err::Failure(ParseError::ByteBuffer((
+                _,
+                ByteBufferError::ForwardPointer { pos: 0, offset: 0 }
+            ))))
+        ));
+    }
+
+    #[test]
+    fn forward_pointer_is_rejected() {
+        // Pointer to `com` after it, with a jump budget to spare
+        let data = [0xC0, 0x02, 3, b'c', b'o', b'm', 0];
+        let buffer = ByteBuffer::new(&data);
+
+        assert!(matches!(
+            buffer.read_qname()(&data),
+            Err(nom::Err::Failure(ParseError::ByteBuffer((
+                _,
+                ByteBufferError::ForwardPointer { pos: 0, offset: 2 }
+            ))))
+        ));
+    }
+
+    #[test]
+    fn overlong_name_is_rejected_early() {
+        // 128 labels of one letter take 256 bytes with the root label. No
+        // root label follows, the name is rejected before running out of
+        // buffer.
+        let data = [1, b'a'].repeat(128);
+        let buffer = ByteBuffer::new(&data);
+
+        assert!(matches!(
+            buffer.read_qname()(&data),
+            Err(nom::Err::Failure(ParseError::ByteBuffer((
+                _,
+                ByteBufferError::QnameError(QnameError::BadTotalLen)
+            ))))
+        ));
+
+        // One label less is as long as a name may be
+        let mut data = [1, b'a'].repeat(127);
+        data.push(0);
+        let buffer = ByteBuffer::new(&data);
+        let (_, qname) = buffer.read_qname()(&data).unwrap();
+        assert_eq!(qname.labels().len(), 127);
+    }
 }
@@ -0,0 +1,151 @@
+use std::io;
+
+use cookie_factory as cf;
+use nom::{multi::length_data, number::complete::be_u16, sequence::tuple};
+
+use super::parse::{Input, ParseResult};
+use super::record::write_hex;
+
+/// Option code of Extended DNS Errors (RFC 8914).
+const EXTENDED_ERROR: u16 = 15;
+
+/// Option codes the resolver is responsible for itself (RFC 7871, RFC 7873,
+/// RFC 7830), which must not be relayed between client and upstream.
+const CLIENT_SUBNET: u16 = 8;
+const COOKIE: u16 = 10;
+const PADDING: u16 = 12;
+
+/// Extended DNS Error "Other Error" info-code. RFC 8914 has no dedicated
+/// code for an overloaded server, so it is used with an explanatory text.
+pub const EDE_OTHER_ERROR: u16 = 0;
+
+/// DNSSEC OK bit of the OPT record flags (RFC 3225), set by clients which
+/// want signatures along with the answer.
+pub const DNSSEC_OK: u16 = 0x8000;
+
+/// Length of the client part of a DNS cookie, the server part takes 8 to 32
+/// bytes if present (RFC 7873, section 4).
+pub const CLIENT_COOKIE_LEN: usize = 8;
+
+/// Option carried in the RDATA of an OPT record.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum EdnsOption {
+    ExtendedError {
+        info_code: u16,
+        extra_text: String,
+    },
+    /// DNS cookie, `server` is empty until the server has handed one out. A
+    /// cookie of any other length is kept as an unknown option.
+    Cookie {
+        client: [u8; CLIENT_COOKIE_LEN],
+        server: Vec<u8>,
+    },
+    Unknown {
+        code: u16,
+        data: Vec<u8>,
+    },
+}
+
+/// Option as in the OPT pseudosection of dig, binary data in hex.
+impl std::fmt::Display for EdnsOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EdnsOption::ExtendedError {
+                info_code,
+                extra_text,
+            } => write!(f, "EDE: {info_code} ({extra_text:?})"),
+            EdnsOption::Cookie { client, server } => {
+                f.write_str("COOKIE: ")?;
+                write_hex(f, client)?;
+                write_hex(f, server)
+            }
+            EdnsOption::Unknown { code, data } => {
+                write!(f, "OPT{code}: ")?;
+                write_hex(f, data)
+            }
+        }
+    }
+}
+
+impl EdnsOption {
+    pub fn parse(i: Input) -> ParseResult<Self> {
+        let (i, (code, data)) = tuple((be_u16, length_data(be_u16)))(i)?;
+
+        // Options which don't parse as their code says are kept as unknown
+        // ones, so they are still relayed byte for byte
+        let option = match (code, data) {
+            (EXTENDED_ERROR, [hi, lo, extra_text @ ..])
+                if std::str::from_utf8(extra_text).is_ok() =>
+            {
+                EdnsOption::ExtendedError {
+                    info_code: u16::from_be_bytes([*hi, *lo]),
+                    extra_text: String::from_utf8_lossy(extra_text).into_owned(),
+                }
+            }
+            (COOKIE, _)
+                if matches!(data.len().checked_sub(CLIENT_COOKIE_LEN), Some(0 | 8..=32)) =>
+            {
+                let (client, server) = data.split_at(CLIENT_COOKIE_LEN);
+                EdnsOption::Cookie {
+                    client: client.try_into().unwrap(),
+                    server: server.to_vec(),
+                }
+            }
+            _ => EdnsOption::Unknown {
+                code,
+                data: data.to_vec(),
+            },
+        };
+
+        Ok((i, option))
+    }
+
+    /// Whether the option is unknown to the resolver and should be copied
+    /// through verbatim, for forward compatibility with new options.
+    pub fn is_relayed(&self) -> bool {
+        match self {
+            EdnsOption::Unknown { code, .. } => !matches!(*code, CLIENT_SUBNET | COOKIE | PADDING),
+            _ => false,
+        }
+    }
+
+    /// Size of the option on the wire: code, length and data.
+    pub fn serialized_size(&self) -> u16 {
+        4 + self.data_size()
+    }
+
+    fn data_size(&self) -> u16 {
+        match self {
+            EdnsOption::ExtendedError { extra_text, .. } => 2 + extra_text.len() as u16,
+            EdnsOption::Cookie { server, .. } => (CLIENT_COOKIE_LEN + server.len()) as u16,
+            EdnsOption::Unknown { data, .. } => data.len() as u16,
+        }
+    }
+
+    pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> Box<dyn cf::SerializeFn<W> + 'a> {
+        use cf::{bytes::be_u16, combinator::slice, combinator::string, sequence::tuple};
+
+        match self {
+            EdnsOption::ExtendedError {
+                info_code,
+                extra_text,
+            } => Box::new(tuple((
+                be_u16(EXTENDED_ERROR),
+                be_u16(self.data_size()),
+                be_u16(*info_code),
+                string(extra_text),
+            ))),
+            EdnsOption::Cookie { client, server } => Box::new(tuple((
+                be_u16(COOKIE),
+                be_u16(self.data_size()),
+                slice(client),
+                slice(server),
+            ))),
+            EdnsOption::Unknown { code, data } => Box::new(tuple((
+                be_u16(*code),
+                be_u16(self.data_size()),
+                slice(data),
+            ))),
+        }
+    }
+}
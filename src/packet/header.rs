@@ -1,7 +1,7 @@
 use std::io;
 
 use super::parse::{write_bits, BitParsable, BitSerialize, Input, ParseResult};
-use super::ResultCode;
+use super::{Opcode, ResultCode};
 
 use cookie_factory as cf;
 use nom::bits::bits;
@@ -9,10 +9,12 @@ use nom::number::complete::be_u16;
 use nom::sequence::tuple;
 use ux::u4;
 
-#[derive(Debug, PartialEq)]
+pub const DNS_HEADER_LEN: usize = 12;
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct DnsHeaderFlags {
     pub response: bool,             // 1 bit
-    pub opcode: u4,                 // 4 bits TODO: make it enum
+    pub opcode: Opcode,             // 4 bits
     pub authoritative_answer: bool, // 1 bit
     pub truncated_message: bool,    // 1 bit
     pub recursion_desired: bool,    // 1 bit
@@ -28,7 +30,7 @@ impl DnsHeaderFlags {
     fn new() -> Self {
         Self {
             response: false,
-            opcode: u4::new(0),
+            opcode: Opcode::Query,
             authoritative_answer: false,
             truncated_message: false,
             recursion_desired: false,
@@ -77,7 +79,7 @@ impl DnsHeaderFlags {
                 recursion_desired,
                 truncated_message,
                 authoritative_answer,
-                opcode,
+                opcode: Opcode::from(opcode),
                 response,
                 rescode: ResultCode::from(rescode),
                 checking_disabled,
@@ -91,7 +93,7 @@ impl DnsHeaderFlags {
     pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
         write_bits(move |b| {
             self.response.write(b);
-            self.opcode.write(b);
+            u4::from(self.opcode).write(b);
             self.authoritative_answer.write(b);
             self.truncated_message.write(b);
             self.recursion_desired.write(b);
@@ -104,7 +106,7 @@ impl DnsHeaderFlags {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct DnsHeader {
     pub id: u16,
     pub flags: DnsHeaderFlags, // 16 bits
@@ -166,13 +168,10 @@ impl DnsHeader {
 
 #[cfg(test)]
 mod tests {
-    use super::DnsHeader;
-    use crate::packet::ResultCode;
+    use super::{DnsHeader, DNS_HEADER_LEN};
+    use crate::packet::{Opcode, ResultCode};
 
     use cookie_factory as cf;
-    use ux::u4;
-
-    const DNS_HEADER_LEN: usize = 12;
 
     #[test]
     fn parse_buffer_variate_len() {
@@ -203,7 +202,7 @@ mod tests {
         assert!(header.flags.recursion_desired);
         assert!(!header.flags.truncated_message);
         assert!(!header.flags.authoritative_answer);
-        assert_eq!(header.flags.opcode, u4::new(0));
+        assert_eq!(header.flags.opcode, Opcode::Query);
         assert!(!header.flags.response);
         assert_eq!(header.flags.rescode, ResultCode::NoError);
         assert!(!header.flags.checking_disabled);
@@ -232,7 +231,7 @@ mod tests {
         assert!(header.flags.recursion_desired);
         assert!(!header.flags.truncated_message);
         assert!(!header.flags.authoritative_answer);
-        assert_eq!(header.flags.opcode, u4::new(0));
+        assert_eq!(header.flags.opcode, Opcode::Query);
         assert!(header.flags.response);
         assert_eq!(header.flags.rescode, ResultCode::NoError);
         assert!(!header.flags.checking_disabled);
@@ -247,4 +246,20 @@ mod tests {
         let serialized = cf::gen_simple(header.serialize(), Vec::new()).unwrap();
         assert_eq!(&data, serialized.as_slice());
     }
+
+    #[test]
+    fn opcode_round_trip() {
+        // STATUS query followed by an unassigned opcode
+        for (byte, opcode) in [(0x10, Opcode::Status), (0x78, Opcode::Unknown(15))] {
+            let data = [
+                0x17, 0x34, byte, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            ];
+
+            let (_, header) = DnsHeader::parse(&data).unwrap();
+            assert_eq!(header.flags.opcode, opcode);
+
+            let serialized = cf::gen_simple(header.serialize(), Vec::new()).unwrap();
+            assert_eq!(&data, serialized.as_slice());
+        }
+    }
 }
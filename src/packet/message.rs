@@ -1,26 +1,48 @@
-use std::net::Ipv4Addr;
+use std::collections::hash_map::{Entry, HashMap};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use cookie_factory as cf;
-use nom::{multi::count, sequence::tuple};
 use rand::seq::IteratorRandom;
 use thiserror::Error;
 
-#[derive(Debug, Error)]
-pub enum DnsPacketError {
-    #[error("cannot parse from buffer: {0}")]
-    ParseOther(#[from] ByteBufferError),
-}
-
 use super::{
     byte_buffer::{ByteBuffer, ByteBufferError},
-    header::DnsHeader,
-    parse::{Input, ParseResult},
+    edns::{EdnsOption, DNSSEC_OK},
+    header::{DnsHeader, DNS_HEADER_LEN},
+    parse::{Input, ParseError, ParseResult},
     qname::Qname,
+    query_type::QueryType,
     question::DnsQuestion,
     record::DnsRecord,
+    ResultCode,
 };
 
-#[derive(Debug)]
+#[derive(Debug, Error)]
+pub enum DnsPacketError {
+    #[error("cannot parse from buffer: {0}")]
+    ParseOther(#[from] ByteBufferError),
+}
+
+#[derive(Debug, Error)]
+pub enum DnsMessageError {
+    #[error("header declares {declared} {section} records, message has {actual}")]
+    CountMismatch {
+        section: &'static str,
+        declared: u16,
+        actual: usize,
+    },
+    #[error("answer for {0} doesn't follow from any question")]
+    UnrelatedAnswer(Qname),
+    #[error("message takes {size} bytes, more than the maximum of {max_size}")]
+    TooLarge { size: usize, max_size: usize },
+    #[error("{0} bytes after the last record")]
+    TrailingData(usize),
+    #[error("message doesn't fit into a buffer of {0} bytes")]
+    BufferTooSmall(usize),
+}
+
+#[derive(Debug, Clone)]
 pub struct DnsMessage {
     pub header: DnsHeader,
     pub questions: Vec<DnsQuestion>,
@@ -29,6 +51,12 @@ pub struct DnsMessage {
     pub resources: Vec<DnsRecord>,
 }
 
+impl Default for DnsMessage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DnsMessage {
     pub fn new() -> Self {
         Self {
@@ -40,38 +68,290 @@ impl DnsMessage {
         }
     }
 
+    /// Response to `request` with the records of `result`, which is
+    /// typically the response of another server or made up locally.
+    ///
+    /// ID, opcode, recursion desired flag and questions are those of the
+    /// request, result code and the authoritative and recursion available
+    /// flags those of the result. OPT records of the result are left out,
+    /// EDNS is hop-by-hop.
+    pub fn respond_to(request: &DnsMessage, result: DnsMessage) -> DnsMessage {
+        let mut response = DnsMessage::new();
+        response.header.id = request.header.id;
+        response.header.flags.response = true;
+        response.header.flags.opcode = request.header.flags.opcode;
+        response.header.flags.recursion_desired = request.header.flags.recursion_desired;
+        response.header.flags.recursion_available = result.header.flags.recursion_available;
+        response.header.flags.authoritative_answer = result.header.flags.authoritative_answer;
+        response.header.flags.rescode = result.header.flags.rescode;
+
+        response.questions = request.questions.clone();
+        response.answers = result.answers;
+        response.authorities = result.authorities;
+        response.resources = result.resources;
+        response
+            .resources
+            .retain(|record| !matches!(record, DnsRecord::Opt { .. }));
+        response.update_header();
+        response
+    }
+
+    /// Start building a query.
+    pub fn builder() -> DnsMessageBuilder {
+        DnsMessageBuilder::default()
+    }
+
     pub fn update_header(&mut self) {
         self.header.questions = self.questions.len() as u16;
         self.header.answers = self.answers.len() as u16;
         self.header.authoritative_entries = self.authorities.len() as u16;
         self.header.resource_entries = self.resources.len() as u16;
+
+        // The header only holds the lower 4 bits of the result code
+        let upper_rcode = (u16::from(self.header.flags.rescode) >> 4) as u8;
+        for record in &mut self.resources {
+            if let DnsRecord::Opt { extended_rcode, .. } = record {
+                *extended_rcode = upper_rcode;
+            }
+        }
     }
 
-    pub fn parse<'a>(i: Input<'a>, buf: &'a ByteBuffer) -> ParseResult<'a, Self> {
-        let (i, header) = DnsHeader::parse(i)?;
-        let (i, (questions, answers, authorities, resources)) = tuple((
-            count(|x| DnsQuestion::parse(x, buf), header.questions as usize),
-            count(|x| DnsRecord::parse(x, buf), header.answers as usize),
-            count(
-                |x| DnsRecord::parse(x, buf),
-                header.authoritative_entries as usize,
+    /// Check that the header counts match the sections and that every answer
+    /// belongs to a question.
+    ///
+    /// An answer belongs to a question if its owner is the question name or
+    /// the target of a CNAME answer preceding it, so the question section
+    /// always keeps the original names and CNAME chains stay in order. A
+    /// DNAME answer belongs to a question below its owner.
+    pub fn validate_structure(&self) -> Result<(), DnsMessageError> {
+        for (section, declared, actual) in [
+            ("question", self.header.questions, self.questions.len()),
+            ("answer", self.header.answers, self.answers.len()),
+            (
+                "authority",
+                self.header.authoritative_entries,
+                self.authorities.len(),
             ),
-            count(
-                |x| DnsRecord::parse(x, buf),
-                header.resource_entries as usize,
+            (
+                "additional",
+                self.header.resource_entries,
+                self.resources.len(),
             ),
-        ))(i)?;
-
-        Ok((
-            i,
-            Self {
-                header,
-                questions,
-                answers,
-                authorities,
-                resources,
-            },
-        ))
+        ] {
+            if declared as usize != actual {
+                return Err(DnsMessageError::CountMismatch {
+                    section,
+                    declared,
+                    actual,
+                });
+            }
+        }
+
+        let mut names: Vec<&Qname> = self.questions.iter().map(|x| &x.name).collect();
+
+        for record in &self.answers {
+            if matches!(record, DnsRecord::Opt { .. }) {
+                continue;
+            }
+
+            let domain = record.domain();
+
+            let related = match record {
+                DnsRecord::Dname { .. } => names.iter().any(|name| name.is_below(domain)),
+                _ => names.iter().any(|name| name == &domain),
+            };
+            if !related {
+                return Err(DnsMessageError::UnrelatedAnswer(domain.clone()));
+            }
+
+            if let DnsRecord::Cname { host, .. } = record {
+                names.push(host);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether this is an authoritative answer to a question about `qname`.
+    pub fn is_authoritative_for(&self, qname: &Qname) -> bool {
+        self.header.flags.authoritative_answer
+            && self
+                .questions
+                .iter()
+                .any(|question| &question.name == qname)
+    }
+
+    /// Remove repeated records from every section, keeping the first of
+    /// each in place. Records which only differ in TTL are repeats too, the
+    /// one kept gets the smallest TTL among them.
+    pub fn dedup(&mut self) {
+        dedup_records(&mut self.answers);
+        dedup_records(&mut self.authorities);
+        dedup_records(&mut self.resources);
+        self.update_header();
+    }
+
+    /// Raise every TTL below `min` to it and lower every one above `max` to
+    /// that, `max` taking precedence. The OPT record has no TTL to change.
+    pub fn clamp_ttls(&mut self, min: u32, max: u32) {
+        for record in self
+            .answers
+            .iter_mut()
+            .chain(&mut self.authorities)
+            .chain(&mut self.resources)
+        {
+            if matches!(record, DnsRecord::Opt { .. }) {
+                continue;
+            }
+            let ttl = record.ttl().max(min).min(max);
+            if ttl != record.ttl() {
+                *record = record.with_ttl(ttl);
+            }
+        }
+    }
+
+    /// Drop the records a resolver doesn't need, like BIND does with
+    /// `minimal-responses yes`. Only the OPT record is kept besides the
+    /// answers, the SOA of a negative response, which tells how long the
+    /// name may be cached as missing, and the nameservers of a referral
+    /// together with their glue.
+    pub fn minimize(&mut self) {
+        let referral = self.answers.is_empty()
+            && self
+                .authorities
+                .iter()
+                .all(|record| record.qtype() != QueryType::Soa);
+        if referral {
+            self.authorities
+                .retain(|record| record.qtype() == QueryType::Ns);
+        } else if self.answers.is_empty() {
+            self.authorities
+                .retain(|record| record.qtype() == QueryType::Soa);
+        } else {
+            self.authorities.clear();
+        }
+
+        let nameservers: Vec<_> = self
+            .authorities
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::Ns { host, .. } => Some(host.clone()),
+                _ => None,
+            })
+            .collect();
+        self.resources.retain(|record| match record {
+            DnsRecord::Opt { .. } => true,
+            DnsRecord::A { domain, .. } | DnsRecord::Aaaa { domain, .. } => {
+                nameservers.contains(domain)
+            }
+            _ => false,
+        });
+        self.update_header();
+    }
+
+    /// Drop trailing records until the serialized message fits into `max_size` bytes.
+    ///
+    /// Additional records are dropped first as they are optional, losing any
+    /// answer or authority record sets the truncated flag.
+    pub fn truncate(&mut self, max_size: usize) {
+        let questions_size: usize = self
+            .questions
+            .iter()
+            .map(|x| x.name.serialized_size() as usize + 4)
+            .sum();
+        let records_size = |records: &[DnsRecord]| -> usize {
+            records.iter().map(|x| x.serialized_size() as usize).sum()
+        };
+
+        let (answers, authorities) = (self.answers.len(), self.authorities.len());
+        let mut size = DNS_HEADER_LEN
+            + questions_size
+            + records_size(&self.answers)
+            + records_size(&self.authorities)
+            + records_size(&self.resources);
+
+        for section in [
+            &mut self.resources,
+            &mut self.authorities,
+            &mut self.answers,
+        ] {
+            while size > max_size {
+                match section.pop() {
+                    Some(record) => size -= record.serialized_size() as usize,
+                    None => break,
+                }
+            }
+        }
+
+        if self.answers.len() < answers || self.authorities.len() < authorities {
+            self.header.flags.truncated_message = true;
+        }
+
+        self.update_header();
+    }
+
+    pub fn parse<'a>(i: Input<'a>, buf: &'a ByteBuffer) -> ParseResult<'a, Self> {
+        let (i, header) = DnsHeader::parse(i)?;
+        let (i, questions) = parse_section(i, "question", header.questions, |x| {
+            DnsQuestion::parse(x, buf)
+        })?;
+        let record = |x| DnsRecord::parse(x, buf);
+        let (i, answers) = parse_section(i, "answer", header.answers, record)?;
+        let (i, authorities) = parse_section(i, "authority", header.authoritative_entries, record)?;
+        let (i, resources) = parse_section(i, "additional", header.resource_entries, record)?;
+
+        // Some servers append their OPT or TSIG record without counting it.
+        // It's of no use without its count, but the message is fine otherwise.
+        let i = match DnsRecord::parse(i, buf) {
+            _ if i.is_empty() => i,
+            Ok((rest, record))
+                if rest.is_empty()
+                    && matches!(record.qtype(), QueryType::Opt | QueryType::Tsig) =>
+            {
+                rest
+            }
+            _ => {
+                return Err(nom::Err::Failure(ParseError::Message((
+                    i,
+                    DnsMessageError::TrailingData(i.len()),
+                ))))
+            }
+        };
+
+        let mut message = Self {
+            header,
+            questions,
+            answers,
+            authorities,
+            resources,
+        };
+        if let Some(&DnsRecord::Opt { extended_rcode, .. }) = message.get_opt() {
+            let rcode = u16::from(extended_rcode) << 4 | u16::from(message.header.flags.rescode);
+            message.header.flags.rescode = ResultCode::from(rcode);
+        }
+
+        Ok((i, message))
+    }
+
+    /// Parse a whole message from `bytes`, resolving compression pointers
+    /// within them.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ParseError<&[u8]>> {
+        match Self::parse(bytes, &ByteBuffer::new(bytes)) {
+            Ok((_, message)) => Ok(message),
+            // The input left is the end of `bytes`, only its length matters
+            Err(nom::Err::Error(err) | nom::Err::Failure(err)) => {
+                Err(err.map_input(|i| &bytes[bytes.len() - i.len().min(bytes.len())..]))
+            }
+            // Only streaming parsers ask for more input
+            Err(nom::Err::Incomplete(_)) => {
+                Err(ParseError::Nom((bytes, nom::error::ErrorKind::Eof)))
+            }
+        }
+    }
+
+    /// Serialize the message as is, header counts included.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        cf::gen_simple(self.serialize(), Vec::new()).unwrap()
     }
 
     pub fn serialize<'a, W: std::io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
@@ -86,17 +366,195 @@ impl DnsMessage {
         ))
     }
 
+    /// Serialize the message into `buf` as is, without allocating, and
+    /// return the number of bytes written. Unless it fits, what was written
+    /// is garbage.
+    pub fn serialize_into(&self, buf: &mut [u8]) -> Result<usize, DnsMessageError> {
+        let size = buf.len();
+        // Writing to a slice only fails once it is full
+        let (_, written) =
+            cf::gen(self.serialize(), buf).map_err(|_| DnsMessageError::BufferTooSmall(size))?;
+        Ok(written as usize)
+    }
+
+    /// Serialize the message with the header counts brought up to date,
+    /// unless it would take more than `max_size` bytes. The message is left
+    /// as is then, for the caller to `truncate` it.
+    pub fn serialize_checked(&mut self, max_size: usize) -> Result<Vec<u8>, DnsMessageError> {
+        self.update_header();
+
+        let serialized = self.to_bytes();
+        if serialized.len() > max_size {
+            return Err(DnsMessageError::TooLarge {
+                size: serialized.len(),
+                max_size,
+            });
+        }
+        Ok(serialized)
+    }
+
+    /// Get the EDNS pseudo-record, if the message has one.
+    pub fn get_opt(&self) -> Option<&DnsRecord> {
+        self.resources
+            .iter()
+            .find(|record| matches!(record, DnsRecord::Opt { .. }))
+    }
+
+    /// Whether the EDNS pseudo-record has the DNSSEC OK bit set.
+    pub fn dnssec_ok(&self) -> bool {
+        matches!(self.get_opt(), Some(DnsRecord::Opt { flags, .. }) if flags & DNSSEC_OK != 0)
+    }
+
+    /// Options of the EDNS pseudo-record, empty if there is none.
+    pub fn edns_options(&self) -> &[EdnsOption] {
+        match self.get_opt() {
+            Some(DnsRecord::Opt { options, .. }) => options,
+            _ => &[],
+        }
+    }
+
+    /// How long a negative (NXDOMAIN or NODATA) response may be cached: the
+    /// lesser of the TTL and the minimum field of the SOA in the authority
+    /// section (RFC 2308).
+    pub fn negative_ttl(&self) -> Option<u32> {
+        self.authorities.iter().find_map(|record| match record {
+            DnsRecord::Soa { ttl, min_ttl, .. } => Some((*ttl).min(*min_ttl)),
+            _ => None,
+        })
+    }
+
+    /// Whether this is a NODATA response (RFC 2308, section 2.2): the name
+    /// exists, but has no records of the type asked for. Unlike a referral,
+    /// it carries the SOA of the zone in the authority section.
+    pub fn is_nodata(&self) -> bool {
+        self.header.flags.rescode == ResultCode::NoError
+            && self.answers.is_empty()
+            && self
+                .authorities
+                .iter()
+                .any(|record| matches!(record, DnsRecord::Soa { .. }))
+    }
+
+    /// Records of the answer, authority and additional sections, in that
+    /// order.
+    pub fn all_records(&self) -> impl Iterator<Item = &DnsRecord> {
+        self.answers
+            .iter()
+            .chain(&self.authorities)
+            .chain(&self.resources)
+    }
+
+    /// Records of type `qtype` from all sections.
+    pub fn records_of_type(&self, qtype: QueryType) -> impl Iterator<Item = &DnsRecord> {
+        self.all_records()
+            .filter(move |record| record.qtype() == qtype)
+    }
+
+    /// Addresses from A records of the answer section, in message order.
+    pub fn a_answers(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        self.answers.iter().filter_map(|record| match record {
+            DnsRecord::A { addr, .. } => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// Address of a random A record of the answer section.
     pub fn get_random_a(&self) -> Option<Ipv4Addr> {
-        if let Some(DnsRecord::A { addr, .. }) = self
+        self.a_answers().choose(&mut rand::thread_rng())
+    }
+
+    /// Addresses from AAAA records of the answer section, in message order.
+    pub fn aaaa_answers(&self) -> impl Iterator<Item = Ipv6Addr> + '_ {
+        self.answers.iter().filter_map(|record| match record {
+            DnsRecord::Aaaa { addr, .. } => Some(*addr),
+            _ => None,
+        })
+    }
+
+    /// Address of a random AAAA record of the answer section.
+    pub fn get_random_aaaa(&self) -> Option<Ipv6Addr> {
+        self.aaaa_answers().choose(&mut rand::thread_rng())
+    }
+
+    /// Addresses from A and AAAA records of the answer section, in message
+    /// order.
+    pub fn addr_answers(&self) -> impl Iterator<Item = IpAddr> + '_ {
+        self.answers.iter().filter_map(|record| match record {
+            DnsRecord::A { addr, .. } => Some(IpAddr::V4(*addr)),
+            DnsRecord::Aaaa { addr, .. } => Some(IpAddr::V6(*addr)),
+            _ => None,
+        })
+    }
+
+    /// Address of a random A or AAAA record of the answer section.
+    pub fn get_random_addr(&self) -> Option<IpAddr> {
+        self.addr_answers().choose(&mut rand::thread_rng())
+    }
+
+    /// Remove the records of every section whose owner isn't `zone` or
+    /// below it, as a nameserver for `zone` has no say about them. The OPT
+    /// record is kept.
+    pub fn retain_in_bailiwick(&mut self, zone: &Qname) {
+        let in_bailiwick = |record: &DnsRecord| {
+            matches!(record, DnsRecord::Opt { .. }) || record.domain().is_subdomain_of(zone)
+        };
+        self.answers.retain(in_bailiwick);
+        self.authorities.retain(in_bailiwick);
+        self.resources.retain(in_bailiwick);
+        self.update_header();
+    }
+
+    /// Move the A or AAAA records for the end of the CNAME chain starting at
+    /// `qname` into the answers, where some servers leave them out in favour
+    /// of the authority or additional section. Nothing is moved if the
+    /// answers already have records of `qtype` for that name.
+    pub fn promote_cname_target(&mut self, qname: &Qname, qtype: QueryType) {
+        if !matches!(qtype, QueryType::A | QueryType::Aaaa) {
+            return;
+        }
+
+        // Bounded by the number of answers, as a chain may loop
+        let mut target = qname;
+        for _ in 0..self.answers.len() {
+            let next = self.answers.iter().find_map(|record| match record {
+                DnsRecord::Cname { domain, host, .. } if domain == target => Some(host),
+                _ => None,
+            });
+            match next {
+                Some(host) => target = host,
+                None => break,
+            }
+        }
+        if target == qname {
+            return;
+        }
+        let target = target.clone();
+
+        let is_target = |record: &DnsRecord| record.qtype() == qtype && record.domain() == &target;
+        if self.answers.iter().any(is_target) {
+            return;
+        }
+        for section in [&mut self.authorities, &mut self.resources] {
+            let (promoted, rest) = std::mem::take(section).into_iter().partition(is_target);
+            *section = rest;
+            self.answers.extend::<Vec<_>>(promoted);
+        }
+        self.update_header();
+    }
+
+    /// MX records of the answer section by ascending priority. Records of
+    /// equal priority keep their message order, the sort is stable.
+    pub fn sorted_mx(&self) -> Vec<&DnsRecord> {
+        let mut mx = self
             .answers
             .iter()
-            .filter(|record| matches!(record, DnsRecord::A { .. }))
-            .choose(&mut rand::thread_rng())
-        {
-            Some(*addr)
-        } else {
-            None
-        }
+            .filter(|record| matches!(record, DnsRecord::Mx { .. }))
+            .collect::<Vec<_>>();
+        mx.sort_by_key(|record| match record {
+            DnsRecord::Mx { priority, .. } => *priority,
+            _ => unreachable!(),
+        });
+        mx
     }
 
     fn get_ns<'a>(&'a self, qname: &'a Qname) -> impl Iterator<Item = (&'a Qname, &'a Qname)> {
@@ -107,43 +565,263 @@ impl DnsMessage {
                 _ => None,
             })
             // Discard server which aren't authoritative to our query
-            .filter(move |(domain, _)| qname.ends_with(domain))
+            .filter(move |(domain, _)| qname.is_subdomain_of(domain))
     }
 
-    pub fn get_resolved_ns(&self, qname: &Qname) -> Option<Ipv4Addr> {
+    /// Zone delegated to by the NS records for `qname`, the one closest to
+    /// it if there are several.
+    pub fn delegated_zone<'a>(&'a self, qname: &'a Qname) -> Option<&'a Qname> {
         self.get_ns(qname)
-            .flat_map(|(_, host)| {
-                self.resources
-                    .iter()
-                    .filter_map(move |record| match record {
-                        DnsRecord::A { domain, addr, .. } if domain == host => Some(addr),
-                        _ => None,
-                    })
-            })
-            .copied()
-            .choose(&mut rand::thread_rng())
+            .map(|(domain, _)| domain)
+            .max_by_key(|domain| domain.labels().len())
+    }
+
+    /// Addresses from A and AAAA glue records of nameservers authoritative
+    /// for `qname`.
+    pub fn resolved_ns<'a>(&'a self, qname: &'a Qname) -> impl Iterator<Item = IpAddr> + 'a {
+        self.get_ns(qname).flat_map(|(_, host)| {
+            self.resources
+                .iter()
+                .filter_map(move |record| match record {
+                    DnsRecord::A { domain, addr, .. } if domain == host => Some(IpAddr::V4(*addr)),
+                    DnsRecord::Aaaa { domain, addr, .. } if domain == host => {
+                        Some(IpAddr::V6(*addr))
+                    }
+                    _ => None,
+                })
+        })
+    }
+
+    /// Hosts of nameservers authoritative for `qname`.
+    pub fn unresolved_ns<'a>(&'a self, qname: &'a Qname) -> impl Iterator<Item = &'a Qname> {
+        self.get_ns(qname).map(|(_, host)| host)
     }
 
+    /// Host of a random nameserver authoritative for `qname`.
     pub fn get_unresolved_ns<'a>(&'a self, qname: &'a Qname) -> Option<&'a Qname> {
-        self.get_ns(qname)
-            .map(|(_, host)| host)
-            .choose(&mut rand::thread_rng())
+        self.unresolved_ns(qname).choose(&mut rand::thread_rng())
+    }
+}
+
+/// Layout of dig: the header, the OPT pseudosection if there is an OPT
+/// record, then every section which isn't empty, a record per line.
+impl fmt::Display for DnsMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = &self.header.flags;
+        writeln!(
+            f,
+            ";; ->>HEADER<<- opcode: {}, status: {}, id: {}",
+            flags.opcode, flags.rescode, self.header.id
+        )?;
+        f.write_str(";; flags:")?;
+        for (set, flag) in [
+            (flags.response, "qr"),
+            (flags.authoritative_answer, "aa"),
+            (flags.truncated_message, "tc"),
+            (flags.recursion_desired, "rd"),
+            (flags.recursion_available, "ra"),
+            (flags.authed_data, "ad"),
+            (flags.checking_disabled, "cd"),
+        ] {
+            if set {
+                write!(f, " {flag}")?;
+            }
+        }
+        writeln!(
+            f,
+            "; QUERY: {}, ANSWER: {}, AUTHORITY: {}, ADDITIONAL: {}",
+            self.questions.len(),
+            self.answers.len(),
+            self.authorities.len(),
+            self.resources.len()
+        )?;
+
+        if let Some(opt) = self.get_opt() {
+            writeln!(f, "\n;; OPT PSEUDOSECTION:\n{opt}")?;
+        }
+
+        if !self.questions.is_empty() {
+            writeln!(f, "\n;; QUESTION SECTION:")?;
+            for question in &self.questions {
+                writeln!(
+                    f,
+                    ";{}\t\t{}\t{}",
+                    question.name.fqdn(),
+                    question.qclass,
+                    question.qtype
+                )?;
+            }
+        }
+
+        for (section, records) in [
+            ("ANSWER", &self.answers),
+            ("AUTHORITY", &self.authorities),
+            ("ADDITIONAL", &self.resources),
+        ] {
+            let mut records = records
+                .iter()
+                .filter(|record| !matches!(record, DnsRecord::Opt { .. }))
+                .peekable();
+            if records.peek().is_none() {
+                continue;
+            }
+            writeln!(f, "\n;; {section} SECTION:")?;
+            for record in records {
+                writeln!(f, "{record}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn dedup_records(records: &mut Vec<DnsRecord>) {
+    let mut seen: HashMap<DnsRecord, usize> = HashMap::new();
+    let mut kept: Vec<DnsRecord> = Vec::with_capacity(records.len());
+    for record in records.drain(..) {
+        match seen.entry(record.with_ttl(0)) {
+            Entry::Occupied(entry) => {
+                let first = &mut kept[*entry.get()];
+                if record.ttl() < first.ttl() {
+                    *first = first.with_ttl(record.ttl());
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(kept.len());
+                kept.push(record);
+            }
+        }
+    }
+    *records = kept;
+}
+
+/// Builder for queries, the header counts are filled in by `build`.
+#[derive(Debug, Default)]
+pub struct DnsMessageBuilder {
+    id: Option<u16>,
+    message: DnsMessage,
+}
+
+/// Parse the `declared` entries of `section`, failing with a count mismatch
+/// rather than a bare end of input if the message ends before all of them.
+fn parse_section<'a, T>(
+    mut i: Input<'a>,
+    section: &'static str,
+    declared: u16,
+    mut parse: impl FnMut(Input<'a>) -> ParseResult<'a, T>,
+) -> ParseResult<'a, Vec<T>> {
+    // Not preallocated, `declared` comes from the sender
+    let mut entries = Vec::new();
+    for actual in 0..usize::from(declared) {
+        if i.is_empty() {
+            return Err(nom::Err::Failure(ParseError::Message((
+                i,
+                DnsMessageError::CountMismatch {
+                    section,
+                    declared,
+                    actual,
+                },
+            ))));
+        }
+        let (rest, entry) = parse(i)?;
+        entries.push(entry);
+        i = rest;
+    }
+    Ok((i, entries))
+}
+
+impl DnsMessageBuilder {
+    /// Use `id` instead of a random one.
+    pub fn id(mut self, id: u16) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> Self {
+        self.message.header.flags.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn checking_disabled(mut self, checking_disabled: bool) -> Self {
+        self.message.header.flags.checking_disabled = checking_disabled;
+        self
+    }
+
+    /// Add a question in the Internet class.
+    pub fn question(mut self, name: Qname, qtype: QueryType) -> Self {
+        self.message.questions.push(DnsQuestion::new(name, qtype));
+        self
+    }
+
+    /// Add a record to the additional section, such as OPT.
+    pub fn additional(mut self, record: DnsRecord) -> Self {
+        self.message.resources.push(record);
+        self
+    }
+
+    pub fn build(self) -> DnsMessage {
+        let mut message = self.message;
+        message.header.id = self.id.unwrap_or_else(rand::random);
+        message.update_header();
+        message
     }
 }
 
 #[cfg(test)]
 mod test {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
     use std::path::Path;
 
-    use crate::packet::byte_buffer::ByteBuffer;
+    use cookie_factory as cf;
+
+    use crate::packet::byte_buffer::{ByteBuffer, MAX_DNS_MSG_SIZE};
+    use crate::packet::{
+        parse::ParseError, qname::Qname, query_class::QClass, query_type::QueryType,
+        question::DnsQuestion, record::DnsRecord, ResultCode,
+    };
 
-    use super::DnsMessage;
+    use super::{DnsMessage, DnsMessageError};
 
     fn get_data(path: &str) -> Vec<u8> {
         let path = Path::new(path);
         std::fs::read(path).expect("cannot read file")
     }
 
+    /// Feed random bytes and randomly damaged real messages to the parser,
+    /// which has to reject what it can't make sense of rather than panic.
+    #[test]
+    fn arbitrary_input_does_not_panic() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(0x5eed);
+        let check = |bytes: &[u8]| {
+            if let Ok(message) = DnsMessage::from_bytes(bytes) {
+                message.to_bytes();
+            }
+        };
+
+        for _ in 0..5_000 {
+            let mut bytes = vec![0u8; rng.gen_range(0..MAX_DNS_MSG_SIZE)];
+            rng.fill(&mut bytes[..]);
+            check(&bytes);
+        }
+
+        for n in 1..=12 {
+            let data = get_data(&format!("test_data/reply_{n}.bin"));
+            for len in 0..data.len() {
+                check(&data[..len]);
+            }
+            for _ in 0..1_000 {
+                let mut bytes = data.clone();
+                for _ in 0..rng.gen_range(1..8) {
+                    let pos = rng.gen_range(0..bytes.len());
+                    bytes[pos] = rng.gen();
+                }
+                bytes.truncate(rng.gen_range(0..=bytes.len()));
+                check(&bytes);
+            }
+        }
+    }
+
     #[test]
     fn parse_1() {
         let data = get_data("test_data/reply_1.bin");
@@ -155,4 +833,815 @@ mod test {
 
         assert_eq!(packet.header.answers as usize, packet.answers.len());
     }
+
+    #[test]
+    fn bytes_round_trip_1() {
+        let data = get_data("test_data/reply_1.bin");
+
+        let packet = DnsMessage::from_bytes(&data).unwrap();
+        assert_eq!(packet.header.answers as usize, packet.answers.len());
+
+        // Names are written uncompressed, so the bytes only match from the
+        // second round on
+        let serialized = packet.to_bytes();
+        let reparsed = DnsMessage::from_bytes(&serialized).unwrap();
+        assert_eq!(reparsed.to_bytes(), serialized);
+        assert_eq!(reparsed.all_records().count(), packet.all_records().count());
+
+        let err = DnsMessage::from_bytes(&data[..data.len() - 1]).unwrap_err();
+        assert!(err.to_string().ends_with("bytes left)"), "{err}");
+    }
+
+    #[test]
+    fn reply_1_is_displayed_like_dig() {
+        let data = get_data("test_data/reply_1.bin");
+        let packet = DnsMessage::from_bytes(&data).unwrap();
+        assert_eq!(
+            packet.to_string(),
+            include_str!("../../test_data/reply_1.txt")
+        );
+    }
+
+    #[test]
+    fn all_records_of_reply_1() {
+        let data = get_data("test_data/reply_1.bin");
+        let buffer = ByteBuffer::new(&data);
+        let (_, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+
+        assert_eq!(
+            packet.all_records().count(),
+            packet.answers.len() + packet.authorities.len() + packet.resources.len()
+        );
+
+        let ns = packet.records_of_type(QueryType::Ns).collect::<Vec<_>>();
+        assert!(!ns.is_empty());
+        assert!(ns.iter().all(|record| record.qtype() == QueryType::Ns));
+    }
+
+    #[test]
+    fn negative_ttl_from_soa() {
+        let data = get_data("test_data/nxdomain.bin");
+        let buffer = ByteBuffer::new(&data);
+
+        let (_, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+
+        assert_eq!(packet.header.flags.rescode, ResultCode::NxDomain);
+        // SOA TTL is 900, its minimum 3600
+        assert_eq!(packet.negative_ttl(), Some(900));
+
+        // Referral without SOA
+        let data = get_data("test_data/reply_1.bin");
+        let buffer = ByteBuffer::new(&data);
+        let (_, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(packet.negative_ttl(), None);
+    }
+
+    #[test]
+    fn nodata_is_told_from_nxdomain_and_referral() {
+        let data = get_data("test_data/nxdomain.bin");
+        let mut packet = DnsMessage::from_bytes(&data).unwrap();
+        assert!(!packet.is_nodata());
+
+        packet.header.flags.rescode = ResultCode::NoError;
+        assert!(packet.is_nodata());
+
+        let data = get_data("test_data/reply_1.bin");
+        let packet = DnsMessage::from_bytes(&data).unwrap();
+        assert!(!packet.is_nodata());
+    }
+
+    #[test]
+    fn minimize_keeps_soa_of_negative_response() {
+        let data = get_data("test_data/nxdomain.bin");
+        let mut packet = DnsMessage::from_bytes(&data).unwrap();
+
+        packet.minimize();
+        assert_eq!(packet.negative_ttl(), Some(900));
+        assert!(packet
+            .authorities
+            .iter()
+            .all(|record| record.qtype() == QueryType::Soa));
+        assert_eq!(packet.header.authoritative_entries, 1);
+    }
+
+    #[test]
+    fn random_address_of_each_family() {
+        let domain = Qname::try_from("example.com").unwrap();
+        let v4 = Ipv4Addr::new(192, 0, 2, 1);
+        let v6 = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+        let mut packet = DnsMessage::new();
+        packet.answers = vec![
+            DnsRecord::Aaaa {
+                domain: domain.clone(),
+                addr: v6,
+                ttl: 60,
+            },
+            DnsRecord::A {
+                domain: domain.clone(),
+                addr: v4,
+                ttl: 60,
+            },
+        ];
+
+        assert_eq!(packet.get_random_a(), Some(v4));
+        assert_eq!(packet.get_random_aaaa(), Some(v6));
+        for _ in 0..10 {
+            let addr = packet.get_random_addr().unwrap();
+            assert!(addr == IpAddr::V4(v4) || addr == IpAddr::V6(v6), "{addr}");
+        }
+
+        packet.answers.truncate(1);
+        assert_eq!(packet.get_random_a(), None);
+        assert_eq!(packet.get_random_addr(), Some(IpAddr::V6(v6)));
+    }
+
+    #[test]
+    fn respond_to_matches_manual_assembly() {
+        let request = DnsMessage::builder()
+            .id(0x1234)
+            .question(Qname::try_from("example.com").unwrap(), QueryType::A)
+            .build();
+        let data = get_data("test_data/reply_1.bin");
+        let mut result = DnsMessage::from_bytes(&data).unwrap();
+        result.resources.push(DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+
+        let mut expected = DnsMessage::new();
+        expected.header.id = request.header.id;
+        expected.header.flags.recursion_desired = request.header.flags.recursion_desired;
+        expected.header.flags.recursion_available = result.header.flags.recursion_available;
+        expected.header.flags.response = true;
+        expected.header.flags.opcode = request.header.flags.opcode;
+        expected.header.flags.rescode = result.header.flags.rescode;
+        expected.questions = request.questions.clone();
+        expected.answers = result.answers.clone();
+        expected.authorities = result.authorities.clone();
+        for rec in result.resources.iter() {
+            if !matches!(rec, DnsRecord::Opt { .. }) {
+                expected.resources.push(rec.clone());
+            }
+        }
+        expected.update_header();
+
+        let response = DnsMessage::respond_to(&request, result);
+        assert_eq!(response.to_bytes(), expected.to_bytes());
+        assert!(response.get_opt().is_none());
+    }
+
+    #[test]
+    fn authoritative_for_its_questions_only() {
+        let qname = Qname::try_from("example.com").unwrap();
+        let mut packet = DnsMessage::builder()
+            .question(qname.clone(), QueryType::A)
+            .build();
+        assert!(!packet.is_authoritative_for(&qname));
+
+        packet.header.flags.authoritative_answer = true;
+        assert!(packet.is_authoritative_for(&qname));
+        assert!(!packet.is_authoritative_for(&Qname::try_from("www.example.com").unwrap()));
+    }
+
+    #[test]
+    fn dedup_keeps_smallest_ttl() {
+        let domain = Qname::try_from("example.com").unwrap();
+        let a = |i, ttl| DnsRecord::A {
+            domain: domain.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, i),
+            ttl,
+        };
+
+        let mut packet = DnsMessage::new();
+        packet.answers = vec![a(1, 300), a(2, 300), a(1, 60), a(1, 120)];
+        packet.update_header();
+
+        packet.dedup();
+        assert_eq!(packet.answers, [a(1, 60), a(2, 300)]);
+        assert_eq!(packet.header.answers, 2);
+    }
+
+    #[test]
+    fn truncate_to_max_size() {
+        let domain = Qname::try_from("example.com").unwrap();
+
+        let mut packet = DnsMessage::new();
+        for i in 0..40 {
+            packet.answers.push(DnsRecord::A {
+                domain: domain.clone(),
+                addr: Ipv4Addr::new(10, 0, 0, i),
+                ttl: 60,
+            });
+        }
+        packet.update_header();
+        packet.truncate(MAX_DNS_MSG_SIZE);
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert!(serialized.len() <= MAX_DNS_MSG_SIZE);
+        assert_eq!(packet.header.answers as usize, packet.answers.len());
+        assert!(packet.header.flags.truncated_message);
+    }
+
+    #[test]
+    fn serialize_checked_syncs_counts() {
+        let mut packet = DnsMessage::new();
+        packet.questions.push(DnsQuestion::new(
+            Qname::try_from("example.com").unwrap(),
+            QueryType::A,
+        ));
+        packet.answers.push(DnsRecord::A {
+            domain: Qname::try_from("example.com").unwrap(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+
+        let serialized = packet.serialize_checked(MAX_DNS_MSG_SIZE).unwrap();
+        let buffer = ByteBuffer::new(&serialized);
+        let (_, parsed) = DnsMessage::parse(&serialized, &buffer).unwrap();
+        assert_eq!(parsed.header.questions, 1);
+        assert_eq!(parsed.header.answers, 1);
+        parsed.validate_structure().unwrap();
+    }
+
+    #[test]
+    fn serialize_checked_reports_oversize() {
+        let mut packet = DnsMessage::new();
+        for i in 0..40 {
+            packet.answers.push(DnsRecord::A {
+                domain: Qname::try_from("example.com").unwrap(),
+                addr: Ipv4Addr::new(192, 0, 2, i),
+                ttl: 300,
+            });
+        }
+
+        match packet.serialize_checked(MAX_DNS_MSG_SIZE) {
+            Err(DnsMessageError::TooLarge { size, max_size }) => {
+                assert!(size > max_size);
+                assert_eq!(max_size, MAX_DNS_MSG_SIZE);
+            }
+            other => panic!("{other:?}"),
+        }
+        assert_eq!(packet.answers.len(), 40);
+
+        packet.truncate(MAX_DNS_MSG_SIZE);
+        assert!(packet.serialize_checked(MAX_DNS_MSG_SIZE).is_ok());
+    }
+
+    #[test]
+    fn serialize_into_reused_buffer() {
+        let mut packet = DnsMessage::new();
+        packet.questions.push(DnsQuestion::new(
+            Qname::try_from("example.com").unwrap(),
+            QueryType::A,
+        ));
+        packet.update_header();
+
+        let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+        for i in 0..40 {
+            packet.answers.push(DnsRecord::A {
+                domain: Qname::try_from("example.com").unwrap(),
+                addr: Ipv4Addr::new(192, 0, 2, i),
+                ttl: 300,
+            });
+            packet.update_header();
+
+            match packet.serialize_into(&mut buf) {
+                Ok(len) => assert_eq!(buf[..len], packet.to_bytes()),
+                Err(DnsMessageError::BufferTooSmall(size)) => {
+                    assert_eq!(size, MAX_DNS_MSG_SIZE);
+                    assert!(packet.to_bytes().len() > MAX_DNS_MSG_SIZE);
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+    }
+
+    /// Message with a single A answer and a header declaring `answers` of
+    /// them, with `extra` appended.
+    fn with_answer_count(answers: u16, extra: Option<DnsRecord>) -> Vec<u8> {
+        let mut packet = DnsMessage::new();
+        packet.questions.push(DnsQuestion::new(
+            Qname::try_from("example.com").unwrap(),
+            QueryType::A,
+        ));
+        packet.answers.push(DnsRecord::A {
+            domain: Qname::try_from("example.com").unwrap(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+        packet.update_header();
+        packet.header.answers = answers;
+
+        let mut data = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        if let Some(extra) = extra {
+            data = cf::gen_simple(extra.serialize(), data).unwrap();
+        }
+        data
+    }
+
+    fn parse_error(data: &[u8]) -> DnsMessageError {
+        match DnsMessage::parse(data, &ByteBuffer::new(data)) {
+            Err(nom::Err::Failure(ParseError::Message((_, err)))) => err,
+            other => panic!("{other:?}"),
+        }
+    }
+
+    #[test]
+    fn over_declared_count() {
+        let data = with_answer_count(2, None);
+        assert!(matches!(
+            parse_error(&data),
+            DnsMessageError::CountMismatch {
+                section: "answer",
+                declared: 2,
+                actual: 1,
+            }
+        ));
+    }
+
+    #[test]
+    fn under_declared_count() {
+        let data = with_answer_count(0, None);
+        assert!(matches!(
+            parse_error(&data),
+            DnsMessageError::TrailingData(len) if len == data.len() - 29
+        ));
+
+        // Except for an uncounted OPT record
+        let opt = DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        };
+        let data = with_answer_count(1, Some(opt));
+        let buffer = ByteBuffer::new(&data);
+        let (rest, parsed) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(parsed.answers.len(), 1);
+        assert!(parsed.resources.is_empty());
+    }
+
+    #[test]
+    fn extended_rcode_round_trip() {
+        let mut packet = DnsMessage::new();
+        packet.header.flags.response = true;
+        packet.header.flags.rescode = ResultCode::BadVers;
+        packet.resources.push(DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+
+        let serialized = packet.serialize_checked(MAX_DNS_MSG_SIZE).unwrap();
+        // 16 is split into 0 in the header and 1 in the OPT record
+        assert_eq!(serialized[3] & 0x0f, 0);
+        assert!(matches!(
+            packet.get_opt(),
+            Some(DnsRecord::Opt {
+                extended_rcode: 1,
+                ..
+            })
+        ));
+
+        let buffer = ByteBuffer::new(&serialized);
+        let (_, parsed) = DnsMessage::parse(&serialized, &buffer).unwrap();
+        assert_eq!(parsed.header.flags.rescode, ResultCode::BadVers);
+
+        // Without EDNS the header code is all there is
+        packet.resources.clear();
+        packet.header.flags.rescode = ResultCode::Refused;
+        let serialized = packet.serialize_checked(MAX_DNS_MSG_SIZE).unwrap();
+        let buffer = ByteBuffer::new(&serialized);
+        let (_, parsed) = DnsMessage::parse(&serialized, &buffer).unwrap();
+        assert_eq!(parsed.header.flags.rescode, ResultCode::Refused);
+    }
+
+    #[test]
+    fn glue_matches_ignoring_case() {
+        let zone = Qname::try_from("example.com").unwrap();
+
+        let mut packet = DnsMessage::new();
+        packet.authorities.push(DnsRecord::Ns {
+            domain: zone.clone(),
+            host: Qname::try_from("NS1.Example.COM").unwrap(),
+            ttl: 3600,
+        });
+        packet.resources.push(DnsRecord::A {
+            domain: Qname::try_from("ns1.example.com").unwrap(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 3600,
+        });
+
+        let qname = Qname::try_from("www.example.com").unwrap();
+        assert_eq!(
+            packet.resolved_ns(&qname).collect::<Vec<_>>(),
+            vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))]
+        );
+        assert_eq!(
+            packet.get_unresolved_ns(&qname),
+            Some(&Qname::try_from("ns1.example.com").unwrap())
+        );
+    }
+
+    #[test]
+    fn aaaa_glue_is_resolved() {
+        let zone = Qname::try_from("example.com").unwrap();
+        let addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+        let mut packet = DnsMessage::new();
+        packet.authorities.push(DnsRecord::Ns {
+            domain: zone,
+            host: Qname::try_from("ns1.example.com").unwrap(),
+            ttl: 3600,
+        });
+        packet.resources.push(DnsRecord::Aaaa {
+            domain: Qname::try_from("ns1.example.com").unwrap(),
+            addr,
+            ttl: 3600,
+        });
+
+        let qname = Qname::try_from("www.example.com").unwrap();
+        assert_eq!(
+            packet.resolved_ns(&qname).collect::<Vec<_>>(),
+            vec![IpAddr::V6(addr)]
+        );
+    }
+
+    #[test]
+    fn unknown_record_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question and one answer
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            // Question: example.com SPF IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x63, 0x00, 0x01,
+            // Answer: example.com SPF IN 300 "hello world"
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x63, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x0c,
+            0x0b, b'h', b'e', b'l', b'l', b'o', b' ', b'w', b'o', b'r', b'l', b'd',
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        assert!(matches!(packet.answers[0], DnsRecord::Unknown { .. }));
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+    }
+
+    #[test]
+    fn mx_sorted_by_priority() {
+        let mut packet = DnsMessage::new();
+        for (priority, host) in [
+            (20, "b.example.com"),
+            (10, "a.example.com"),
+            (20, "c.example.com"),
+        ] {
+            packet.answers.push(DnsRecord::Mx {
+                domain: Qname::try_from("example.com").unwrap(),
+                priority,
+                host: Qname::try_from(host).unwrap(),
+                ttl: 300,
+            });
+        }
+        packet.answers.push(DnsRecord::A {
+            domain: Qname::try_from("example.com").unwrap(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+
+        let hosts = packet
+            .sorted_mx()
+            .into_iter()
+            .map(|record| match record {
+                DnsRecord::Mx { host, .. } => host.to_string(),
+                other => panic!("{other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(hosts, ["a.example.com", "b.example.com", "c.example.com"]);
+    }
+
+    #[test]
+    fn build_a_query() {
+        let query = DnsMessage::builder()
+            .id(0x1234)
+            .recursion_desired(true)
+            .question(Qname::try_from("example.com").unwrap(), QueryType::A)
+            .build();
+        let serialized = cf::gen_simple(query.serialize(), Vec::new()).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            // Header: query, recursion desired, one question
+            0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            // Question: example.com A IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x01, 0x00, 0x01,
+        ];
+        assert_eq!(serialized, expected);
+    }
+
+    #[test]
+    fn chaos_class_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question and one answer
+            0x12, 0x34, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            // Question: version.bind TXT CH
+            0x07, b'v', b'e', b'r', b's', b'i', b'o', b'n', 0x04, b'b', b'i', b'n', b'd', 0x00,
+            0x00, 0x10, 0x00, 0x03,
+            // Answer: version.bind TXT CH 0 "9.18"
+            0xc0, 0x0c, 0x00, 0x10, 0x00, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x05,
+            0x04, b'9', b'.', b'1', b'8',
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        assert_eq!(packet.questions[0].qclass, QClass::Ch);
+        assert_eq!(packet.answers[0].qtype(), QueryType::Txt);
+        assert_eq!(packet.answers[0].class(), QClass::Ch);
+
+        // Names aren't compressed on output
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        let buffer = ByteBuffer::new(&serialized);
+        let (_, reparsed) = DnsMessage::parse(&serialized, &buffer).unwrap();
+        assert_eq!(reparsed.questions[0].qclass, QClass::Ch);
+        assert_eq!(reparsed.answers[0].class(), QClass::Ch);
+        assert_eq!(reparsed.answers[0].domain(), packet.answers[0].domain());
+    }
+
+    #[test]
+    fn hinfo_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question and one answer
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            // Question: example.com HINFO IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x0d, 0x00, 0x01,
+            // Answer: example.com HINFO IN 300 "Intel" "Linux"
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x0d, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x0c,
+            0x05, b'I', b'n', b't', b'e', b'l', 0x05, b'L', b'i', b'n', b'u', b'x',
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        assert!(matches!(
+            &packet.answers[0],
+            DnsRecord::Hinfo { cpu, os, .. } if cpu == "Intel" && os == "Linux"
+        ));
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+    }
+
+    #[test]
+    fn wks_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question and one answer
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            // Question: example.com WKS IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x0b, 0x00, 0x01,
+            // Answer: example.com WKS IN 300 192.0.2.1 TCP (smtp), port 25
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x0b, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x09,
+            0xc0, 0x00, 0x02, 0x01, 0x06, 0x00, 0x00, 0x00, 0x40,
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        assert!(matches!(
+            &packet.answers[0],
+            DnsRecord::Wks { address, protocol: 6, bitmap, .. }
+                if *address == Ipv4Addr::new(192, 0, 2, 1) && bitmap == &[0, 0, 0, 0x40]
+        ));
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+    }
+
+    #[test]
+    fn ds_and_rrsig_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question and two answers
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            // Question: example.com DS IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x2b, 0x00, 0x01,
+            // Answer: example.com DS IN 86400 20326 8 2 <digest>
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x2b, 0x00, 0x01, 0x00, 0x01, 0x51, 0x80, 0x00, 0x0c,
+            0x4f, 0x66, 0x08, 0x02,
+            0xe0, 0x6d, 0x44, 0xb8, 0x0b, 0x8f, 0x1d, 0x39,
+            // Answer: example.com RRSIG IN 86400 DS 8 2 86400 <expiration>
+            // <inception> 12345 com. <signature>
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x2e, 0x00, 0x01, 0x00, 0x01, 0x51, 0x80, 0x00, 0x1f,
+            0x00, 0x2b, 0x08, 0x02, 0x00, 0x01, 0x51, 0x80,
+            0x65, 0xa1, 0xb2, 0xc3, 0x65, 0x8e, 0x3c, 0x43, 0x30, 0x39,
+            0x03, b'c', b'o', b'm', 0x00,
+            0x9a, 0x1c, 0x4e, 0x7f, 0x02, 0xd3, 0x88, 0x51,
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        assert!(matches!(
+            &packet.answers[0],
+            DnsRecord::Ds { key_tag: 20326, algorithm: 8, digest_type: 2, digest, .. }
+                if digest.len() == 8
+        ));
+        assert!(matches!(
+            &packet.answers[1],
+            DnsRecord::Rrsig {
+                type_covered: QueryType::Ds,
+                labels: 2,
+                original_ttl: 86400,
+                key_tag: 12345,
+                signer,
+                signature,
+                ..
+            } if signer == &Qname::try_from("com").unwrap() && signature.len() == 8
+        ));
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+    }
+
+    #[test]
+    fn dnskey_and_nsec_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question, one answer and one authority
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00,
+            // Question: example.com DNSKEY IN
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x30, 0x00, 0x01,
+            // Answer: example.com DNSKEY IN 3600 257 3 13 <public key>
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x30, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x0c,
+            0x01, 0x01, 0x03, 0x0d,
+            0x99, 0xdb, 0x2c, 0xc1, 0x4c, 0xab, 0xdc, 0x33,
+            // Authority: example.com NSEC IN 3600 www.example.com. A NS SOA
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x2f, 0x00, 0x01, 0x00, 0x00, 0x0e, 0x10, 0x00, 0x14,
+            0x03, b'w', b'w', b'w',
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x01, 0x62,
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        assert!(matches!(
+            &packet.answers[0],
+            DnsRecord::Dnskey { flags: 257, protocol: 3, algorithm: 13, public_key, .. }
+                if public_key.len() == 8
+        ));
+        assert!(matches!(
+            &packet.authorities[0],
+            DnsRecord::Nsec { next, type_bitmaps, .. }
+                if next == &Qname::try_from("www.example.com").unwrap()
+                    && type_bitmaps == &[0x00, 0x01, 0x62]
+        ));
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+    }
+
+    #[test]
+    fn https_round_trip() {
+        #[rustfmt::skip]
+        let data = [
+            // Header: response, one question and one answer
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00,
+            // Question: cloudflare.com HTTPS IN
+            0x0a, b'c', b'l', b'o', b'u', b'd', b'f', b'l', b'a', b'r', b'e', 0x03, b'c', b'o', b'm',
+            0x00, 0x00, 0x41, 0x00, 0x01,
+            // Answer: cloudflare.com HTTPS IN 300 1 . alpn="h3,h2"
+            //   ipv4hint=104.16.132.229,104.16.133.229
+            //   ipv6hint=2606:4700::6810:84e5,2606:4700::6810:85e5
+            0x0a, b'c', b'l', b'o', b'u', b'd', b'f', b'l', b'a', b'r', b'e', 0x03, b'c', b'o', b'm',
+            0x00, 0x00, 0x41, 0x00, 0x01, 0x00, 0x00, 0x01, 0x2c, 0x00, 0x3d,
+            0x00, 0x01, 0x00,
+            0x00, 0x01, 0x00, 0x06, 0x02, b'h', b'3', 0x02, b'h', b'2',
+            0x00, 0x04, 0x00, 0x08, 104, 16, 132, 229, 104, 16, 133, 229,
+            0x00, 0x06, 0x00, 0x20,
+            0x26, 0x06, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68, 0x10, 0x84, 0xe5,
+            0x26, 0x06, 0x47, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x68, 0x10, 0x85, 0xe5,
+        ];
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+        match &packet.answers[0] {
+            DnsRecord::Https {
+                priority,
+                target,
+                params,
+                ..
+            } => {
+                assert_eq!(*priority, 1);
+                assert_eq!(target, &Qname::try_from(".").unwrap());
+                let keys: Vec<_> = params.iter().map(|(key, _)| *key).collect();
+                assert_eq!(keys, [1, 4, 6]);
+                assert_eq!(params[0].1, b"\x02h3\x02h2");
+            }
+            other => panic!("{other:?}"),
+        }
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+
+        // A compressed target is not allowed
+        let mut compressed = data[..56].to_vec();
+        compressed.extend_from_slice(&[0x00, 0x04, 0x00, 0x01, 0xc0, 0x0c]);
+        let buffer = ByteBuffer::new(&compressed);
+        assert!(DnsMessage::parse(&compressed, &buffer).is_err());
+    }
+
+    #[test]
+    fn caa_round_trip() {
+        let data = get_data("test_data/caa.bin");
+        let buffer = ByteBuffer::new(&data);
+
+        let (i, packet) = DnsMessage::parse(&data, &buffer).unwrap();
+        assert_eq!(i.len(), 0);
+
+        let caa: Vec<_> = packet
+            .answers
+            .iter()
+            .map(|record| match record {
+                DnsRecord::Caa {
+                    flags, tag, value, ..
+                } => (*flags, tag.as_str(), value.as_str()),
+                _ => panic!("not a CAA record: {record:?}"),
+            })
+            .collect();
+        assert_eq!(
+            caa,
+            [
+                (0, "issue", "pki.goog"),
+                (0, "iodef", "mailto:security@google.com"),
+                // Unknown tags are kept
+                (128, "tbs", "Unknown"),
+            ]
+        );
+
+        let serialized = cf::gen_simple(packet.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, data);
+    }
+
+    #[test]
+    fn validate_cname_chain() {
+        let qname = Qname::try_from("www.example.com").unwrap();
+        let canonical = Qname::try_from("example.com").unwrap();
+
+        let mut packet = DnsMessage::new();
+        packet
+            .questions
+            .push(DnsQuestion::new(qname.clone(), QueryType::A));
+        packet.answers.push(DnsRecord::Cname {
+            domain: qname,
+            host: canonical.clone(),
+            ttl: 300,
+        });
+        packet.answers.push(DnsRecord::A {
+            domain: canonical,
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+
+        assert!(matches!(
+            packet.validate_structure(),
+            Err(DnsMessageError::CountMismatch { .. })
+        ));
+
+        packet.update_header();
+        packet.validate_structure().unwrap();
+
+        // Target before the CNAME leading to it breaks the chain
+        packet.answers.reverse();
+        assert!(matches!(
+            packet.validate_structure(),
+            Err(DnsMessageError::UnrelatedAnswer(_))
+        ));
+    }
 }
@@ -1,47 +1,168 @@
 use ux::u4;
 
 pub mod byte_buffer;
+pub mod edns;
 mod header;
 pub mod message;
 mod parse;
 pub mod qname;
+pub mod query_class;
 pub mod query_type;
 pub mod question;
 pub mod record;
+pub mod serial;
+
+pub use header::DNS_HEADER_LEN;
+pub use parse::ParseError;
 
 #[derive(Debug, Copy, Clone, PartialEq)]
-pub enum ResultCode {
-    NoError = 0,
-    FormErr = 1,
-    ServFail = 2,
-    NxDomain = 3,
-    NoTimp = 4,
-    Refused = 5,
+pub enum Opcode {
+    Query,
+    IQuery,
+    Status,
+    Notify,
+    Update,
+    Unknown(u8),
 }
 
-impl From<u4> for ResultCode {
+impl From<u4> for Opcode {
     fn from(value: u4) -> Self {
         match u8::from(value) {
+            0 => Opcode::Query,
+            1 => Opcode::IQuery,
+            2 => Opcode::Status,
+            4 => Opcode::Notify,
+            5 => Opcode::Update,
+            value => Opcode::Unknown(value),
+        }
+    }
+}
+
+impl From<Opcode> for u4 {
+    fn from(value: Opcode) -> Self {
+        match value {
+            Opcode::Query => u4::new(0),
+            Opcode::IQuery => u4::new(1),
+            Opcode::Status => u4::new(2),
+            Opcode::Notify => u4::new(4),
+            Opcode::Update => u4::new(5),
+            Opcode::Unknown(value) => u4::new(value & 0x0f),
+        }
+    }
+}
+
+/// Mnemonic of the opcode, `OPCODEn` for unknown ones.
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            Opcode::Query => "QUERY",
+            Opcode::IQuery => "IQUERY",
+            Opcode::Status => "STATUS",
+            Opcode::Notify => "NOTIFY",
+            Opcode::Update => "UPDATE",
+            Opcode::Unknown(value) => return write!(f, "OPCODE{value}"),
+        };
+        f.write_str(mnemonic)
+    }
+}
+
+/// Response code. Only its lower 4 bits fit into the header, EDNS extends it
+/// to 12 bits by carrying the upper 8 in the OPT record (RFC 6891, section
+/// 6.1.3).
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ResultCode {
+    NoError,
+    FormErr,
+    ServFail,
+    NxDomain,
+    NoTimp,
+    Refused,
+    YxDomain,
+    YxRrSet,
+    NxRrSet,
+    NotAuth,
+    NotZone,
+    /// Unsupported EDNS version.
+    BadVers,
+    /// Bad or missing server cookie.
+    BadCookie,
+    Unknown(u16),
+}
+
+impl From<u16> for ResultCode {
+    fn from(value: u16) -> Self {
+        match value {
+            0 => ResultCode::NoError,
             1 => ResultCode::FormErr,
             2 => ResultCode::ServFail,
             3 => ResultCode::NxDomain,
             4 => ResultCode::NoTimp,
             5 => ResultCode::Refused,
-            0 => ResultCode::NoError,
-            _ => ResultCode::NoError,
+            6 => ResultCode::YxDomain,
+            7 => ResultCode::YxRrSet,
+            8 => ResultCode::NxRrSet,
+            9 => ResultCode::NotAuth,
+            10 => ResultCode::NotZone,
+            16 => ResultCode::BadVers,
+            23 => ResultCode::BadCookie,
+            value => ResultCode::Unknown(value),
         }
     }
 }
 
-impl From<ResultCode> for u4 {
+impl From<ResultCode> for u16 {
     fn from(value: ResultCode) -> Self {
         match value {
-            ResultCode::FormErr => u4::new(1),
-            ResultCode::ServFail => u4::new(2),
-            ResultCode::NxDomain => u4::new(3),
-            ResultCode::NoTimp => u4::new(4),
-            ResultCode::Refused => u4::new(5),
-            ResultCode::NoError => u4::new(0),
+            ResultCode::NoError => 0,
+            ResultCode::FormErr => 1,
+            ResultCode::ServFail => 2,
+            ResultCode::NxDomain => 3,
+            ResultCode::NoTimp => 4,
+            ResultCode::Refused => 5,
+            ResultCode::YxDomain => 6,
+            ResultCode::YxRrSet => 7,
+            ResultCode::NxRrSet => 8,
+            ResultCode::NotAuth => 9,
+            ResultCode::NotZone => 10,
+            ResultCode::BadVers => 16,
+            ResultCode::BadCookie => 23,
+            ResultCode::Unknown(value) => value,
         }
     }
 }
+
+/// Mnemonic of the code, `RCODEn` for unknown ones.
+impl std::fmt::Display for ResultCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mnemonic = match self {
+            ResultCode::NoError => "NOERROR",
+            ResultCode::FormErr => "FORMERR",
+            ResultCode::ServFail => "SERVFAIL",
+            ResultCode::NxDomain => "NXDOMAIN",
+            ResultCode::NoTimp => "NOTIMP",
+            ResultCode::Refused => "REFUSED",
+            ResultCode::YxDomain => "YXDOMAIN",
+            ResultCode::YxRrSet => "YXRRSET",
+            ResultCode::NxRrSet => "NXRRSET",
+            ResultCode::NotAuth => "NOTAUTH",
+            ResultCode::NotZone => "NOTZONE",
+            ResultCode::BadVers => "BADVERS",
+            ResultCode::BadCookie => "BADCOOKIE",
+            ResultCode::Unknown(value) => return write!(f, "RCODE{value}"),
+        };
+        f.write_str(mnemonic)
+    }
+}
+
+impl From<u4> for ResultCode {
+    fn from(value: u4) -> Self {
+        ResultCode::from(u16::from(u8::from(value)))
+    }
+}
+
+/// Lower 4 bits of the code, as written into the header.
+impl From<ResultCode> for u4 {
+    fn from(value: ResultCode) -> Self {
+        u4::new((u16::from(value) & 0x0f) as u8)
+    }
+}
@@ -1,3 +1,4 @@
+use std::fmt;
 use std::io;
 
 use bitvec::{prelude::Msb0, vec::BitVec, view::BitView};
@@ -5,9 +6,11 @@ use cookie_factory as cf;
 use nom::bits::complete::take;
 use nom::combinator::map;
 use nom::error::ErrorKind as NomErrorKind;
+use nom::InputLength;
 use ux::u4;
 
 use super::byte_buffer::ByteBufferError;
+use super::message::DnsMessageError;
 use super::qname::QnameError;
 use super::question::DnsQuestionError;
 
@@ -25,8 +28,39 @@ pub enum ParseError<I> {
     ByteBuffer((I, ByteBufferError)),
     Qname((I, QnameError)),
     DnsQuestion((I, DnsQuestionError)),
+    Message((I, DnsMessageError)),
 }
 
+impl<I> ParseError<I> {
+    /// Replace the remaining input the error refers to.
+    pub(crate) fn map_input<J>(self, f: impl FnOnce(I) -> J) -> ParseError<J> {
+        match self {
+            Self::Nom((i, e)) => ParseError::Nom((f(i), e)),
+            Self::ByteBuffer((i, e)) => ParseError::ByteBuffer((f(i), e)),
+            Self::Qname((i, e)) => ParseError::Qname((f(i), e)),
+            Self::DnsQuestion((i, e)) => ParseError::DnsQuestion((f(i), e)),
+            Self::Message((i, e)) => ParseError::Message((f(i), e)),
+        }
+    }
+}
+
+/// Shows what went wrong and how much input was left at that point, rather
+/// than the input itself.
+impl<I: InputLength> fmt::Display for ParseError<I> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (i, reason) = match self {
+            Self::Nom((i, kind)) => (i, format!("{} failed", kind.description())),
+            Self::ByteBuffer((i, e)) => (i, e.to_string()),
+            Self::Qname((i, e)) => (i, e.to_string()),
+            Self::DnsQuestion((i, e)) => (i, e.to_string()),
+            Self::Message((i, e)) => (i, e.to_string()),
+        };
+        write!(f, "{reason} ({} bytes left)", i.input_len())
+    }
+}
+
+impl<I: fmt::Debug + InputLength> std::error::Error for ParseError<I> {}
+
 impl<I> nom::error::ParseError<I> for ParseError<I> {
     fn from_error_kind(input: I, kind: NomErrorKind) -> Self {
         Self::Nom((input, kind))
@@ -40,12 +74,7 @@ impl<I> nom::error::ParseError<I> for ParseError<I> {
 impl<I> nom::ErrorConvert<ParseError<I>> for ParseError<(I, usize)> {
     fn convert(self) -> ParseError<I> {
         // TODO: Recheck its validity
-        match self {
-            Self::Nom((i, e)) => ParseError::Nom((i.0, e)),
-            Self::ByteBuffer((i, e)) => ParseError::ByteBuffer((i.0, e)),
-            Self::Qname((i, e)) => ParseError::Qname((i.0, e)),
-            Self::DnsQuestion((i, e)) => ParseError::DnsQuestion((i.0, e)),
-        }
+        self.map_input(|i| i.0)
     }
 }
 
@@ -114,3 +143,40 @@ impl BitSerialize for bool {
         b.write_last_n_bits(u8::from(*self), 1)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use nom::error::ErrorKind;
+
+    use super::ParseError;
+    use crate::packet::byte_buffer::ByteBufferError;
+    use crate::packet::message::DnsMessageError;
+    use crate::packet::qname::QnameError;
+    use crate::packet::question::DnsQuestionError;
+
+    #[test]
+    fn display_variants() {
+        let i: &[u8] = &[0; 3];
+        let errors = [
+            ParseError::Nom((i, ErrorKind::Eof)),
+            ParseError::ByteBuffer((i, ByteBufferError::JumpLimitExceeded)),
+            ParseError::Qname((i, QnameError::BadTotalLen)),
+            ParseError::DnsQuestion((i, DnsQuestionError::ParseClassError(42))),
+            ParseError::Message((i, DnsMessageError::TrailingData(3))),
+        ];
+
+        let messages = errors
+            .iter()
+            .map(ToString::to_string)
+            .collect::<HashSet<_>>();
+        assert_eq!(messages.len(), errors.len());
+        for message in &messages {
+            assert!(message.ends_with("(3 bytes left)"), "{message}");
+        }
+
+        // Usable wherever errors are boxed
+        let _: Box<dyn std::error::Error> = Box::new(ParseError::Nom((i, ErrorKind::Eof)));
+    }
+}
@@ -1,6 +1,8 @@
 use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
 
 use cookie_factory as cf;
+use rand::Rng;
 use thiserror::Error;
 
 use super::parse::ParseError;
@@ -10,10 +12,24 @@ pub const MAX_LABEL_LEN: usize = 63;
 
 #[derive(Debug, Error)]
 pub enum QnameError {
-    #[error("bogus qname label length: {0}, expected < {}", MAX_LABEL_LEN)]
+    #[error("bogus qname label length: {0}, expected <= {}", MAX_LABEL_LEN)]
     BadLabelLen(usize),
     #[error("exceeded maximum qname length, expected < {}", MAX_QNAME_LEN)]
     BadTotalLen,
+    #[error("bogus internationalized domain name")]
+    Idna,
+    #[error("compression pointer outside of a message")]
+    CompressionPointer,
+    #[error("qname is not terminated by an empty label")]
+    Unterminated,
+    #[error("illegal character {0:?} in qname label")]
+    BadLabelChar(char),
+    #[error("empty label in qname")]
+    EmptyLabel,
+    #[error("qname label {0:?} starts or ends with a hyphen")]
+    BadHyphen(String),
+    #[error("{0:?} is not an email address")]
+    BadEmail(String),
 }
 
 impl<I> From<(I, QnameError)> for ParseError<I> {
@@ -22,18 +38,34 @@ impl<I> From<(I, QnameError)> for ParseError<I> {
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
+/// Domain name as a sequence of labels.
+///
+/// Names are always stored without the trailing dot, so `example.com` and
+/// `example.com.` are the same name, and the root (`""` or `"."`) has no
+/// labels at all. Non-ASCII names are stored in their punycode form. Labels
+/// are lowercased, so names compare and hash the way DNS does, ignoring case.
+///
+/// Names constructed from strings follow the LDH rule: letters, digits and
+/// hyphens, though not at either end of a label, and no empty labels but
+/// for a single trailing dot. Underscores, common in SRV-style names like
+/// `_dmarc.example.com`, are allowed too unless the name is built with
+/// [`Qname::parse`] forbidding them. Names read from the wire are accepted leniently,
+/// with only their lengths checked: servers do hand out labels with other
+/// bytes, DNS-SD instance names contain spaces for example, and those still
+/// have to be relayed as is.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
 pub struct Qname {
     inner: Vec<String>,
 }
 
+/// The root domain.
+pub static ROOT: Qname = Qname { inner: Vec::new() };
+
 impl TryFrom<String> for Qname {
     type Error = QnameError;
 
     fn try_from(value: String) -> Result<Self, Self::Error> {
-        Ok(Self {
-            inner: Qname::split_strings(value.as_str())?,
-        })
+        Qname::parse(&value, true)
     }
 }
 
@@ -41,9 +73,7 @@ impl TryFrom<&str> for Qname {
     type Error = QnameError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
-        Ok(Self {
-            inner: Qname::split_strings(value)?,
-        })
+        Qname::parse(value, true)
     }
 }
 
@@ -51,48 +81,259 @@ impl TryFrom<Vec<String>> for Qname {
     type Error = QnameError;
 
     fn try_from(value: Vec<String>) -> Result<Self, Self::Error> {
-        let checked_vec = value
-            .into_iter()
-            .map(|x| {
-                if x.len() < MAX_LABEL_LEN {
-                    Ok(x)
-                } else {
-                    Err(QnameError::BadLabelLen(x.len()))
-                }
-            })
-            .collect::<Result<Vec<String>, QnameError>>()?;
-
-        let sum: usize = checked_vec.iter().map(|x| x.len() + 1).sum();
-        if sum + 1 > MAX_QNAME_LEN {
-            return Err(QnameError::BadTotalLen);
+        // Only the last label may be empty, as the root is on the wire
+        let labels = match value.split_last() {
+            Some((last, labels)) if last.is_empty() => labels,
+            _ => &value,
+        };
+        for label in labels {
+            check_label(label, true)?;
         }
+        Qname::from_wire_labels(value)
+    }
+}
 
-        Ok(Self { inner: checked_vec })
+impl std::str::FromStr for Qname {
+    type Err = QnameError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::try_from(s)
     }
 }
 
+/// Dotted presentation form without the trailing dot, `.` for the root.
+///
+/// The alternate form (`{:#}`) decodes punycode labels to Unicode.
 impl std::fmt::Display for Qname {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Qname {{ {} }}", self.inner.join("."))
+        if self.inner.is_empty() {
+            return f.write_str(".");
+        }
+        if f.alternate() {
+            return f.write_str(&self.to_unicode());
+        }
+
+        for (i, label) in self.inner.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            f.write_str(label)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fully qualified presentation form of a [`Qname`], with the trailing dot.
+pub struct Fqdn<'a>(&'a Qname);
+
+impl std::fmt::Display for Fqdn<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.inner.is_empty() {
+            return f.write_str(".");
+        }
+        write!(f, "{}.", self.0)
     }
 }
 
 impl Qname {
-    fn split_strings(value: &str) -> Result<Vec<String>, QnameError> {
+    /// The name with its trailing dot, as in zone files.
+    pub fn fqdn(&self) -> Fqdn<'_> {
+        Fqdn(self)
+    }
+
+    /// Name from its presentation form, with underscores in its labels only
+    /// if `allow_underscore` is set.
+    pub fn parse(value: &str, allow_underscore: bool) -> Result<Self, QnameError> {
+        Ok(Self {
+            inner: Qname::split_strings(value, allow_underscore)?,
+        })
+    }
+
+    fn split_strings(value: &str, allow_underscore: bool) -> Result<Vec<String>, QnameError> {
+        // Limits apply to the encoded form, not to the Unicode input
+        let encoded;
+        let value = if value.is_ascii() {
+            value
+        } else {
+            encoded = idna::domain_to_ascii(value).map_err(|_| QnameError::Idna)?;
+            encoded.as_str()
+        };
+
         if value.len() > MAX_QNAME_LEN {
             return Err(QnameError::BadTotalLen);
         }
 
+        let value = value.strip_suffix('.').unwrap_or(value);
+        if value.is_empty() {
+            return Ok(Vec::new());
+        }
+
         value
             .split('.')
             .map(|x| {
-                if x.len() < MAX_LABEL_LEN {
-                    Ok(x.into())
+                if x.len() > MAX_LABEL_LEN {
+                    return Err(QnameError::BadLabelLen(x.len()));
+                }
+                check_label(x, allow_underscore)?;
+                Ok(x.to_ascii_lowercase())
+            })
+            .collect()
+    }
+
+    /// Name from labels read off the wire, only their lengths are checked.
+    pub(crate) fn from_wire_labels(mut value: Vec<String>) -> Result<Self, QnameError> {
+        if value.last().is_some_and(|x| x.is_empty()) {
+            value.pop();
+        }
+
+        let checked_vec = value
+            .into_iter()
+            .map(|x| {
+                if x.len() <= MAX_LABEL_LEN {
+                    Ok(x.to_ascii_lowercase())
                 } else {
                     Err(QnameError::BadLabelLen(x.len()))
                 }
             })
-            .collect()
+            .collect::<Result<Vec<String>, QnameError>>()?;
+
+        let sum: usize = checked_vec.iter().map(|x| x.len() + 1).sum();
+        if sum + 1 > MAX_QNAME_LEN {
+            return Err(QnameError::BadTotalLen);
+        }
+
+        Ok(Self { inner: checked_vec })
+    }
+
+    /// Name for reverse lookups of `addr`, `4.3.2.1.in-addr.arpa` for
+    /// 1.2.3.4.
+    pub fn from_reverse_v4(addr: Ipv4Addr) -> Self {
+        let octets = addr.octets().into_iter().rev().map(|x| x.to_string());
+        Self {
+            inner: octets.chain(["in-addr".into(), "arpa".into()]).collect(),
+        }
+    }
+
+    /// Name for reverse lookups of `addr` with every nibble of the address as
+    /// a label, least significant first, under `ip6.arpa`.
+    pub fn from_reverse_v6(addr: Ipv6Addr) -> Self {
+        let nibbles = addr
+            .octets()
+            .into_iter()
+            .rev()
+            .flat_map(|x| [x & 0xf, x >> 4])
+            .map(|x| format!("{x:x}"));
+        Self {
+            inner: nibbles.chain(["ip6".into(), "arpa".into()]).collect(),
+        }
+    }
+
+    /// Labels of the name, leftmost first, none for the root.
+    pub fn labels(&self) -> &[String] {
+        &self.inner
+    }
+
+    /// Name with the leftmost label dropped, `None` for the root.
+    pub fn parent(&self) -> Option<Qname> {
+        let (_, rest) = self.inner.split_first()?;
+        Some(Self {
+            inner: rest.to_vec(),
+        })
+    }
+
+    /// Whether the name is `ancestor` itself or below it.
+    pub fn is_subdomain_of(&self, ancestor: &Qname) -> bool {
+        self.inner.ends_with(&ancestor.inner)
+    }
+
+    /// Whether the name is strictly below `ancestor`.
+    pub fn is_below(&self, ancestor: &Qname) -> bool {
+        self.inner.len() > ancestor.inner.len() && self.inner.ends_with(&ancestor.inner)
+    }
+
+    /// Name with the `owner` suffix replaced by `target`, as a DNAME record
+    /// of `owner` redirects it. `None` if the name isn't below `owner`, an
+    /// error if the substituted name is too long.
+    pub fn substitute_dname(
+        &self,
+        owner: &Qname,
+        target: &Qname,
+    ) -> Option<Result<Qname, QnameError>> {
+        if !self.is_below(owner) {
+            return None;
+        }
+        let prefix = &self.inner[..self.inner.len() - owner.inner.len()];
+        let labels = prefix.iter().chain(&target.inner).cloned().collect();
+        Some(Qname::from_wire_labels(labels))
+    }
+
+    /// Mailbox name as in the RNAME of a SOA record, the local part of
+    /// `email` as the first label followed by the domain.
+    ///
+    /// Dots in the local part stay within the first label, which is written
+    /// with them escaped in zone files: `john.doe@example.com` becomes
+    /// `john\.doe.example.com`.
+    pub fn from_email(email: &str) -> Result<Self, QnameError> {
+        let bad_email = || QnameError::BadEmail(email.to_string());
+        let (local, domain) = email.rsplit_once('@').ok_or_else(bad_email)?;
+        if local.is_empty() || !local.chars().all(|x| x.is_ascii_graphic()) {
+            return Err(bad_email());
+        }
+
+        let domain = Qname::try_from(domain)?;
+        if domain.inner.is_empty() {
+            return Err(bad_email());
+        }
+        let labels = std::iter::once(local.to_string())
+            .chain(domain.inner)
+            .collect();
+        Qname::from_wire_labels(labels)
+    }
+
+    /// Email address of a mailbox name as in the RNAME of a SOA record,
+    /// the reverse of [`Qname::from_email`]. `None` for names of less than
+    /// two labels, which have no domain.
+    pub fn to_email(&self) -> Option<String> {
+        match self.inner.as_slice() {
+            [local, domain @ ..] if !domain.is_empty() => {
+                Some(format!("{local}@{}", domain.join(".")))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the name is a valid host name, with strictly letters, digits
+    /// and hyphens in its labels.
+    pub fn is_hostname(&self) -> bool {
+        self.inner.iter().all(|x| check_label(x, false).is_ok())
+    }
+
+    /// Parse an uncompressed wire-format name, returning it together with
+    /// the number of bytes it took.
+    ///
+    /// Compression pointers can't be followed without the message they point
+    /// into and are rejected.
+    pub fn from_wire(wire: &[u8]) -> Result<(Self, usize), QnameError> {
+        let mut labels = Vec::new();
+        let mut pos = 0;
+
+        loop {
+            let len = *wire.get(pos).ok_or(QnameError::Unterminated)? as usize;
+            if len & 0xC0 == 0xC0 {
+                return Err(QnameError::CompressionPointer);
+            }
+            pos += 1;
+
+            if len == 0 {
+                break;
+            }
+
+            let label = wire.get(pos..pos + len).ok_or(QnameError::Unterminated)?;
+            labels.push(String::from_utf8_lossy(label).into_owned());
+            pos += len;
+        }
+
+        Ok((Self::from_wire_labels(labels)?, pos))
     }
 
     pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
@@ -106,6 +347,28 @@ impl Qname {
         ))
     }
 
+    /// Wire form with every letter upper- or lowercased at random, for
+    /// queries using the 0x20 trick (draft-vixie-dnsext-dns0x20). Servers
+    /// echo the question back as is, so a spoofed response also has to guess
+    /// the casing.
+    pub fn to_wire_random_case(&self) -> Vec<u8> {
+        let mut rng = rand::thread_rng();
+        let mut wire = cf::gen_simple(self.serialize(), Vec::new()).unwrap();
+        // Label lengths are at most 63, below any ASCII letter
+        for x in wire.iter_mut().filter(|x| x.is_ascii_alphabetic()) {
+            if rng.gen() {
+                x.make_ascii_uppercase();
+            }
+        }
+        wire
+    }
+
+    /// Presentation form with punycode labels decoded to Unicode.
+    pub fn to_unicode(&self) -> String {
+        let (unicode, _) = idna::domain_to_unicode(&self.inner.join("."));
+        unicode
+    }
+
     // TODO: eliminate usage of this method by using cookie_factory methods
     pub fn serialized_size(&self) -> u16 {
         use cf::gen;
@@ -115,7 +378,308 @@ impl Qname {
         pos as u16
     }
 
+    /// Whether the last labels of the name are those of `other`, the same
+    /// as [`Qname::is_subdomain_of`].
     pub fn ends_with(&self, other: &Qname) -> bool {
-        std::iter::zip(self.inner.iter().rev(), other.inner.iter().rev()).all(|(x, y)| x == y)
+        self.is_subdomain_of(other)
+    }
+}
+
+/// Check that `label` follows the LDH rule: letters, digits and hyphens,
+/// plus underscores if `allow_underscore` is set. It may be neither empty
+/// nor start or end with a hyphen.
+fn check_label(label: &str, allow_underscore: bool) -> Result<(), QnameError> {
+    if label.is_empty() {
+        return Err(QnameError::EmptyLabel);
+    }
+    if let Some(x) = label
+        .chars()
+        .find(|&x| !(x.is_ascii_alphanumeric() || x == '-' || (allow_underscore && x == '_')))
+    {
+        return Err(QnameError::BadLabelChar(x));
+    }
+    if label.starts_with('-') || label.ends_with('-') {
+        return Err(QnameError::BadHyphen(label.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use cookie_factory as cf;
+
+    use super::{Qname, QnameError};
+    use crate::packet::byte_buffer::ByteBuffer;
+
+    #[test]
+    fn email_round_trip() {
+        let rname = Qname::from_email("hostmaster@example.com").unwrap();
+        assert_eq!(rname, Qname::try_from("hostmaster.example.com").unwrap());
+        assert_eq!(rname.to_email().as_deref(), Some("hostmaster@example.com"));
+
+        // The dot belongs to the first label, it isn't a label separator
+        let rname = Qname::from_email("john.doe@example.com").unwrap();
+        assert_eq!(rname.labels(), ["john.doe", "example", "com"]);
+        assert_ne!(rname, Qname::try_from("john.doe.example.com").unwrap());
+        assert_eq!(rname.to_email().as_deref(), Some("john.doe@example.com"));
+
+        let mut buf = [0u8; 64];
+        let (_, len) = cf::gen(rname.serialize(), &mut buf[..]).unwrap();
+        assert_eq!(&buf[..len as usize], b"\x08john.doe\x07example\x03com\x00");
+
+        for email in [
+            "example.com",
+            "@example.com",
+            "john doe@example.com",
+            "root@",
+        ] {
+            assert!(
+                matches!(Qname::from_email(email), Err(QnameError::BadEmail(_))),
+                "{email}"
+            );
+        }
+        assert_eq!(Qname::try_from("com").unwrap().to_email(), None);
+    }
+
+    #[test]
+    fn trailing_dot_is_ignored() {
+        assert_eq!(
+            Qname::try_from("example.com").unwrap(),
+            Qname::try_from("example.com.").unwrap()
+        );
+        assert_eq!(
+            Qname::try_from("example.com.").unwrap(),
+            Qname::try_from(vec!["example".to_string(), "com".to_string()]).unwrap()
+        );
+        assert_eq!(
+            Qname::try_from(vec![
+                "example".to_string(),
+                "com".to_string(),
+                String::new()
+            ])
+            .unwrap(),
+            Qname::try_from("example.com").unwrap()
+        );
+        assert_ne!(
+            Qname::try_from("example.com.").unwrap(),
+            Qname::try_from("example.org.").unwrap()
+        );
+    }
+
+    #[test]
+    fn root_equals_itself() {
+        let root = Qname::try_from(".").unwrap();
+
+        assert_eq!(root, Qname::try_from("").unwrap());
+        assert_eq!(root, Qname::try_from(Vec::new()).unwrap());
+        assert_ne!(root, Qname::try_from("com.").unwrap());
+    }
+
+    #[test]
+    fn display_and_parse() {
+        for (input, displayed) in [
+            (".", "."),
+            ("", "."),
+            ("localhost", "localhost"),
+            ("www.example.com", "www.example.com"),
+            ("www.example.com.", "www.example.com"),
+        ] {
+            let qname = input.parse::<Qname>().unwrap();
+            assert_eq!(qname.to_string(), displayed);
+            assert_eq!(displayed.parse::<Qname>().unwrap(), qname);
+        }
+    }
+
+    #[test]
+    fn wire_round_trip() {
+        for (input, wire) in [
+            (".", &b"\x00"[..]),
+            ("com", &b"\x03com\x00"[..]),
+            ("www.example.com", &b"\x03www\x07example\x03com\x00"[..]),
+        ] {
+            let qname = Qname::try_from(input).unwrap();
+
+            let (serialized, _) = cf::gen(qname.serialize(), Vec::new()).unwrap();
+            assert_eq!(serialized, wire);
+            assert_eq!(qname.serialized_size() as usize, wire.len());
+
+            let buffer = ByteBuffer::new(wire);
+            let (rest, parsed) = buffer.read_qname()(wire).unwrap();
+            assert!(rest.is_empty());
+            assert_eq!(parsed, qname);
+        }
+    }
+
+    #[test]
+    fn unicode_labels_are_punycode() {
+        let qname = Qname::try_from("www.münchen.de").unwrap();
+        assert_eq!(qname, Qname::try_from("www.xn--mnchen-3ya.de").unwrap());
+
+        let (serialized, _) = cf::gen(qname.serialize(), Vec::new()).unwrap();
+        assert_eq!(serialized, b"\x03www\x0exn--mnchen-3ya\x02de\x00");
+
+        assert_eq!(qname.to_string(), "www.xn--mnchen-3ya.de");
+        assert_eq!(qname.to_unicode(), "www.münchen.de");
+        assert_eq!(format!("{qname:#}"), "www.münchen.de");
+    }
+
+    #[test]
+    fn label_length_limit() {
+        let longest = "a".repeat(63);
+        let qname = Qname::try_from(format!("{longest}.com")).unwrap();
+        assert_eq!(qname.labels()[0], longest);
+        assert!(matches!(
+            Qname::try_from(format!("{longest}a.com")),
+            Err(QnameError::BadLabelLen(64))
+        ));
+
+        let mut wire = vec![63];
+        wire.extend_from_slice(longest.as_bytes());
+        wire.extend_from_slice(b"\x03com\x00");
+        assert_eq!(Qname::from_wire(&wire).unwrap(), (qname, 69));
+    }
+
+    #[test]
+    fn unicode_label_length_is_checked_encoded() {
+        // 60 bytes of UTF-8, but 64 bytes of punycode
+        let label = "日本語のドメイン名例文字列長い試験用漢字";
+        assert!(matches!(
+            Qname::try_from(format!("{label}.jp")),
+            Err(QnameError::BadLabelLen(_))
+        ));
+    }
+
+    #[test]
+    fn from_wire() {
+        let wire = b"\x03www\x07Example\x03com\x00trailing";
+        let (qname, len) = Qname::from_wire(wire).unwrap();
+        assert_eq!(qname, Qname::try_from("www.example.com").unwrap());
+        assert_eq!(len, 17);
+
+        assert_eq!(
+            Qname::from_wire(b"\x00").unwrap(),
+            (Qname::try_from(".").unwrap(), 1)
+        );
+
+        assert!(matches!(
+            Qname::from_wire(b"\x03www\xc0\x0c"),
+            Err(QnameError::CompressionPointer)
+        ));
+        assert!(matches!(
+            Qname::from_wire(b"\x03www\x07exam"),
+            Err(QnameError::Unterminated)
+        ));
+    }
+
+    #[test]
+    fn label_characters() {
+        let qname = Qname::try_from("www-1.example.com").unwrap();
+        assert!(qname.is_hostname());
+
+        let qname = Qname::try_from("_sip._udp.example.com").unwrap();
+        assert!(!qname.is_hostname());
+
+        assert!(matches!(
+            Qname::try_from("exa\x01mple.com"),
+            Err(QnameError::BadLabelChar('\x01'))
+        ));
+        assert!(matches!(
+            Qname::try_from(vec!["my printer".to_string(), "local".to_string()]),
+            Err(QnameError::BadLabelChar(' '))
+        ));
+
+        // Lenient on the wire
+        let (qname, _) = Qname::from_wire(b"\x0amy printer\x05local\x00").unwrap();
+        assert_eq!(qname.to_string(), "my printer.local");
+
+        for name in ["a..b", "example.com..", "..", ".example.com"] {
+            assert!(
+                matches!(Qname::try_from(name), Err(QnameError::EmptyLabel)),
+                "{name}"
+            );
+        }
+        assert!(matches!(
+            Qname::try_from(vec!["a".to_string(), String::new(), "b".to_string()]),
+            Err(QnameError::EmptyLabel)
+        ));
+        assert_eq!(Qname::try_from("example.com.").unwrap().labels().len(), 2);
+        assert_eq!(
+            Qname::try_from(vec!["example".to_string(), String::new()]).unwrap(),
+            Qname::try_from("example").unwrap()
+        );
+
+        for name in ["-www.example.com", "www-.example.com", "www.example.-"] {
+            assert!(
+                matches!(Qname::try_from(name), Err(QnameError::BadHyphen(_))),
+                "{name}"
+            );
+        }
+
+        assert!(Qname::parse("_sip._udp.example.com", true).is_ok());
+        assert!(matches!(
+            Qname::parse("_sip._udp.example.com", false),
+            Err(QnameError::BadLabelChar('_'))
+        ));
+        assert!(Qname::parse("www-1.example.com", false).is_ok());
+    }
+
+    #[test]
+    fn case_is_normalized() {
+        let wire = b"\x03ns1\x07example\x03com\x00";
+        let buffer = ByteBuffer::new(wire);
+        let (_, parsed) = buffer.read_qname()(wire).unwrap();
+
+        let constructed = Qname::try_from("NS1.Example.COM").unwrap();
+        assert_eq!(constructed, parsed);
+        assert_eq!(constructed.to_string(), "ns1.example.com");
+        assert!(HashSet::from([parsed]).contains(&constructed));
+
+        let (from_wire, _) = Qname::from_wire(b"\x03NS1\x07Example\x03COM\x00").unwrap();
+        assert_eq!(from_wire, constructed);
+
+        let random_case = constructed.to_wire_random_case();
+        assert!(random_case.eq_ignore_ascii_case(wire));
+        assert_eq!(Qname::from_wire(&random_case).unwrap().0, constructed);
+    }
+
+    #[test]
+    fn parent_chain() {
+        let qname = Qname::try_from("www.example.com").unwrap();
+        assert_eq!(qname.labels(), ["www", "example", "com"]);
+
+        let example = qname.parent().unwrap();
+        assert_eq!(example, Qname::try_from("example.com").unwrap());
+        let com = example.parent().unwrap();
+        assert_eq!(com.labels(), ["com"]);
+        let root = com.parent().unwrap();
+        assert!(root.labels().is_empty());
+        assert_eq!(root.parent(), None);
+
+        for ancestor in [&qname, &example, &com, &root] {
+            assert!(qname.is_subdomain_of(ancestor), "{ancestor}");
+            assert!(qname.ends_with(ancestor), "{ancestor}");
+        }
+        assert!(!com.is_subdomain_of(&qname));
+        assert!(!com.ends_with(&example));
+        assert!(!qname.is_subdomain_of(&Qname::try_from("ample.com").unwrap()));
+    }
+
+    #[test]
+    fn reverse_names() {
+        assert_eq!(
+            Qname::from_reverse_v4(Ipv4Addr::new(8, 8, 8, 8)).to_string(),
+            "8.8.8.8.in-addr.arpa"
+        );
+        assert_eq!(
+            Qname::from_reverse_v4(Ipv4Addr::new(192, 0, 2, 1)).to_string(),
+            "1.2.0.192.in-addr.arpa"
+        );
+        assert_eq!(
+            Qname::from_reverse_v6("2001:db8::567:89ab".parse::<Ipv6Addr>().unwrap()).to_string(),
+            "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+        );
     }
 }
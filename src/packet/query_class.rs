@@ -0,0 +1,56 @@
+use super::parse::{Input, ParseResult};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum QClass {
+    Unknown(u16),
+    In, // 1, the Internet
+    Ch, // 3, CHAOS
+    Hs, // 4, Hesiod
+}
+
+impl From<u16> for QClass {
+    fn from(value: u16) -> Self {
+        match value {
+            1 => QClass::In,
+            3 => QClass::Ch,
+            4 => QClass::Hs,
+            _ => QClass::Unknown(value),
+        }
+    }
+}
+
+impl From<QClass> for u16 {
+    fn from(value: QClass) -> Self {
+        match value {
+            QClass::Unknown(x) => x,
+            QClass::In => 1,
+            QClass::Ch => 3,
+            QClass::Hs => 4,
+        }
+    }
+}
+
+/// Mnemonic of the class, `CLASSn` for unknown ones (RFC 3597).
+impl std::fmt::Display for QClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QClass::In => f.write_str("IN"),
+            QClass::Ch => f.write_str("CH"),
+            QClass::Hs => f.write_str("HS"),
+            QClass::Unknown(value) => write!(f, "CLASS{value}"),
+        }
+    }
+}
+
+impl QClass {
+    pub fn parse(i: Input) -> ParseResult<Self> {
+        let (i, qclass) = nom::number::complete::be_u16(i)?;
+        Ok((i, Self::from(qclass)))
+    }
+
+    pub fn serialize<'a, W: std::io::Write + 'a>(
+        &'a self,
+    ) -> impl cookie_factory::SerializeFn<W> + 'a {
+        cookie_factory::bytes::be_u16((*self).into())
+    }
+}
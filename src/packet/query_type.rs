@@ -1,16 +1,38 @@
+use std::fmt;
+use std::str::FromStr;
+
+use thiserror::Error;
+
 use super::parse::{Input, ParseResult};
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Error)]
+#[error("unknown record type {0:?}")]
+pub struct UnknownQueryType(String);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum QueryType {
     Unknown(u16),
     A,     // a host address
     Ns,    // an authoritative name server
     Cname, // the canonical name for an alias
     Soa,   // marks the start of a zone of authority
-    // WKS,   // a well known service description
+    Wks,   // a well known service description
     // PTR,   // a domain name pointer
-    Mx,   // mail exchange
-    Aaaa, // 28
+    Hinfo, // host information
+    Mx,    // mail exchange
+    Txt,   // text strings
+    Aaaa,  // 28
+    Dname, // 39, alias for a subtree
+    Opt,   // 41, EDNS pseudo-record
+    Ds,    // 43, delegation signer
+    Rrsig, // 46, signature of a record set
+    Nsec,  // 47, next secure name
+    Dnskey, // 48, public key of a zone
+    Svcb,  // 64, service binding
+    Https, // 65, service binding for HTTPS
+    Tsig,  // 250, transaction signature
+    Any,   // 255, all records of a name
+    Caa,   // 257, certification authority authorization
 }
 
 impl From<u16> for QueryType {
@@ -20,10 +42,23 @@ impl From<u16> for QueryType {
             2 => QueryType::Ns,
             5 => QueryType::Cname,
             6 => QueryType::Soa,
-            // 11 => QueryType::WKS,
+            11 => QueryType::Wks,
             // 12 => QueryType::PTR,
+            13 => QueryType::Hinfo,
             15 => QueryType::Mx,
+            16 => QueryType::Txt,
             28 => QueryType::Aaaa,
+            39 => QueryType::Dname,
+            41 => QueryType::Opt,
+            43 => QueryType::Ds,
+            46 => QueryType::Rrsig,
+            47 => QueryType::Nsec,
+            48 => QueryType::Dnskey,
+            64 => QueryType::Svcb,
+            65 => QueryType::Https,
+            250 => QueryType::Tsig,
+            255 => QueryType::Any,
+            257 => QueryType::Caa,
             _ => QueryType::Unknown(value),
         }
     }
@@ -37,14 +72,88 @@ impl From<QueryType> for u16 {
             QueryType::Ns => 2,
             QueryType::Cname => 5,
             QueryType::Soa => 6,
-            // QueryType::WKS => 11,
+            QueryType::Wks => 11,
             // QueryType::PTR => 12,
+            QueryType::Hinfo => 13,
             QueryType::Mx => 15,
+            QueryType::Txt => 16,
             QueryType::Aaaa => 28,
+            QueryType::Dname => 39,
+            QueryType::Opt => 41,
+            QueryType::Ds => 43,
+            QueryType::Rrsig => 46,
+            QueryType::Nsec => 47,
+            QueryType::Dnskey => 48,
+            QueryType::Svcb => 64,
+            QueryType::Https => 65,
+            QueryType::Tsig => 250,
+            QueryType::Any => 255,
+            QueryType::Caa => 257,
         }
     }
 }
 
+/// Mnemonics of the known types, as in zone files.
+const MNEMONICS: [(QueryType, &str); 21] = [
+    (QueryType::A, "A"),
+    (QueryType::Ns, "NS"),
+    (QueryType::Cname, "CNAME"),
+    (QueryType::Soa, "SOA"),
+    (QueryType::Wks, "WKS"),
+    (QueryType::Hinfo, "HINFO"),
+    (QueryType::Mx, "MX"),
+    (QueryType::Txt, "TXT"),
+    (QueryType::Aaaa, "AAAA"),
+    (QueryType::Dname, "DNAME"),
+    (QueryType::Opt, "OPT"),
+    (QueryType::Ds, "DS"),
+    (QueryType::Rrsig, "RRSIG"),
+    (QueryType::Nsec, "NSEC"),
+    (QueryType::Dnskey, "DNSKEY"),
+    (QueryType::Svcb, "SVCB"),
+    (QueryType::Https, "HTTPS"),
+    (QueryType::Tsig, "TSIG"),
+    (QueryType::Any, "ANY"),
+    (QueryType::Caa, "CAA"),
+    // Type 255 is also known by its wildcard form
+    (QueryType::Any, "*"),
+];
+
+/// Mnemonic of the type, `TYPEn` for unknown ones (RFC 3597).
+impl fmt::Display for QueryType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match MNEMONICS.iter().find(|(qtype, _)| qtype == self) {
+            Some((_, mnemonic)) => f.write_str(mnemonic),
+            None => write!(f, "TYPE{}", u16::from(*self)),
+        }
+    }
+}
+
+/// Parse a mnemonic or the `TYPEn` form, ignoring case.
+impl FromStr for QueryType {
+    type Err = UnknownQueryType;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(&(qtype, _)) = MNEMONICS
+            .iter()
+            .find(|(_, mnemonic)| mnemonic.eq_ignore_ascii_case(s))
+        {
+            return Ok(qtype);
+        }
+
+        let number = s
+            .get(..4)
+            .filter(|prefix| prefix.eq_ignore_ascii_case("TYPE"))
+            .map(|_| &s[4..])
+            // A sign would be accepted by `u16::from_str`
+            .filter(|number| number.bytes().all(|x| x.is_ascii_digit()));
+        number
+            .and_then(|number| number.parse::<u16>().ok())
+            .map(QueryType::from)
+            .ok_or_else(|| UnknownQueryType(s.to_string()))
+    }
+}
+
 impl QueryType {
     pub fn parse(i: Input) -> ParseResult<Self> {
         let (i, qtype) = nom::number::complete::be_u16(i)?;
@@ -55,3 +164,38 @@ impl QueryType {
         cookie_factory::bytes::be_u16((*self).into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::QueryType;
+
+    #[test]
+    fn mnemonics_round_trip() {
+        for (text, qtype) in [
+            ("A", QueryType::A),
+            ("MX", QueryType::Mx),
+            ("AAAA", QueryType::Aaaa),
+        ] {
+            assert_eq!(text.parse::<QueryType>().unwrap(), qtype);
+            assert_eq!(text.to_lowercase().parse::<QueryType>().unwrap(), qtype);
+            assert_eq!(qtype.to_string(), text);
+        }
+    }
+
+    #[test]
+    fn numeric_form() {
+        assert_eq!("TYPE15".parse::<QueryType>().unwrap(), QueryType::Mx);
+        assert_eq!("type28".parse::<QueryType>().unwrap(), QueryType::Aaaa);
+
+        let unknown = "TYPE1234".parse::<QueryType>().unwrap();
+        assert_eq!(unknown, QueryType::Unknown(1234));
+        assert_eq!(unknown.to_string(), "TYPE1234");
+    }
+
+    #[test]
+    fn invalid_names() {
+        for text in ["", "MXX", "TYPE", "TYPE65536", "TYPE-1", "TYPE+1", "ÄAAA"] {
+            assert!(text.parse::<QueryType>().is_err(), "{text}");
+        }
+    }
+}
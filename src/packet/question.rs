@@ -4,16 +4,17 @@ use super::{
     byte_buffer::ByteBuffer,
     parse::{Input, ParseError, ParseResult},
     qname::Qname,
+    query_class::QClass,
     query_type::QueryType,
 };
 
 use cookie_factory as cf;
-use nom::{number::complete::be_u16, sequence::tuple};
+use nom::sequence::tuple;
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum DnsQuestionError {
-    #[error("qclass is not IN, value is {0}, should be 1")]
+    #[error("qclass {0} is not supported, should be IN, CH or HS")]
     ParseClassError(u16),
 }
 
@@ -23,29 +24,51 @@ impl<I> From<(I, DnsQuestionError)> for ParseError<I> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DnsQuestion {
     pub name: Qname,
     pub qtype: QueryType,
+    pub qclass: QClass,
 }
 
 impl DnsQuestion {
+    /// Question in the Internet class.
+    pub fn new(name: Qname, qtype: QueryType) -> Self {
+        Self {
+            name,
+            qtype,
+            qclass: QClass::In,
+        }
+    }
+
     pub fn parse<'a>(i: Input<'a>, buf: &'a ByteBuffer) -> ParseResult<'a, Self> {
-        let (i, (name, qtype, qclass)) = tuple((buf.read_qname(), QueryType::parse, be_u16))(i)?;
+        let (i, (name, qtype, qclass)) =
+            tuple((buf.read_qname(), QueryType::parse, QClass::parse))(i)?;
 
-        if qclass != 1 {
+        if let QClass::Unknown(qclass) = qclass {
             return Err(nom::Err::Failure(ParseError::DnsQuestion((
                 i,
                 DnsQuestionError::ParseClassError(qclass),
             ))));
         }
 
-        Ok((i, Self { name, qtype }))
+        Ok((
+            i,
+            Self {
+                name,
+                qtype,
+                qclass,
+            },
+        ))
     }
 
     pub fn serialize<'a, W: io::Write + 'a>(&'a self) -> impl cf::SerializeFn<W> + 'a {
-        use cf::{bytes::be_u16, sequence::tuple};
+        use cf::sequence::tuple;
 
-        tuple((self.name.serialize(), self.qtype.serialize(), be_u16(1)))
+        tuple((
+            self.name.serialize(),
+            self.qtype.serialize(),
+            self.qclass.serialize(),
+        ))
     }
 }
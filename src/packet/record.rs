@@ -1,24 +1,37 @@
+use std::fmt;
 use std::net::{Ipv4Addr, Ipv6Addr};
 
 use nom::{
     bytes::complete::take,
-    number::complete::{be_u16, be_u32},
+    combinator::{all_consuming, map, rest, verify},
+    multi::{length_data, many0},
+    number::complete::{be_u128, be_u16, be_u32, be_u8},
     sequence::tuple,
 };
 
 use super::{
     byte_buffer::ByteBuffer,
-    parse::{Input, ParseResult},
-    qname::Qname,
+    edns::{EdnsOption, DNSSEC_OK},
+    parse::{Input, ParseError, ParseResult},
+    qname::{Qname, ROOT},
+    query_class::QClass,
     query_type::QueryType,
+    serial::SoaSerial,
 };
 
-#[derive(Debug)]
+/// SvcParams of SVCB and HTTPS records as key and value pairs.
+pub type SvcParams = Vec<(u16, Vec<u8>)>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DnsRecord {
+    /// Record of a type which isn't modeled, kept as raw RDATA so it can be
+    /// relayed as is. Records outside of the Internet class always end up
+    /// here, the other variants are implicitly in class IN.
     Unknown {
         domain: Qname,
         qtype: QueryType,
-        data_len: u16,
+        class: QClass,
+        rdata: Vec<u8>,
         ttl: u32,
     },
     A {
@@ -36,6 +49,12 @@ pub enum DnsRecord {
         host: Qname,
         ttl: u32,
     },
+    /// Alias for all names below `domain`, but not `domain` itself.
+    Dname {
+        domain: Qname,
+        target: Qname,
+        ttl: u32,
+    },
     Soa {
         domain: Qname,
         ttl: u32,
@@ -47,6 +66,22 @@ pub enum DnsRecord {
         expire: u32,
         min_ttl: u32,
     },
+    /// Well known services of `protocol` at `address`, with one bit per port
+    /// in `bitmap`.
+    Wks {
+        domain: Qname,
+        address: Ipv4Addr,
+        protocol: u8,
+        bitmap: Vec<u8>,
+        ttl: u32,
+    },
+    /// Host information, also used for minimal answers to ANY queries.
+    Hinfo {
+        domain: Qname,
+        cpu: String,
+        os: String,
+        ttl: u32,
+    },
     Mx {
         domain: Qname,
         priority: u16,
@@ -58,24 +93,120 @@ pub enum DnsRecord {
         addr: Ipv6Addr,
         ttl: u32,
     },
+    Txt {
+        domain: Qname,
+        strings: Vec<Vec<u8>>,
+        ttl: u32,
+    },
+    /// Certification authority authorization. The tag is normally one of
+    /// `issue`, `issuewild` or `iodef`, other well formed tags are kept as is.
+    Caa {
+        domain: Qname,
+        flags: u8,
+        tag: String,
+        value: String,
+        ttl: u32,
+    },
+    /// Service binding (RFC 9460), with the SvcParams kept raw in wire
+    /// order.
+    Svcb {
+        domain: Qname,
+        priority: u16,
+        target: Qname,
+        params: SvcParams,
+        ttl: u32,
+    },
+    /// Service binding for HTTPS, laid out like SVCB.
+    Https {
+        domain: Qname,
+        priority: u16,
+        target: Qname,
+        params: SvcParams,
+        ttl: u32,
+    },
+    /// Digest of a DNSKEY of the child zone, held by the parent (RFC 4034).
+    Ds {
+        domain: Qname,
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>,
+        ttl: u32,
+    },
+    /// Signature over the record set of `type_covered` (RFC 4034). Times
+    /// are seconds since the epoch, modulo 2^32.
+    Rrsig {
+        domain: Qname,
+        type_covered: QueryType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer: Qname,
+        signature: Vec<u8>,
+        ttl: u32,
+    },
+    /// Next name of the zone in canonical order, with the types present at
+    /// `domain` kept in their wire form (RFC 4034).
+    Nsec {
+        domain: Qname,
+        next: Qname,
+        type_bitmaps: Vec<u8>,
+        ttl: u32,
+    },
+    /// Public key of a zone, used to check its RRSIG records (RFC 4034).
+    Dnskey {
+        domain: Qname,
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>,
+        ttl: u32,
+    },
+    /// EDNS pseudo-record, its owner is always the root.
+    Opt {
+        udp_payload_size: u16,
+        extended_rcode: u8,
+        version: u8,
+        flags: u16,
+        options: Vec<EdnsOption>,
+    },
 }
 
 impl DnsRecord {
     pub fn parse<'a>(i: Input<'a>, buf: &'a ByteBuffer) -> ParseResult<'a, Self> {
-        let (i, (domain, qtype, _, ttl, data_len)) =
-            tuple((buf.read_qname(), QueryType::parse, be_u16, be_u32, be_u16))(i)?;
+        let (i, (domain, qtype, class, ttl, data_len)) = tuple((
+            buf.read_qname(),
+            QueryType::parse,
+            QClass::parse,
+            be_u32,
+            be_u16,
+        ))(i)?;
+
+        // RDATA of other classes isn't necessarily laid out like in IN
+        if class != QClass::In && qtype != QueryType::Opt {
+            let (i, rdata) = take(data_len)(i)?;
+            return Ok((
+                i,
+                DnsRecord::Unknown {
+                    domain,
+                    qtype,
+                    class,
+                    rdata: rdata.to_vec(),
+                    ttl,
+                },
+            ));
+        }
 
         match qtype {
             QueryType::A => {
-                let (i, raw_addr) = take(4usize)(i)?;
-                let addr = Ipv4Addr::from(<&[u8] as TryInto<[u8; 4]>>::try_into(raw_addr).unwrap());
-
+                let (i, addr) = map(be_u32, Ipv4Addr::from)(i)?;
                 Ok((i, DnsRecord::A { domain, addr, ttl }))
             }
             QueryType::Aaaa => {
-                let (i, raw_addr) = take(16usize)(i)?;
-                let addr =
-                    Ipv6Addr::from(<&[u8] as TryInto<[u8; 16]>>::try_into(raw_addr).unwrap());
+                let (i, addr) = map(be_u128, Ipv6Addr::from)(i)?;
                 Ok((i, DnsRecord::Aaaa { domain, addr, ttl }))
             }
             QueryType::Ns => {
@@ -86,6 +217,20 @@ impl DnsRecord {
                 let (i, host) = buf.read_qname()(i)?;
                 Ok((i, DnsRecord::Cname { domain, host, ttl }))
             }
+            QueryType::Dname => {
+                // Never compressed (RFC 6672, section 2.5)
+                let (i, rdata) = take(data_len)(i)?;
+                let (target, _) = Qname::from_wire(rdata)
+                    .map_err(|e| nom::Err::Failure(ParseError::Qname((rdata, e))))?;
+                Ok((
+                    i,
+                    DnsRecord::Dname {
+                        domain,
+                        target,
+                        ttl,
+                    },
+                ))
+            }
             QueryType::Soa => {
                 let (i, (primary_ns, email, serial, refresh, retry, expire, min_ttl)) =
                     tuple((
@@ -112,6 +257,38 @@ impl DnsRecord {
                     },
                 ))
             }
+            QueryType::Wks => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (bitmap, (address, protocol)) =
+                    tuple((map(be_u32, Ipv4Addr::from), be_u8))(rdata)?;
+                Ok((
+                    i,
+                    DnsRecord::Wks {
+                        domain,
+                        address,
+                        protocol,
+                        bitmap: bitmap.to_vec(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Hinfo => {
+                let (i, rdata) = take(data_len)(i)?;
+                let utf8 = |x: &[u8]| std::str::from_utf8(x).is_ok();
+                let (_, (cpu, os)) = all_consuming(tuple((
+                    verify(length_data(be_u8), utf8),
+                    verify(length_data(be_u8), utf8),
+                )))(rdata)?;
+                Ok((
+                    i,
+                    DnsRecord::Hinfo {
+                        domain,
+                        cpu: String::from_utf8_lossy(cpu).into_owned(),
+                        os: String::from_utf8_lossy(os).into_owned(),
+                        ttl,
+                    },
+                ))
+            }
             QueryType::Mx => {
                 let (i, (priority, host)) = tuple((be_u16, buf.read_qname()))(i)?;
                 Ok((
@@ -124,14 +301,166 @@ impl DnsRecord {
                     },
                 ))
             }
-            QueryType::Unknown(_) => {
-                let (i, _) = take(data_len)(i)?;
+            QueryType::Txt => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (_, strings) = all_consuming(many0(length_data(be_u8)))(rdata)?;
+                Ok((
+                    i,
+                    DnsRecord::Txt {
+                        domain,
+                        strings: strings.into_iter().map(<[u8]>::to_vec).collect(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Caa => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (_, (flags, tag, value)) = tuple((
+                    be_u8,
+                    verify(length_data(be_u8), |tag: &[u8]| {
+                        !tag.is_empty() && tag.iter().all(u8::is_ascii_alphanumeric)
+                    }),
+                    verify(rest, |value: &[u8]| std::str::from_utf8(value).is_ok()),
+                ))(rdata)?;
+                Ok((
+                    i,
+                    DnsRecord::Caa {
+                        domain,
+                        flags,
+                        tag: String::from_utf8_lossy(tag).into_owned(),
+                        value: String::from_utf8_lossy(value).into_owned(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Svcb | QueryType::Https => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (_, (priority, target, params)) = parse_svcb_rdata(rdata)?;
+                let record = if qtype == QueryType::Svcb {
+                    DnsRecord::Svcb {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    }
+                } else {
+                    DnsRecord::Https {
+                        domain,
+                        priority,
+                        target,
+                        params,
+                        ttl,
+                    }
+                };
+                Ok((i, record))
+            }
+            QueryType::Ds => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (_, (key_tag, algorithm, digest_type, digest)) =
+                    tuple((be_u16, be_u8, be_u8, rest))(rdata)?;
+                Ok((
+                    i,
+                    DnsRecord::Ds {
+                        domain,
+                        key_tag,
+                        algorithm,
+                        digest_type,
+                        digest: digest.to_vec(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Rrsig => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (
+                    rdata,
+                    (type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag),
+                ) = tuple((
+                    QueryType::parse,
+                    be_u8,
+                    be_u8,
+                    be_u32,
+                    be_u32,
+                    be_u32,
+                    be_u16,
+                ))(rdata)?;
+                // Never compressed (RFC 4034, section 3.1.7)
+                let (signer, len) = Qname::from_wire(rdata)
+                    .map_err(|e| nom::Err::Failure(ParseError::Qname((rdata, e))))?;
+                Ok((
+                    i,
+                    DnsRecord::Rrsig {
+                        domain,
+                        type_covered,
+                        algorithm,
+                        labels,
+                        original_ttl,
+                        expiration,
+                        inception,
+                        key_tag,
+                        signer,
+                        signature: rdata[len..].to_vec(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Nsec => {
+                let (i, rdata) = take(data_len)(i)?;
+                // Never compressed (RFC 4034, section 4.1.1)
+                let (next, len) = Qname::from_wire(rdata)
+                    .map_err(|e| nom::Err::Failure(ParseError::Qname((rdata, e))))?;
+                Ok((
+                    i,
+                    DnsRecord::Nsec {
+                        domain,
+                        next,
+                        type_bitmaps: rdata[len..].to_vec(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Dnskey => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (_, (flags, protocol, algorithm, public_key)) =
+                    tuple((be_u16, be_u8, be_u8, rest))(rdata)?;
+                Ok((
+                    i,
+                    DnsRecord::Dnskey {
+                        domain,
+                        flags,
+                        protocol,
+                        algorithm,
+                        public_key: public_key.to_vec(),
+                        ttl,
+                    },
+                ))
+            }
+            QueryType::Opt => {
+                let (i, rdata) = take(data_len)(i)?;
+                let (_, options) = all_consuming(many0(EdnsOption::parse))(rdata)?;
+                Ok((
+                    i,
+                    // Class and TTL fields are repurposed by EDNS
+                    DnsRecord::Opt {
+                        udp_payload_size: class.into(),
+                        extended_rcode: (ttl >> 24) as u8,
+                        version: (ttl >> 16) as u8,
+                        flags: ttl as u16,
+                        options,
+                    },
+                ))
+            }
+            // ANY only exists in questions, such a record is kept as is
+            QueryType::Unknown(_) | QueryType::Any | QueryType::Tsig => {
+                let (i, rdata) = take(data_len)(i)?;
                 Ok((
                     i,
                     DnsRecord::Unknown {
                         domain,
                         qtype,
-                        data_len,
+                        class,
+                        rdata: rdata.to_vec(),
                         ttl,
                     },
                 ))
@@ -139,12 +468,178 @@ impl DnsRecord {
         }
     }
 
+    /// Serial of a SOA record, `None` for any other record.
+    pub fn soa_serial(&self) -> Option<SoaSerial> {
+        match self {
+            DnsRecord::Soa { serial, .. } => Some(SoaSerial(*serial)),
+            _ => None,
+        }
+    }
+
+    /// Owner name of the record.
+    pub fn domain(&self) -> &Qname {
+        match self {
+            DnsRecord::Unknown { domain, .. }
+            | DnsRecord::A { domain, .. }
+            | DnsRecord::Aaaa { domain, .. }
+            | DnsRecord::Ns { domain, .. }
+            | DnsRecord::Cname { domain, .. }
+            | DnsRecord::Dname { domain, .. }
+            | DnsRecord::Soa { domain, .. }
+            | DnsRecord::Wks { domain, .. }
+            | DnsRecord::Hinfo { domain, .. }
+            | DnsRecord::Mx { domain, .. }
+            | DnsRecord::Txt { domain, .. }
+            | DnsRecord::Caa { domain, .. }
+            | DnsRecord::Svcb { domain, .. }
+            | DnsRecord::Https { domain, .. }
+            | DnsRecord::Ds { domain, .. }
+            | DnsRecord::Rrsig { domain, .. }
+            | DnsRecord::Nsec { domain, .. }
+            | DnsRecord::Dnskey { domain, .. } => domain,
+            DnsRecord::Opt { .. } => &ROOT,
+        }
+    }
+
+    /// Time to live of the record. OPT has none, its TTL field carries EDNS
+    /// flags instead, so it is reported as 0.
+    pub fn ttl(&self) -> u32 {
+        match self {
+            DnsRecord::Unknown { ttl, .. }
+            | DnsRecord::A { ttl, .. }
+            | DnsRecord::Aaaa { ttl, .. }
+            | DnsRecord::Ns { ttl, .. }
+            | DnsRecord::Cname { ttl, .. }
+            | DnsRecord::Dname { ttl, .. }
+            | DnsRecord::Soa { ttl, .. }
+            | DnsRecord::Wks { ttl, .. }
+            | DnsRecord::Hinfo { ttl, .. }
+            | DnsRecord::Mx { ttl, .. }
+            | DnsRecord::Txt { ttl, .. }
+            | DnsRecord::Caa { ttl, .. }
+            | DnsRecord::Svcb { ttl, .. }
+            | DnsRecord::Https { ttl, .. }
+            | DnsRecord::Ds { ttl, .. }
+            | DnsRecord::Rrsig { ttl, .. }
+            | DnsRecord::Nsec { ttl, .. }
+            | DnsRecord::Dnskey { ttl, .. } => *ttl,
+            DnsRecord::Opt { .. } => 0,
+        }
+    }
+
+    /// Copy of the record with its time to live replaced by `ttl`. OPT is
+    /// copied as is, its TTL field holds EDNS flags.
+    pub fn with_ttl(&self, ttl: u32) -> DnsRecord {
+        let mut record = self.clone();
+        match &mut record {
+            DnsRecord::Unknown { ttl: old, .. }
+            | DnsRecord::A { ttl: old, .. }
+            | DnsRecord::Aaaa { ttl: old, .. }
+            | DnsRecord::Ns { ttl: old, .. }
+            | DnsRecord::Cname { ttl: old, .. }
+            | DnsRecord::Dname { ttl: old, .. }
+            | DnsRecord::Soa { ttl: old, .. }
+            | DnsRecord::Wks { ttl: old, .. }
+            | DnsRecord::Hinfo { ttl: old, .. }
+            | DnsRecord::Mx { ttl: old, .. }
+            | DnsRecord::Txt { ttl: old, .. }
+            | DnsRecord::Caa { ttl: old, .. }
+            | DnsRecord::Svcb { ttl: old, .. }
+            | DnsRecord::Https { ttl: old, .. }
+            | DnsRecord::Ds { ttl: old, .. }
+            | DnsRecord::Rrsig { ttl: old, .. }
+            | DnsRecord::Nsec { ttl: old, .. }
+            | DnsRecord::Dnskey { ttl: old, .. } => *old = ttl,
+            DnsRecord::Opt { .. } => {}
+        }
+        record
+    }
+
+    pub fn qtype(&self) -> QueryType {
+        match self {
+            DnsRecord::Unknown { qtype, .. } => *qtype,
+            DnsRecord::A { .. } => QueryType::A,
+            DnsRecord::Aaaa { .. } => QueryType::Aaaa,
+            DnsRecord::Ns { .. } => QueryType::Ns,
+            DnsRecord::Cname { .. } => QueryType::Cname,
+            DnsRecord::Dname { .. } => QueryType::Dname,
+            DnsRecord::Soa { .. } => QueryType::Soa,
+            DnsRecord::Wks { .. } => QueryType::Wks,
+            DnsRecord::Hinfo { .. } => QueryType::Hinfo,
+            DnsRecord::Mx { .. } => QueryType::Mx,
+            DnsRecord::Txt { .. } => QueryType::Txt,
+            DnsRecord::Caa { .. } => QueryType::Caa,
+            DnsRecord::Svcb { .. } => QueryType::Svcb,
+            DnsRecord::Https { .. } => QueryType::Https,
+            DnsRecord::Ds { .. } => QueryType::Ds,
+            DnsRecord::Rrsig { .. } => QueryType::Rrsig,
+            DnsRecord::Nsec { .. } => QueryType::Nsec,
+            DnsRecord::Dnskey { .. } => QueryType::Dnskey,
+            DnsRecord::Opt { .. } => QueryType::Opt,
+        }
+    }
+
+    /// Class of the record. OPT has none, its class field carries the UDP
+    /// payload size instead, which is reported as is.
+    pub fn class(&self) -> QClass {
+        match self {
+            DnsRecord::Unknown { class, .. } => *class,
+            DnsRecord::Opt {
+                udp_payload_size, ..
+            } => QClass::from(*udp_payload_size),
+            _ => QClass::In,
+        }
+    }
+
+    /// Size of the record on the wire: owner name, type, class, TTL,
+    /// RDLENGTH and RDATA.
+    pub fn serialized_size(&self) -> u16 {
+        self.domain().serialized_size() + 10 + self.rdata_size()
+    }
+
+    /// Value of the RDLENGTH field.
+    fn rdata_size(&self) -> u16 {
+        match self {
+            DnsRecord::Unknown { rdata, .. } => rdata.len() as u16,
+            DnsRecord::A { .. } => 4,
+            DnsRecord::Aaaa { .. } => 16,
+            DnsRecord::Ns { host, .. } | DnsRecord::Cname { host, .. } => host.serialized_size(),
+            DnsRecord::Dname { target, .. } => target.serialized_size(),
+            DnsRecord::Soa {
+                primary_ns, email, ..
+            } => primary_ns.serialized_size() + email.serialized_size() + 5 * 4,
+            DnsRecord::Wks { bitmap, .. } => 5 + bitmap.len() as u16,
+            DnsRecord::Hinfo { cpu, os, .. } => 1 + cpu.len() as u16 + 1 + os.len() as u16,
+            DnsRecord::Mx { host, .. } => host.serialized_size() + 2,
+            DnsRecord::Txt { strings, .. } => strings
+                .iter()
+                .flat_map(|x| char_strings(x))
+                .map(|x| 1 + x.len() as u16)
+                .sum(),
+            DnsRecord::Caa { tag, value, .. } => 1 + 1 + tag.len() as u16 + value.len() as u16,
+            DnsRecord::Svcb { target, params, .. } | DnsRecord::Https { target, params, .. } => {
+                let params: u16 = params.iter().map(|(_, x)| 4 + x.len() as u16).sum();
+                2 + target.serialized_size() + params
+            }
+            DnsRecord::Ds { digest, .. } => 4 + digest.len() as u16,
+            DnsRecord::Rrsig {
+                signer, signature, ..
+            } => 18 + signer.serialized_size() + signature.len() as u16,
+            DnsRecord::Nsec {
+                next, type_bitmaps, ..
+            } => next.serialized_size() + type_bitmaps.len() as u16,
+            DnsRecord::Dnskey { public_key, .. } => 4 + public_key.len() as u16,
+            DnsRecord::Opt { options, .. } => options.iter().map(|x| x.serialized_size()).sum(),
+        }
+    }
+
     pub fn serialize<'a, W: std::io::Write + 'a>(
         &'a self,
     ) -> Box<dyn cookie_factory::SerializeFn<W> + 'a> {
         use cookie_factory::{
-            bytes::{be_u16, be_u32},
+            bytes::{be_u16, be_u32, be_u8},
             combinator::slice,
+            multi::all,
             sequence::tuple,
         };
 
@@ -158,7 +653,7 @@ impl DnsRecord {
                 QueryType::A.serialize(),
                 be_u16(1),
                 be_u32(ttl),
-                be_u16(4),
+                be_u16(self.rdata_size()),
                 slice(addr.octets()),
             ))),
             DnsRecord::Aaaa {
@@ -170,7 +665,7 @@ impl DnsRecord {
                 QueryType::Aaaa.serialize(),
                 be_u16(1),
                 be_u32(ttl),
-                be_u16(16),
+                be_u16(self.rdata_size()),
                 slice(addr.octets()),
             ))),
             DnsRecord::Ns {
@@ -182,7 +677,7 @@ impl DnsRecord {
                 QueryType::Ns.serialize(),
                 be_u16(1),
                 be_u32(ttl),
-                be_u16(host.serialized_size()),
+                be_u16(self.rdata_size()),
                 host.serialize(),
             ))),
             DnsRecord::Cname {
@@ -194,9 +689,21 @@ impl DnsRecord {
                 QueryType::Cname.serialize(),
                 be_u16(1),
                 be_u32(ttl),
-                be_u16(host.serialized_size()),
+                be_u16(self.rdata_size()),
                 host.serialize(),
             ))),
+            DnsRecord::Dname {
+                ref domain,
+                ref target,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Dname.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                target.serialize(),
+            ))),
             DnsRecord::Soa {
                 ref domain,
                 ttl,
@@ -212,7 +719,7 @@ impl DnsRecord {
                 QueryType::Soa.serialize(),
                 be_u16(1),
                 be_u32(ttl),
-                be_u16(primary_ns.serialized_size() + email.serialized_size() + 5 * 4),
+                be_u16(self.rdata_size()),
                 primary_ns.serialize(),
                 email.serialize(),
                 be_u32(serial),
@@ -221,6 +728,38 @@ impl DnsRecord {
                 be_u32(expire),
                 be_u32(min_ttl),
             ))),
+            DnsRecord::Wks {
+                ref domain,
+                address,
+                protocol,
+                ref bitmap,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Wks.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                slice(address.octets()),
+                be_u8(protocol),
+                slice(bitmap),
+            ))),
+            DnsRecord::Hinfo {
+                ref domain,
+                ref cpu,
+                ref os,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Hinfo.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                be_u8(cpu.len() as u8),
+                slice(cpu),
+                be_u8(os.len() as u8),
+                slice(os),
+            ))),
             DnsRecord::Mx {
                 ref domain,
                 priority,
@@ -231,14 +770,744 @@ impl DnsRecord {
                 QueryType::Mx.serialize(),
                 be_u16(1),
                 be_u32(ttl),
-                be_u16(host.serialized_size() + 2),
+                be_u16(self.rdata_size()),
                 be_u16(priority),
                 host.serialize(),
             ))),
-            DnsRecord::Unknown { .. } => {
-                println!("Skipping record serialization: {:?}", self);
-                Box::new(Ok)
+            DnsRecord::Txt {
+                ref domain,
+                ref strings,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Txt.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                all(strings
+                    .iter()
+                    .flat_map(|x| char_strings(x))
+                    .map(|x| tuple((be_u8(x.len() as u8), slice(x))))),
+            ))),
+            DnsRecord::Caa {
+                ref domain,
+                flags,
+                ref tag,
+                ref value,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Caa.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                be_u8(flags),
+                be_u8(tag.len() as u8),
+                slice(tag),
+                slice(value),
+            ))),
+            DnsRecord::Svcb {
+                ref domain,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            }
+            | DnsRecord::Https {
+                ref domain,
+                priority,
+                ref target,
+                ref params,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                be_u16(self.qtype().into()),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                be_u16(priority),
+                // Never compressed, RFC 9460 forbids it inside RDATA
+                target.serialize(),
+                all(params.iter().map(|(key, value)| {
+                    tuple((be_u16(*key), be_u16(value.len() as u16), slice(value)))
+                })),
+            ))),
+            DnsRecord::Ds {
+                ref domain,
+                key_tag,
+                algorithm,
+                digest_type,
+                ref digest,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Ds.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                be_u16(key_tag),
+                be_u8(algorithm),
+                be_u8(digest_type),
+                slice(digest),
+            ))),
+            DnsRecord::Rrsig {
+                ref domain,
+                ref type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                ref signer,
+                ref signature,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Rrsig.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                type_covered.serialize(),
+                be_u8(algorithm),
+                be_u8(labels),
+                be_u32(original_ttl),
+                be_u32(expiration),
+                be_u32(inception),
+                be_u16(key_tag),
+                signer.serialize(),
+                slice(signature),
+            ))),
+            DnsRecord::Nsec {
+                ref domain,
+                ref next,
+                ref type_bitmaps,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Nsec.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                next.serialize(),
+                slice(type_bitmaps),
+            ))),
+            DnsRecord::Dnskey {
+                ref domain,
+                flags,
+                protocol,
+                algorithm,
+                ref public_key,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                QueryType::Dnskey.serialize(),
+                be_u16(1),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                be_u16(flags),
+                be_u8(protocol),
+                be_u8(algorithm),
+                slice(public_key),
+            ))),
+            DnsRecord::Opt {
+                udp_payload_size,
+                extended_rcode,
+                version,
+                flags,
+                ref options,
+            } => Box::new(tuple((
+                be_u8(0),
+                QueryType::Opt.serialize(),
+                be_u16(udp_payload_size),
+                be_u8(extended_rcode),
+                be_u8(version),
+                be_u16(flags),
+                be_u16(self.rdata_size()),
+                all(options.iter().map(|x| x.serialize())),
+            ))),
+            DnsRecord::Unknown {
+                ref domain,
+                ref qtype,
+                ref class,
+                ref rdata,
+                ttl,
+            } => Box::new(tuple((
+                domain.serialize(),
+                qtype.serialize(),
+                class.serialize(),
+                be_u32(ttl),
+                be_u16(self.rdata_size()),
+                slice(rdata),
+            ))),
+        }
+    }
+}
+
+/// Zone file line as dig prints it: owner, TTL, class, type and data.
+/// Binary data is shown in hex, the data of records of unknown types in the
+/// generic form of RFC 3597. OPT has no zone file form and is shown like the
+/// pseudosection of dig instead.
+impl fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let DnsRecord::Opt {
+            udp_payload_size,
+            version,
+            flags,
+            options,
+            ..
+        } = self
+        {
+            write!(f, "; EDNS: version: {version}, flags:")?;
+            if flags & DNSSEC_OK != 0 {
+                f.write_str(" do")?;
+            }
+            write!(f, "; udp: {udp_payload_size}")?;
+            for option in options {
+                write!(f, "\n; {option}")?;
+            }
+            return Ok(());
+        }
+
+        write!(
+            f,
+            "{}\t{}\t{}\t{}\t",
+            self.domain().fqdn(),
+            self.ttl(),
+            self.class(),
+            self.qtype()
+        )?;
+        match self {
+            DnsRecord::Unknown { rdata, .. } => {
+                write!(f, "\\# {}", rdata.len())?;
+                if !rdata.is_empty() {
+                    f.write_str(" ")?;
+                    write_hex(f, rdata)?;
+                }
+                Ok(())
+            }
+            DnsRecord::A { addr, .. } => write!(f, "{addr}"),
+            DnsRecord::Aaaa { addr, .. } => write!(f, "{addr}"),
+            DnsRecord::Ns { host, .. } | DnsRecord::Cname { host, .. } => {
+                write!(f, "{}", host.fqdn())
+            }
+            DnsRecord::Dname { target, .. } => write!(f, "{}", target.fqdn()),
+            DnsRecord::Soa {
+                primary_ns,
+                email,
+                serial,
+                refresh,
+                retry,
+                expire,
+                min_ttl,
+                ..
+            } => write!(
+                f,
+                "{} {} {serial} {refresh} {retry} {expire} {min_ttl}",
+                primary_ns.fqdn(),
+                email.fqdn()
+            ),
+            DnsRecord::Wks {
+                address,
+                protocol,
+                bitmap,
+                ..
+            } => {
+                write!(f, "{address} {protocol}")?;
+                for port in bits_set(bitmap) {
+                    write!(f, " {port}")?;
+                }
+                Ok(())
+            }
+            DnsRecord::Hinfo { cpu, os, .. } => {
+                write_char_string(f, cpu.as_bytes())?;
+                f.write_str(" ")?;
+                write_char_string(f, os.as_bytes())
+            }
+            DnsRecord::Mx { priority, host, .. } => write!(f, "{priority} {}", host.fqdn()),
+            DnsRecord::Txt { strings, .. } => {
+                for (i, string) in strings.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write_char_string(f, string)?;
+                }
+                Ok(())
+            }
+            DnsRecord::Caa {
+                flags, tag, value, ..
+            } => {
+                write!(f, "{flags} {tag} ")?;
+                write_char_string(f, value.as_bytes())
+            }
+            DnsRecord::Svcb {
+                priority,
+                target,
+                params,
+                ..
+            }
+            | DnsRecord::Https {
+                priority,
+                target,
+                params,
+                ..
+            } => {
+                write!(f, "{priority} {}", target.fqdn())?;
+                for (key, value) in params {
+                    write!(f, " key{key}=")?;
+                    write_char_string(f, value)?;
+                }
+                Ok(())
+            }
+            DnsRecord::Ds {
+                key_tag,
+                algorithm,
+                digest_type,
+                digest,
+                ..
+            } => {
+                write!(f, "{key_tag} {algorithm} {digest_type} ")?;
+                write_hex(f, digest)
+            }
+            DnsRecord::Rrsig {
+                type_covered,
+                algorithm,
+                labels,
+                original_ttl,
+                expiration,
+                inception,
+                key_tag,
+                signer,
+                signature,
+                ..
+            } => {
+                write!(
+                    f,
+                    "{type_covered} {algorithm} {labels} {original_ttl} {expiration} \
+                     {inception} {key_tag} {} ",
+                    signer.fqdn()
+                )?;
+                write_hex(f, signature)
+            }
+            DnsRecord::Nsec {
+                next, type_bitmaps, ..
+            } => {
+                write!(f, "{}", next.fqdn())?;
+                for qtype in nsec_types(type_bitmaps) {
+                    write!(f, " {qtype}")?;
+                }
+                Ok(())
+            }
+            DnsRecord::Dnskey {
+                flags,
+                protocol,
+                algorithm,
+                public_key,
+                ..
+            } => {
+                write!(f, "{flags} {protocol} {algorithm} ")?;
+                write_hex(f, public_key)
+            }
+            DnsRecord::Opt { .. } => unreachable!("OPT is shown above"),
+        }
+    }
+}
+
+pub(crate) fn write_hex(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    bytes.iter().try_for_each(|byte| write!(f, "{byte:02X}"))
+}
+
+/// Quoted character string, with quotes, backslashes and anything
+/// unprintable escaped.
+fn write_char_string(f: &mut fmt::Formatter<'_>, bytes: &[u8]) -> fmt::Result {
+    f.write_str("\"")?;
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' => write!(f, "\\{}", byte as char)?,
+            0x20..=0x7e => write!(f, "{}", byte as char)?,
+            _ => write!(f, "\\{byte:03}")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+/// `bytes` split into character strings of at most 255 bytes, the most
+/// their length octet can tell. Empty input is a single empty string.
+fn char_strings(bytes: &[u8]) -> impl Iterator<Item = &[u8]> + Clone {
+    let empty = bytes.is_empty().then_some(bytes);
+    bytes.chunks(255).chain(empty)
+}
+
+/// RDATA of a TXT record holding `text`, split into character strings the
+/// same way as serializing a [`DnsRecord::Txt`] does. For records of other
+/// classes than IN, which only fit into [`DnsRecord::Unknown`].
+pub(crate) fn txt_rdata(text: &[u8]) -> Vec<u8> {
+    let mut rdata = Vec::new();
+    for x in char_strings(text) {
+        rdata.push(x.len() as u8);
+        rdata.extend_from_slice(x);
+    }
+    rdata
+}
+
+/// Positions of the bits set in `bitmap`, most significant bit first.
+fn bits_set(bitmap: &[u8]) -> impl Iterator<Item = usize> + '_ {
+    bitmap.iter().enumerate().flat_map(|(i, byte)| {
+        (0..8)
+            .filter(move |bit| byte & (0x80 >> bit) != 0)
+            .map(move |bit| i * 8 + bit)
+    })
+}
+
+/// Types in the window blocks of NSEC type bitmaps (RFC 4034, section
+/// 4.1.2). A malformed block ends the list.
+fn nsec_types(mut bitmaps: &[u8]) -> Vec<QueryType> {
+    let mut types = Vec::new();
+    while let [window, len, rest @ ..] = bitmaps {
+        let Some(bitmap) = rest.get(..usize::from(*len)) else {
+            break;
+        };
+        types.extend(
+            bits_set(bitmap).map(|bit| QueryType::from(u16::from(*window) << 8 | bit as u16)),
+        );
+        bitmaps = &rest[bitmap.len()..];
+    }
+    types
+}
+
+/// Priority, target and SvcParams of SVCB and HTTPS records. The target
+/// must not be compressed (RFC 9460, section 2.2), so it's read in place
+/// instead of through the message buffer.
+fn parse_svcb_rdata(rdata: Input) -> ParseResult<(u16, Qname, SvcParams)> {
+    let (i, priority) = be_u16(rdata)?;
+    let (target, len) =
+        Qname::from_wire(i).map_err(|e| nom::Err::Failure(ParseError::Qname((i, e))))?;
+    let (i, params) = all_consuming(many0(tuple((be_u16, length_data(be_u16)))))(&i[len..])?;
+    let params = params
+        .into_iter()
+        .map(|(key, value)| (key, value.to_vec()))
+        .collect();
+    Ok((i, (priority, target, params)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use cookie_factory as cf;
+
+    use super::DnsRecord;
+    use crate::packet::{
+        byte_buffer::ByteBuffer,
+        edns::{EdnsOption, EDE_OTHER_ERROR},
+        qname::Qname,
+        query_class::QClass,
+        query_type::QueryType,
+        serial::SoaSerial,
+    };
+
+    fn qname(name: &str) -> Qname {
+        Qname::try_from(name).unwrap()
+    }
+
+    fn sample_records() -> Vec<DnsRecord> {
+        vec![
+            DnsRecord::Unknown {
+                domain: qname("example.com"),
+                qtype: QueryType::Unknown(99),
+                class: QClass::In,
+                rdata: b"\x0bhello world".to_vec(),
+                ttl: 300,
+            },
+            DnsRecord::A {
+                domain: qname("example.com"),
+                addr: Ipv4Addr::new(93, 184, 216, 34),
+                ttl: 300,
+            },
+            DnsRecord::Ns {
+                domain: qname("example.com"),
+                host: qname("a.iana-servers.net"),
+                ttl: 300,
+            },
+            DnsRecord::Cname {
+                domain: qname("www.example.com"),
+                host: qname("example.com"),
+                ttl: 300,
+            },
+            DnsRecord::Dname {
+                domain: qname("example.com"),
+                target: qname("example.net"),
+                ttl: 300,
+            },
+            DnsRecord::Soa {
+                domain: qname("example.com"),
+                ttl: 300,
+                primary_ns: qname("ns.icann.org"),
+                email: qname("noc.dns.icann.org"),
+                serial: 2022091303,
+                refresh: 7200,
+                retry: 3600,
+                expire: 1209600,
+                min_ttl: 3600,
+            },
+            DnsRecord::Wks {
+                domain: qname("example.com"),
+                address: Ipv4Addr::new(93, 184, 216, 34),
+                protocol: 6,
+                bitmap: vec![0x00, 0x00, 0x00, 0x40],
+                ttl: 300,
+            },
+            DnsRecord::Hinfo {
+                domain: qname("example.com"),
+                cpu: "Intel".into(),
+                os: "Linux".into(),
+                ttl: 300,
+            },
+            DnsRecord::Mx {
+                domain: qname("example.com"),
+                priority: 10,
+                host: qname("mail.example.com"),
+                ttl: 300,
+            },
+            DnsRecord::Aaaa {
+                domain: qname("example.com"),
+                addr: Ipv6Addr::new(0x2606, 0x2800, 0x220, 1, 0x248, 0x1893, 0x25c8, 0x1946),
+                ttl: 300,
+            },
+            DnsRecord::Txt {
+                domain: qname("example.com"),
+                strings: vec![b"v=spf1 -all".to_vec(), Vec::new()],
+                ttl: 300,
+            },
+            DnsRecord::Caa {
+                domain: qname("example.com"),
+                flags: 0,
+                tag: "issue".into(),
+                value: "letsencrypt.org".into(),
+                ttl: 300,
+            },
+            DnsRecord::Https {
+                domain: qname("example.com"),
+                priority: 1,
+                target: qname("."),
+                params: vec![(1, b"\x02h2".to_vec()), (3, vec![0x01, 0xbb])],
+                ttl: 300,
+            },
+            DnsRecord::Ds {
+                domain: qname("example.com"),
+                key_tag: 370,
+                algorithm: 13,
+                digest_type: 2,
+                digest: vec![0xbe; 32],
+                ttl: 300,
+            },
+            DnsRecord::Rrsig {
+                domain: qname("example.com"),
+                type_covered: QueryType::Ds,
+                algorithm: 13,
+                labels: 2,
+                original_ttl: 86400,
+                expiration: 1_700_604_800,
+                inception: 1_700_000_000,
+                key_tag: 4534,
+                signer: qname("com"),
+                signature: vec![0xef; 64],
+                ttl: 300,
+            },
+            DnsRecord::Nsec {
+                domain: qname("example.com"),
+                next: qname("www.example.com"),
+                type_bitmaps: vec![0x00, 0x01, 0x62],
+                ttl: 300,
+            },
+            DnsRecord::Dnskey {
+                domain: qname("example.com"),
+                flags: 257,
+                protocol: 3,
+                algorithm: 13,
+                public_key: vec![0xab; 64],
+                ttl: 300,
+            },
+            DnsRecord::Opt {
+                udp_payload_size: 1232,
+                extended_rcode: 0,
+                version: 0,
+                flags: 0,
+                options: vec![EdnsOption::ExtendedError {
+                    info_code: EDE_OTHER_ERROR,
+                    extra_text: "server overloaded".into(),
+                }],
+            },
+        ]
+    }
+
+    #[test]
+    fn serialized_size_matches_serialization() {
+        for record in sample_records() {
+            let serialized = cf::gen_simple(record.serialize(), Vec::new()).unwrap();
+            assert_eq!(
+                record.serialized_size() as usize,
+                serialized.len(),
+                "{record:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn overlong_txt_strings_are_split() {
+        let txt = DnsRecord::Txt {
+            domain: qname("example.com"),
+            strings: vec![vec![b'a'; 300], Vec::new()],
+            ttl: 60,
+        };
+
+        let serialized = cf::gen_simple(txt.serialize(), Vec::new()).unwrap();
+        assert_eq!(txt.serialized_size() as usize, serialized.len());
+        let buffer = ByteBuffer::new(&serialized);
+        let (_, parsed) = DnsRecord::parse(&serialized, &buffer).unwrap();
+        assert_eq!(
+            parsed,
+            DnsRecord::Txt {
+                domain: qname("example.com"),
+                strings: vec![vec![b'a'; 255], vec![b'a'; 45], Vec::new()],
+                ttl: 60,
             }
+        );
+    }
+
+    #[test]
+    fn opt_options_round_trip() {
+        let opt = DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: vec![
+                EdnsOption::Cookie {
+                    client: [7; 8],
+                    server: vec![9; 16],
+                },
+                EdnsOption::Unknown {
+                    code: 65001,
+                    data: b"vendor".to_vec(),
+                },
+                // Padding
+                EdnsOption::Unknown {
+                    code: 12,
+                    data: vec![0; 20],
+                },
+            ],
+        };
+
+        let serialized = cf::gen_simple(opt.serialize(), Vec::new()).unwrap();
+        let buffer = ByteBuffer::new(&serialized);
+        let (_, parsed) = DnsRecord::parse(&serialized, &buffer).unwrap();
+        assert_eq!(parsed, opt);
+        assert_eq!(
+            cf::gen_simple(parsed.serialize(), Vec::new()).unwrap(),
+            serialized
+        );
+    }
+
+    #[test]
+    fn malformed_known_options_are_kept_raw() {
+        let options = [
+            // Extended error without room for its info code
+            (15, vec![0]),
+            // Extended error text which isn't UTF-8
+            (15, vec![0, 0, 0xff]),
+            // Cookie cut short
+            (10, vec![7; 5]),
+        ];
+
+        for (code, data) in options {
+            let mut rdata = Vec::new();
+            rdata.extend_from_slice(&u16::to_be_bytes(code));
+            rdata.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            rdata.extend_from_slice(&data);
+
+            let (_, option) = EdnsOption::parse(&rdata).unwrap();
+            assert_eq!(option, EdnsOption::Unknown { code, data });
         }
     }
+
+    #[test]
+    fn records_are_displayed_in_zone_file_form() {
+        let records = sample_records();
+        assert_eq!(
+            records[0].to_string(),
+            "example.com.\t300\tIN\tTYPE99\t\\# 12 0B68656C6C6F20776F726C64"
+        );
+        assert_eq!(
+            records[1].to_string(),
+            "example.com.\t300\tIN\tA\t93.184.216.34"
+        );
+
+        let txt = DnsRecord::Txt {
+            domain: qname("example.com"),
+            strings: vec![b"say \"hi\"".to_vec(), vec![0, b'\\']],
+            ttl: 60,
+        };
+        assert_eq!(
+            txt.to_string(),
+            "example.com.\t60\tIN\tTXT\t\"say \\\"hi\\\"\" \"\\000\\\\\""
+        );
+
+        let opt = DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0x8000,
+            options: vec![EdnsOption::Cookie {
+                client: [7; 8],
+                server: Vec::new(),
+            }],
+        };
+        assert_eq!(
+            opt.to_string(),
+            "; EDNS: version: 0, flags: do; udp: 1232\n; COOKIE: 0707070707070707"
+        );
+    }
+
+    #[test]
+    fn common_field_accessors() {
+        let expected = [
+            ("example.com", 300, QueryType::Unknown(99), QClass::In),
+            ("example.com", 300, QueryType::A, QClass::In),
+            ("example.com", 300, QueryType::Ns, QClass::In),
+            ("www.example.com", 300, QueryType::Cname, QClass::In),
+            ("example.com", 300, QueryType::Dname, QClass::In),
+            ("example.com", 300, QueryType::Soa, QClass::In),
+            ("example.com", 300, QueryType::Wks, QClass::In),
+            ("example.com", 300, QueryType::Hinfo, QClass::In),
+            ("example.com", 300, QueryType::Mx, QClass::In),
+            ("example.com", 300, QueryType::Aaaa, QClass::In),
+            ("example.com", 300, QueryType::Txt, QClass::In),
+            ("example.com", 300, QueryType::Caa, QClass::In),
+            ("example.com", 300, QueryType::Https, QClass::In),
+            ("example.com", 300, QueryType::Ds, QClass::In),
+            ("example.com", 300, QueryType::Rrsig, QClass::In),
+            ("example.com", 300, QueryType::Nsec, QClass::In),
+            ("example.com", 300, QueryType::Dnskey, QClass::In),
+            (".", 0, QueryType::Opt, QClass::Unknown(1232)),
+        ];
+
+        let records = sample_records();
+        assert_eq!(records.len(), expected.len());
+        for (record, (domain, ttl, qtype, class)) in records.iter().zip(expected) {
+            assert_eq!(record.domain(), &qname(domain), "{record:?}");
+            assert_eq!(record.ttl(), ttl, "{record:?}");
+            assert_eq!(record.qtype(), qtype, "{record:?}");
+            assert_eq!(record.class(), class, "{record:?}");
+        }
+    }
+
+    #[test]
+    fn soa_serial_only_of_soa() {
+        let serials: Vec<_> = sample_records()
+            .iter()
+            .filter_map(DnsRecord::soa_serial)
+            .collect();
+        assert_eq!(serials, [SoaSerial(2022091303)]);
+    }
 }
@@ -0,0 +1,77 @@
+use std::cmp::Ordering;
+
+/// Largest increment of a serial number, and half of its sequence space.
+const HALF: u32 = 1 << 31;
+
+/// SOA serial number, compared with sequence space arithmetic (RFC 1982).
+///
+/// Serials wrap around at 2^32, so 1 follows 0xFFFFFFFF. Two serials are
+/// ordered by whichever way round is the shorter distance between them. At
+/// exactly half the space apart they are not comparable, `partial_cmp`
+/// returns `None` and every comparison operator is false.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SoaSerial(pub u32);
+
+impl SoaSerial {
+    /// Serial `n` ahead of this one, `None` if `n` exceeds the largest
+    /// increment of 2^31 - 1.
+    pub fn checked_add(self, n: u32) -> Option<SoaSerial> {
+        (n < HALF).then(|| SoaSerial(self.0.wrapping_add(n)))
+    }
+
+    /// Whether a zone with this serial is a newer version of one with
+    /// `other`.
+    pub fn is_newer_than(self, other: SoaSerial) -> bool {
+        self > other
+    }
+}
+
+impl PartialOrd for SoaSerial {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match self.0.wrapping_sub(other.0) {
+            0 => Some(Ordering::Equal),
+            HALF => None,
+            ahead if ahead < HALF => Some(Ordering::Greater),
+            _ => Some(Ordering::Less),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::SoaSerial;
+
+    #[test]
+    fn ordering_across_wraparound() {
+        // Examples of RFC 1982, section 5.2, with 32-bit serials
+        assert!(SoaSerial(1) > SoaSerial(0));
+        assert!(SoaSerial(0) > SoaSerial(0xFFFF_FFFF));
+        assert!(SoaSerial(0x7FFF_FFFF) > SoaSerial(0));
+        assert!(SoaSerial(0x8000_0001) < SoaSerial(0));
+        assert!(SoaSerial(5) > SoaSerial(0xFFFF_FFF0));
+        assert!(SoaSerial(0xFFFF_FFFF) < SoaSerial(0x7FFF_FFFE));
+        assert_eq!(
+            SoaSerial(0xFFFF_FFFF).partial_cmp(&SoaSerial(0xFFFF_FFFF)),
+            Some(Ordering::Equal)
+        );
+
+        // Half the space apart, neither is newer
+        assert_eq!(SoaSerial(0).partial_cmp(&SoaSerial(0x8000_0000)), None);
+        assert!(!SoaSerial(0).is_newer_than(SoaSerial(0x8000_0000)));
+        assert!(!SoaSerial(0x8000_0000).is_newer_than(SoaSerial(0)));
+    }
+
+    #[test]
+    fn addition_wraps() {
+        let serial = SoaSerial(0xFFFF_FFFE);
+        assert_eq!(serial.checked_add(3), Some(SoaSerial(1)));
+        assert!(serial.checked_add(3).unwrap().is_newer_than(serial));
+        assert_eq!(
+            serial.checked_add(0x7FFF_FFFF),
+            Some(SoaSerial(0x7FFF_FFFD))
+        );
+        assert_eq!(serial.checked_add(0x8000_0000), None);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::config::Config;
+use crate::packet::message::DnsMessage;
+use crate::packet::qname::Qname;
+use crate::packet::query_type::QueryType;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record::{txt_rdata, DnsRecord};
+use crate::packet::ResultCode;
+
+/// TTL of synthesized sinkhole records.
+const SINKHOLE_TTL: u32 = 60;
+
+/// TTL of the synthesized HINFO answer to ANY queries.
+const MINIMAL_ANY_TTL: u32 = 3600;
+
+/// Names of CHAOS TXT records describing the server itself, with the RFC 4892
+/// equivalents.
+const VERSION_NAMES: [&str; 2] = ["version.bind", "version.server"];
+const HOSTNAME_NAMES: [&str; 2] = ["hostname.bind", "id.server"];
+
+/// What a response resolving to a blocked address is replaced with.
+#[derive(Debug, Clone)]
+pub enum BlockAction {
+    NxDomain,
+    NoData,
+    /// Answer with these addresses instead, NODATA for other query types.
+    Sinkhole {
+        v4: Ipv4Addr,
+        v6: Ipv6Addr,
+    },
+}
+
+/// Rewrite the response to `question` if any of its A or AAAA answers is in
+/// one of the blocked networks. Returns whether the response was rewritten.
+pub fn filter_answers(config: &Config, question: &DnsQuestion, response: &mut DnsMessage) -> bool {
+    let blocked = response.answers.iter().any(|record| {
+        let addr = match record {
+            DnsRecord::A { addr, .. } => IpAddr::V4(*addr),
+            DnsRecord::Aaaa { addr, .. } => IpAddr::V6(*addr),
+            _ => return false,
+        };
+        config
+            .blocked_answer_nets
+            .iter()
+            .any(|net| net.contains(&addr))
+    });
+    if !blocked {
+        return false;
+    }
+
+    response.answers.clear();
+    response.authorities.clear();
+    response.resources.clear();
+
+    match config.blocked_answer_action {
+        BlockAction::NxDomain => response.header.flags.rescode = ResultCode::NxDomain,
+        BlockAction::NoData => response.header.flags.rescode = ResultCode::NoError,
+        BlockAction::Sinkhole { v4, v6 } => {
+            response.header.flags.rescode = ResultCode::NoError;
+
+            let domain = question.name.clone();
+            match question.qtype {
+                QueryType::A => response.answers.push(DnsRecord::A {
+                    domain,
+                    addr: v4,
+                    ttl: SINKHOLE_TTL,
+                }),
+                QueryType::Aaaa => response.answers.push(DnsRecord::Aaaa {
+                    domain,
+                    addr: v6,
+                    ttl: SINKHOLE_TTL,
+                }),
+                _ => {}
+            }
+        }
+    }
+
+    response.update_header();
+    true
+}
+
+/// Rotate every A and AAAA record set of `answers` by `offset` within the
+/// positions it takes, leaving all other records where they are.
+pub fn rotate_answers(answers: &mut [DnsRecord], offset: usize) {
+    let mut sets: HashMap<(Qname, QueryType), Vec<usize>> = HashMap::new();
+    for (i, record) in answers.iter().enumerate() {
+        if matches!(record, DnsRecord::A { .. } | DnsRecord::Aaaa { .. }) {
+            sets.entry((record.domain().clone(), record.qtype()))
+                .or_default()
+                .push(i);
+        }
+    }
+
+    for positions in sets.into_values() {
+        let mut records: Vec<_> = positions.iter().map(|&i| answers[i].clone()).collect();
+        records.rotate_left(offset % positions.len());
+        for (i, record) in positions.into_iter().zip(records) {
+            answers[i] = record;
+        }
+    }
+}
+
+/// Minimal answer to an ANY query as described in RFC 8482: a single HINFO
+/// record with CPU "RFC8482" and an empty OS.
+pub fn minimal_any_response(question: &DnsQuestion) -> DnsMessage {
+    let mut response = DnsMessage::new();
+    response.header.flags.response = true;
+    response.questions.push(question.clone());
+    response.answers.push(DnsRecord::Hinfo {
+        domain: question.name.clone(),
+        cpu: "RFC8482".into(),
+        os: String::new(),
+        ttl: MINIMAL_ANY_TTL,
+    });
+    response.update_header();
+    response
+}
+
+/// Answer to a CHAOS class question about the server itself, `None` if it's
+/// about anything else or the answer is not configured.
+pub fn chaos_response(config: &Config, question: &DnsQuestion) -> Option<DnsMessage> {
+    let name = question.name.to_string();
+    let text = if VERSION_NAMES.contains(&name.as_str()) {
+        config.server_version.as_ref()?
+    } else if HOSTNAME_NAMES.contains(&name.as_str()) {
+        config.server_hostname.as_ref()?
+    } else {
+        return None;
+    };
+
+    let mut response = DnsMessage::new();
+    response.header.flags.response = true;
+    response.header.flags.authoritative_answer = true;
+    response.questions.push(question.clone());
+    if matches!(question.qtype, QueryType::Txt | QueryType::Any) {
+        response.answers.push(DnsRecord::Unknown {
+            domain: question.name.clone(),
+            qtype: QueryType::Txt,
+            class: question.qclass,
+            rdata: txt_rdata(text.as_bytes()),
+            ttl: 0,
+        });
+    }
+    response.update_header();
+    Some(response)
+}
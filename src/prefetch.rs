@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use tokio::sync::mpsc;
+
+use crate::cache::CacheKey;
+use crate::packet::question::DnsQuestion;
+
+/// Refreshes waiting to be started before new ones are dropped.
+const QUEUE_LEN: usize = 256;
+
+/// Questions whose cached responses are to be refreshed in the background.
+///
+/// A question is only queued once until its refresh is done, however often
+/// its expiring response is handed out meanwhile.
+pub(crate) struct PrefetchQueue {
+    pending: Mutex<HashSet<CacheKey>>,
+    sender: mpsc::Sender<DnsQuestion>,
+    receiver: Mutex<Option<mpsc::Receiver<DnsQuestion>>>,
+}
+
+impl PrefetchQueue {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel(QUEUE_LEN);
+        Self {
+            pending: Mutex::new(HashSet::new()),
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+        }
+    }
+
+    /// Queue a refresh of `question`, unless one is already pending or the
+    /// queue is full. Returns whether it was queued.
+    pub fn push(&self, question: &DnsQuestion) -> bool {
+        let key = (question.name.clone(), question.qtype);
+        let mut pending = self.pending.lock().unwrap();
+        if pending.contains(&key) || self.sender.try_send(question.clone()).is_err() {
+            return false;
+        }
+        pending.insert(key);
+        true
+    }
+
+    /// Allow `question` to be queued again once its refresh is over.
+    pub fn done(&self, question: &DnsQuestion) {
+        let key = (question.name.clone(), question.qtype);
+        self.pending.lock().unwrap().remove(&key);
+    }
+
+    /// The receiving end of the queue, only handed out once.
+    pub fn take_receiver(&self) -> Option<mpsc::Receiver<DnsQuestion>> {
+        self.receiver.lock().unwrap().take()
+    }
+}
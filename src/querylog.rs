@@ -0,0 +1,187 @@
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::packet::question::DnsQuestion;
+use crate::packet::ResultCode;
+
+/// Entries waiting to be written before new ones are dropped.
+const QUEUE_LEN: usize = 4096;
+
+/// What is known about a query once it has been answered.
+#[derive(Debug, Clone)]
+pub struct QueryLogEntry {
+    /// When the query was received.
+    pub timestamp: SystemTime,
+    pub client: IpAddr,
+    /// First question of the query, `None` if it had none or couldn't be
+    /// parsed.
+    pub question: Option<DnsQuestion>,
+    pub rcode: ResultCode,
+    /// Answer records in the response.
+    pub answers: usize,
+    /// Time from receiving the query to having the response ready.
+    pub latency: Duration,
+    /// Whether every question was answered from the cache.
+    pub cache_hit: bool,
+}
+
+/// Sink of one entry per answered query.
+///
+/// Entries are handed over on the response path, so implementations must
+/// return right away and leave any slow work to a task of their own.
+pub trait QueryLog: Send + Sync {
+    fn log(&self, entry: QueryLogEntry);
+}
+
+/// Shape of a line written by [`JsonFileLogger`].
+#[derive(Serialize)]
+struct JsonLine {
+    /// Milliseconds since the Unix epoch.
+    timestamp_ms: u128,
+    client: IpAddr,
+    name: Option<String>,
+    #[serde(rename = "type")]
+    qtype: Option<String>,
+    rcode: String,
+    answers: usize,
+    latency_us: u128,
+    cache_hit: bool,
+}
+
+impl From<QueryLogEntry> for JsonLine {
+    fn from(entry: QueryLogEntry) -> Self {
+        Self {
+            timestamp_ms: entry
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis(),
+            client: entry.client,
+            name: entry.question.as_ref().map(|x| x.name.to_string()),
+            qtype: entry.question.as_ref().map(|x| x.qtype.to_string()),
+            rcode: entry.rcode.to_string(),
+            answers: entry.answers,
+            latency_us: entry.latency.as_micros(),
+            cache_hit: entry.cache_hit,
+        }
+    }
+}
+
+/// Query log writing a JSON object per line.
+///
+/// Entries are queued and written by a background task, which flushes
+/// whenever the queue runs empty. If the writer can't keep up and the queue
+/// fills, further entries are dropped rather than holding up responses.
+pub struct JsonFileLogger {
+    queue: mpsc::Sender<QueryLogEntry>,
+}
+
+impl JsonFileLogger {
+    /// Logger appending to the file at `path`, which is created if missing.
+    pub async fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .await?;
+        Ok(Self::new(file))
+    }
+
+    /// Logger writing to `writer`. Must be called within a Tokio runtime.
+    pub fn new(writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        let (queue, entries) = mpsc::channel(QUEUE_LEN);
+        tokio::spawn(write_entries(entries, writer));
+        Self { queue }
+    }
+}
+
+impl QueryLog for JsonFileLogger {
+    fn log(&self, entry: QueryLogEntry) {
+        if self.queue.try_send(entry).is_err() {
+            debug!("query log is backed up, dropping entry");
+        }
+    }
+}
+
+async fn write_entries(
+    mut entries: mpsc::Receiver<QueryLogEntry>,
+    writer: impl AsyncWrite + Unpin,
+) {
+    let mut writer = BufWriter::new(writer);
+    let mut line = Vec::new();
+
+    while let Some(entry) = entries.recv().await {
+        line.clear();
+        serde_json::to_writer(&mut line, &JsonLine::from(entry))
+            .expect("query log entries are always serializable");
+        line.push(b'\n');
+
+        let mut result = writer.write_all(&line).await;
+        if result.is_ok() && entries.is_empty() {
+            result = writer.flush().await;
+        }
+        if let Err(err) = result {
+            warn!("failed to write query log, stopping it: {err}");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use super::{JsonFileLogger, QueryLog, QueryLogEntry};
+    use crate::packet::query_class::QClass;
+    use crate::packet::query_type::QueryType;
+    use crate::packet::question::DnsQuestion;
+    use crate::packet::ResultCode;
+
+    #[tokio::test]
+    async fn entry_is_written_as_json_line() {
+        let (writer, reader) = tokio::io::duplex(4096);
+        let logger = JsonFileLogger::new(writer);
+
+        logger.log(QueryLogEntry {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_123),
+            client: IpAddr::V4(Ipv4Addr::new(192, 0, 2, 7)),
+            question: Some(DnsQuestion {
+                name: "example.com".try_into().unwrap(),
+                qtype: QueryType::Aaaa,
+                qclass: QClass::In,
+            }),
+            rcode: ResultCode::NxDomain,
+            answers: 0,
+            latency: Duration::from_micros(1500),
+            cache_hit: true,
+        });
+
+        let mut line = String::new();
+        BufReader::new(reader).read_line(&mut line).await.unwrap();
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "timestamp_ms": 1_700_000_000_123u64,
+                "client": "192.0.2.7",
+                "name": "example.com",
+                "type": "AAAA",
+                "rcode": "NXDOMAIN",
+                "answers": 0,
+                "latency_us": 1500,
+                "cache_hit": true,
+            })
+        );
+    }
+}
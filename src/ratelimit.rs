@@ -0,0 +1,169 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::Config;
+
+/// Number of independently locked parts of the bucket table, so clients
+/// don't all contend for one lock.
+const SHARDS: usize = 16;
+
+/// Number of clients a shard tracks at most.
+const MAX_CLIENTS_PER_SHARD: usize = 4096;
+
+/// How often a full shard is swept for buckets which are full again at
+/// most.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct Shard {
+    buckets: HashMap<IpAddr, Bucket>,
+    next_sweep: Instant,
+}
+
+/// Token bucket per client address.
+///
+/// Every client may send `rate_limit_burst` queries at once, after which the
+/// bucket refills at `rate_limit` queries per second.
+///
+/// Clients whose bucket is full again are forgotten once a shard fills up.
+/// If it is still full after that, as in a flood from spoofed addresses,
+/// queries from clients it doesn't track yet are dropped until the next
+/// sweep.
+pub struct RateLimiter {
+    shards: Vec<Mutex<Shard>>,
+    rate: f64,
+    burst: f64,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            shards: (0..SHARDS)
+                .map(|_| {
+                    Mutex::new(Shard {
+                        buckets: HashMap::new(),
+                        next_sweep: Instant::now(),
+                    })
+                })
+                .collect(),
+            rate: config.rate_limit.into(),
+            burst: config.rate_limit_burst.max(1).into(),
+        }
+    }
+
+    /// Take a token for a query from `client`, or tell it has to be dropped.
+    pub fn allow(&self, client: IpAddr) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+
+        let mut hasher = DefaultHasher::new();
+        client.hash(&mut hasher);
+        let mut shard = self.shards[hasher.finish() as usize % SHARDS]
+            .lock()
+            .unwrap();
+
+        let now = Instant::now();
+        if shard.buckets.len() >= MAX_CLIENTS_PER_SHARD && !shard.buckets.contains_key(&client) {
+            if now < shard.next_sweep {
+                return false;
+            }
+            shard.next_sweep = now + SWEEP_INTERVAL;
+            shard
+                .buckets
+                .retain(|_, bucket| self.refill(bucket, now) < self.burst);
+            if shard.buckets.len() >= MAX_CLIENTS_PER_SHARD {
+                return false;
+            }
+        }
+
+        let bucket = shard.buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        if self.refill(bucket, now) < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.updated = now;
+        bucket.tokens
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use super::{RateLimiter, MAX_CLIENTS_PER_SHARD, SHARDS};
+    use crate::config::Config;
+
+    #[tokio::test(start_paused = true)]
+    async fn flood_from_one_client_is_limited() {
+        let limiter = RateLimiter::new(&Config {
+            rate_limit: 10,
+            rate_limit_burst: 20,
+            ..Config::default()
+        });
+        let flooder = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let other = IpAddr::from(Ipv4Addr::new(192, 0, 2, 2));
+
+        let allowed = (0..100).filter(|_| limiter.allow(flooder)).count();
+        assert_eq!(allowed, 20);
+        assert!(limiter.allow(other));
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        let allowed = (0..100).filter(|_| limiter.allow(flooder)).count();
+        assert_eq!(allowed, 5);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn clients_are_capped_per_shard() {
+        let limiter = RateLimiter::new(&Config {
+            rate_limit: 1,
+            rate_limit_burst: 2,
+            ..Config::default()
+        });
+
+        // Every spoofed client is tracked with a bucket which isn't full
+        let spoofed = (0..u32::MAX).map(|x| IpAddr::from(Ipv4Addr::from(x)));
+        for client in spoofed.take(SHARDS * MAX_CLIENTS_PER_SHARD * 2) {
+            limiter.allow(client);
+        }
+        for shard in &limiter.shards {
+            assert!(shard.lock().unwrap().buckets.len() <= MAX_CLIENTS_PER_SHARD);
+        }
+
+        // Once the buckets are full again they make room for new clients
+        let new_client = IpAddr::from(Ipv4Addr::new(203, 0, 113, 1));
+        assert!(!limiter.allow(new_client));
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert!(limiter.allow(new_client));
+    }
+
+    #[test]
+    fn zero_rate_disables_limit() {
+        let limiter = RateLimiter::new(&Config {
+            rate_limit: 0,
+            ..Config::default()
+        });
+        let client = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        assert!((0..10_000).all(|_| limiter.allow(client)));
+    }
+}
@@ -0,0 +1,1665 @@
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::time::{timeout_at, Instant};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::cookies::CookieJar;
+use crate::packet::byte_buffer::MAX_DNS_MSG_SIZE;
+use crate::packet::edns::{EdnsOption, DNSSEC_OK};
+use crate::packet::message::DnsMessage;
+use crate::packet::qname::{Qname, ROOT};
+use crate::packet::query_type::QueryType;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record::DnsRecord;
+use crate::packet::ResultCode;
+use crate::stats::Stats;
+use crate::transport::{Transport, UdpTransport};
+use crate::upstream::{self, UpstreamHealth};
+
+/// DNSSEC bits of a client query, passed on to the queries sent on its
+/// behalf.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct DnssecFlags {
+    /// Ask for the signatures of the answer as well.
+    pub dnssec_ok: bool,
+    /// Ask validating servers for the answer even if it fails validation.
+    pub checking_disabled: bool,
+}
+
+impl DnssecFlags {
+    /// Bits of the client query `request`.
+    pub fn of(request: &DnsMessage) -> Self {
+        Self {
+            dnssec_ok: request.dnssec_ok(),
+            checking_disabled: request.header.flags.checking_disabled,
+        }
+    }
+
+    /// Whether any bit is set, so cached responses, which may lack
+    /// signatures or have passed validation, won't do.
+    pub fn any(self) -> bool {
+        self.dnssec_ok || self.checking_disabled
+    }
+}
+
+/// Query `server` over `transport`. If `options` hold a cookie, responses
+/// have to echo it. The `dnssec` bits are set in the query as they were in
+/// the client's.
+pub(crate) async fn lookup(
+    transport: &dyn Transport,
+    qname: &Qname,
+    qtype: QueryType,
+    server: (IpAddr, u16),
+    options: &[EdnsOption],
+    dnssec: DnssecFlags,
+) -> io::Result<DnsMessage> {
+    let mut builder = DnsMessage::builder()
+        .recursion_desired(true)
+        .checking_disabled(dnssec.checking_disabled)
+        .question(qname.clone(), qtype);
+    if !options.is_empty() || dnssec.dnssec_ok {
+        builder = builder.additional(DnsRecord::Opt {
+            udp_payload_size: MAX_DNS_MSG_SIZE as u16,
+            extended_rcode: 0,
+            version: 0,
+            flags: if dnssec.dnssec_ok { DNSSEC_OK } else { 0 },
+            options: options.to_vec(),
+        });
+    }
+    let packet = builder.build();
+
+    transport.query(&packet, server.into()).await
+}
+
+/// Add the CNAME a DNAME answer implies for `qname` (RFC 6672, section 3.1)
+/// right after it, unless the nameserver already did. If the substituted name
+/// would be too long, the answer is turned into NXDOMAIN instead of YXDOMAIN,
+/// which isn't modeled.
+fn synthesize_dname_cname(response: &mut DnsMessage, qname: &Qname) {
+    let has_cname = response
+        .answers
+        .iter()
+        .any(|record| matches!(record, DnsRecord::Cname { domain, .. } if domain == qname));
+    if has_cname {
+        return;
+    }
+
+    let dname = response
+        .answers
+        .iter()
+        .enumerate()
+        .find_map(|(i, record)| match record {
+            DnsRecord::Dname {
+                domain,
+                target,
+                ttl,
+            } => Some((i, qname.substitute_dname(domain, target)?, *ttl)),
+            _ => None,
+        });
+    match dname {
+        Some((i, Ok(host), ttl)) => {
+            debug!("synthesizing CNAME from {qname} to {host}");
+            let cname = DnsRecord::Cname {
+                domain: qname.clone(),
+                host,
+                ttl,
+            };
+            response.answers.insert(i + 1, cname);
+            response.update_header();
+        }
+        Some((_, Err(_), _)) => response.header.flags.rescode = ResultCode::NxDomain,
+        None => {}
+    }
+}
+
+pub(crate) fn is_same_question(response: &[DnsQuestion], query: &[DnsQuestion]) -> bool {
+    response.len() == query.len()
+        && std::iter::zip(response, query)
+            .all(|(x, y)| x.name == y.name && x.qtype == y.qtype && x.qclass == y.qclass)
+}
+
+/// Addresses of the root nameservers, where recursion starts by default.
+pub const ROOT_SERVERS: [IpAddr; 13] = [
+    IpAddr::V4(Ipv4Addr::new(198, 41, 0, 4)),
+    IpAddr::V4(Ipv4Addr::new(199, 9, 14, 201)),
+    IpAddr::V4(Ipv4Addr::new(192, 33, 4, 12)),
+    IpAddr::V4(Ipv4Addr::new(199, 7, 91, 13)),
+    IpAddr::V4(Ipv4Addr::new(192, 203, 230, 10)),
+    IpAddr::V4(Ipv4Addr::new(192, 5, 5, 241)),
+    IpAddr::V4(Ipv4Addr::new(192, 112, 36, 4)),
+    IpAddr::V4(Ipv4Addr::new(198, 97, 190, 53)),
+    IpAddr::V4(Ipv4Addr::new(192, 36, 148, 17)),
+    IpAddr::V4(Ipv4Addr::new(192, 58, 128, 30)),
+    IpAddr::V4(Ipv4Addr::new(193, 0, 14, 129)),
+    IpAddr::V4(Ipv4Addr::new(199, 7, 83, 42)),
+    IpAddr::V4(Ipv4Addr::new(202, 12, 27, 33)),
+];
+
+/// Recursive resolver, walking the delegations from the root nameservers
+/// down to the nameservers authoritative for a name.
+pub struct Resolver {
+    config: Config,
+    root_servers: Vec<IpAddr>,
+    /// Port nameservers are queried on.
+    pub(crate) port: u16,
+    pub(crate) upstreams: UpstreamHealth,
+    transport: Box<dyn Transport>,
+    cookies: CookieJar,
+    stats: Arc<Stats>,
+}
+
+impl Resolver {
+    pub fn new(config: Config) -> Self {
+        Self {
+            root_servers: config.root_servers.clone(),
+            port: config.nameserver_port,
+            upstreams: UpstreamHealth::new(&config),
+            transport: Box::new(UdpTransport::new(&config)),
+            cookies: CookieJar::new(),
+            stats: Arc::default(),
+            config,
+        }
+    }
+
+    /// Count failed queries to nameservers in `stats`.
+    pub fn with_stats(mut self, stats: Arc<Stats>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Send queries over `transport` instead of UDP.
+    pub fn with_transport(mut self, transport: Box<dyn Transport>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Start recursion at these nameservers instead of the root ones.
+    pub fn with_root_servers(mut self, root_servers: Vec<IpAddr>) -> Self {
+        self.root_servers = root_servers;
+        self
+    }
+
+    /// How long to wait for a nameserver to answer a single query. Sets
+    /// the transport back to UDP, so any other has to be given afterwards.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.config.query_timeout = timeout;
+        self.transport = Box::new(UdpTransport::new(&self.config));
+        self
+    }
+
+    /// Resolve `name` recursively.
+    pub async fn resolve(&self, name: &Qname, qtype: QueryType) -> io::Result<DnsMessage> {
+        let (response, _) = self
+            .resolve_with_options(name, qtype, &[], DnssecFlags::default())
+            .await?;
+        Ok(response)
+    }
+
+    /// Resolve `name` recursively, passing `options` and the `dnssec` bits
+    /// on to the nameservers. Returns the response together with the
+    /// nameserver which sent it.
+    pub(crate) async fn resolve_with_options(
+        &self,
+        name: &Qname,
+        qtype: QueryType,
+        options: &[EdnsOption],
+        dnssec: DnssecFlags,
+    ) -> io::Result<(DnsMessage, IpAddr)> {
+        let candidates = self.root_candidates();
+        let deadline = Instant::now() + self.config.resolve_timeout;
+        follow_delegations(
+            self,
+            name,
+            qtype,
+            options,
+            dnssec,
+            candidates,
+            0,
+            deadline,
+            &mut HashSet::new(),
+        )
+        .await
+    }
+
+    /// Ask the root nameservers only, for the delegation of `name` they know
+    /// of. Returns the response together with the nameserver which sent it.
+    pub(crate) async fn referral(
+        &self,
+        name: &Qname,
+        qtype: QueryType,
+        options: &[EdnsOption],
+        dnssec: DnssecFlags,
+    ) -> io::Result<(DnsMessage, IpAddr)> {
+        let candidates = self.root_candidates();
+        lookup_fastest(self, name, qtype, &candidates, options, dnssec).await
+    }
+
+    fn with_port(&self, servers: Vec<IpAddr>) -> Vec<(IpAddr, u16)> {
+        servers.into_iter().map(|ns| (ns, self.port)).collect()
+    }
+
+    fn root_candidates(&self) -> Vec<(IpAddr, u16)> {
+        self.with_port(self.upstreams.rank(self.root_servers.iter().copied()))
+    }
+}
+
+/// Query the first `parallel_queries` candidates at once, each with its own
+/// retries, and take whichever response comes first together with the
+/// nameserver which sent it. The other queries are cancelled.
+pub(crate) async fn lookup_fastest(
+    resolver: &Resolver,
+    qname: &Qname,
+    qtype: QueryType,
+    candidates: &[(IpAddr, u16)],
+    options: &[EdnsOption],
+    dnssec: DnssecFlags,
+) -> io::Result<(DnsMessage, IpAddr)> {
+    let started = Instant::now();
+    let parallel = candidates
+        .len()
+        .min(resolver.config.parallel_queries.max(1));
+    let candidates = &candidates[..parallel];
+
+    let queries = candidates
+        .iter()
+        .map(|&(ns, port)| {
+            Box::pin(async move {
+                debug!(%ns, "looking up {qtype} entry for {qname}");
+
+                let mut options = options.to_vec();
+                if resolver.config.dns_cookies {
+                    options.extend(resolver.cookies.option_for(ns));
+                }
+
+                let result = lookup(
+                    &*resolver.transport,
+                    qname,
+                    qtype,
+                    (ns, port),
+                    &options,
+                    dnssec,
+                )
+                .await;
+                match &result {
+                    Ok(response) => {
+                        resolver.upstreams.record_success(ns, started.elapsed());
+                        resolver.cookies.update(ns, response);
+                    }
+                    Err(_) => {
+                        resolver.upstreams.record_failure(ns);
+                        resolver.stats.record_upstream_error();
+                    }
+                }
+                result.map(|response| (response, ns))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if queries.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "no nameserver to query",
+        ));
+    }
+
+    let ((response, winner), _) = futures::future::select_ok(queries).await?;
+
+    // The cancelled ones were at least as slow as the winner
+    for &(ns, _) in candidates {
+        if ns != winner {
+            resolver.upstreams.record_slower_than(ns, started.elapsed());
+        }
+    }
+
+    Ok((response, winner))
+}
+
+/// Walk down the delegations starting at `candidates`.
+///
+/// Zones can delegate to each other in a loop, so every nameserver is asked
+/// about a name and type only once and resolving nameserver names nests at most
+/// `max_recursion_depth` levels deep. Past the `deadline` no further
+/// nameserver is asked and the query in flight is abandoned.
+///
+/// The `candidates` are taken to be root nameservers. Every later one only
+/// has authority over the zone it was delegated, so records of its
+/// responses for names outside that zone are dropped rather than trusted.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn follow_delegations(
+    resolver: &Resolver,
+    qname: &Qname,
+    qtype: QueryType,
+    options: &[EdnsOption],
+    dnssec: DnssecFlags,
+    mut candidates: Vec<(IpAddr, u16)>,
+    depth: usize,
+    deadline: Instant,
+    visited: &mut HashSet<(Qname, QueryType, SocketAddr)>,
+) -> io::Result<(DnsMessage, IpAddr)> {
+    if depth > resolver.config.max_recursion_depth {
+        return Err(io::Error::other("recursion depth exceeded"));
+    }
+
+    let mut zone = ROOT.clone();
+    loop {
+        if Instant::now() >= deadline {
+            return Err(deadline_exceeded());
+        }
+
+        candidates.retain(|&ns| !visited.contains(&(qname.clone(), qtype, ns.into())));
+        if candidates.is_empty() {
+            return Err(io::Error::other("delegation loop"));
+        }
+
+        // The first `parallel_queries` candidates are asked at once, and if
+        // none of them answers the next ones, as long as time is left
+        let (mut response, ns) = loop {
+            let parallel = resolver.config.parallel_queries.max(1);
+            let batch: Vec<_> = candidates.drain(..parallel.min(candidates.len())).collect();
+            visited.extend(batch.iter().map(|&ns| (qname.clone(), qtype, ns.into())));
+
+            let result = timeout_at(
+                deadline,
+                lookup_fastest(resolver, qname, qtype, &batch, options, dnssec),
+            )
+            .await
+            .map_err(|_| deadline_exceeded())?;
+            match result {
+                Ok(found) => break found,
+                Err(err) if candidates.is_empty() => return Err(err),
+                Err(err) => debug!(?batch, "no answer, trying the next nameservers: {err}"),
+            }
+        };
+        response.retain_in_bailiwick(&zone);
+
+        if !response.answers.is_empty() && response.header.flags.rescode == ResultCode::NoError {
+            synthesize_dname_cname(&mut response, qname);
+            // Out of bailiwick records are gone already, they stay left out
+            response.promote_cname_target(qname, qtype);
+            return Ok((response, ns));
+        }
+
+        if response.header.flags.rescode == ResultCode::NxDomain {
+            return Ok((response, ns));
+        }
+
+        // The name exists without records of this type, any NS records next
+        // to the SOA are no delegation to follow
+        if response.is_nodata() {
+            debug!(%ns, "{qname} has no {qtype} records");
+            return Ok((response, ns));
+        }
+
+        let Some(delegated) = response.delegated_zone(qname).cloned() else {
+            return Ok((response, ns));
+        };
+
+        // Not every host can reach nameservers over IPv6, so IPv4 glue is
+        // preferred and IPv6 only saves resolving the nameserver names.
+        let (glue_v4, glue_v6): (Vec<_>, Vec<_>) =
+            response.resolved_ns(qname).partition(IpAddr::is_ipv4);
+        let mut glue = resolver.upstreams.rank(glue_v4);
+        glue.extend(resolver.upstreams.rank(glue_v6));
+        if !glue.is_empty() {
+            debug!(?glue, "following delegation of {qname} to {delegated}");
+            candidates = resolver.with_port(glue);
+            zone = delegated;
+            continue;
+        }
+
+        // Without glue the nameserver names have to be resolved first. Some
+        // of them may not resolve at all, so each is tried in turn, and for
+        // an IPv6 address only if it has no IPv4 one.
+        let stable_order = resolver.config.stable_answer_order;
+        let mut addrs = Vec::new();
+        let mut last_err = None;
+        'names: for ns_name in upstream::shuffled(stable_order, response.unresolved_ns(qname)) {
+            for ns_qtype in [QueryType::A, QueryType::Aaaa] {
+                if Instant::now() >= deadline {
+                    return Err(deadline_exceeded());
+                }
+                debug!("resolving nameserver {ns_name} of {qname} ({ns_qtype})");
+
+                match Box::pin(follow_delegations(
+                    resolver,
+                    ns_name,
+                    ns_qtype,
+                    &[],
+                    DnssecFlags::default(),
+                    resolver.root_candidates(),
+                    depth + 1,
+                    deadline,
+                    visited,
+                ))
+                .await
+                {
+                    Ok((recursive_response, _)) => {
+                        addrs = resolver.upstreams.rank(recursive_response.addr_answers());
+                        if !addrs.is_empty() {
+                            break 'names;
+                        }
+                        debug!("nameserver {ns_name} has no {ns_qtype} address");
+                    }
+                    Err(err) => {
+                        debug!("failed to resolve nameserver {ns_name}: {err}");
+                        last_err = Some(err);
+                    }
+                }
+            }
+        }
+
+        if addrs.is_empty() {
+            return match last_err {
+                Some(err) => Err(err),
+                None => Ok((response, ns)),
+            };
+        }
+        candidates = resolver.with_port(addrs);
+        zone = delegated;
+    }
+}
+
+fn deadline_exceeded() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "resolve deadline exceeded")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::UdpSocket;
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::packet::DNS_HEADER_LEN;
+    use crate::testing::{make_reply, parse_message, referral, MockTransport};
+
+    #[tokio::test]
+    async fn lookup_skips_reply_with_wrong_question() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let buf = &buf[..len];
+            let query = DnsMessage::from_bytes(buf).unwrap();
+
+            let spoofed = make_reply(query.header.id, "evil.com", Ipv4Addr::new(6, 6, 6, 6));
+            stub.send_to(&spoofed, src).await.unwrap();
+
+            let genuine = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&genuine, src).await.unwrap();
+        });
+
+        let config = Config::default();
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.questions[0].name, qname);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn lookup_rejects_mismatched_question() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let buf = &buf[..len];
+            let query = DnsMessage::from_bytes(buf).unwrap();
+
+            let spoofed = make_reply(query.header.id, "evil.com", Ipv4Addr::new(6, 6, 6, 6));
+            stub.send_to(&spoofed, src).await.unwrap();
+        });
+
+        let config = Config {
+            query_timeout: Duration::from_millis(100),
+            max_retries: 0,
+            ..Config::default()
+        };
+        let qname = Qname::try_from("example.com").unwrap();
+        let err = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn lookup_compares_question_ignoring_case() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let buf = &buf[..len];
+            let query = DnsMessage::from_bytes(buf).unwrap();
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+        });
+
+        let config = Config::default();
+        let qname = Qname::try_from("Example.COM").unwrap();
+        let response = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn lookup_requires_echoed_qname_case() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let buf = &buf[..len];
+            let query = DnsMessage::from_bytes(buf).unwrap();
+            let name =
+                DNS_HEADER_LEN..DNS_HEADER_LEN + query.questions[0].name.serialized_size() as usize;
+
+            // Same name, but with the case of its first letter flipped
+            let mut miscased =
+                make_reply(query.header.id, "example.com", Ipv4Addr::new(6, 6, 6, 6));
+            miscased[name.clone()].copy_from_slice(&buf[name.clone()]);
+            miscased[name.start + 1] ^= 0x20;
+            stub.send_to(&miscased, src).await.unwrap();
+
+            let mut echoed = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            echoed[name.clone()].copy_from_slice(&buf[name]);
+            stub.send_to(&echoed, src).await.unwrap();
+        });
+
+        let config = Config {
+            randomize_qname_case: true,
+            ..Config::default()
+        };
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    /// Reply to `query` for example.com, with `cookie` in an OPT record.
+    fn reply_with_cookie(query: &DnsMessage, cookie: EdnsOption) -> Vec<u8> {
+        let mut reply = parse_message(&make_reply(
+            query.header.id,
+            "example.com",
+            Ipv4Addr::new(1, 2, 3, 4),
+        ));
+        reply.resources.push(DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: vec![cookie],
+        });
+        reply.update_header();
+        reply.to_bytes()
+    }
+
+    #[tokio::test]
+    async fn server_cookie_is_sent_back() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let mut cookies = Vec::new();
+            for _ in 0..2 {
+                let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                let query = parse_message(&buf[..len]);
+                let Some(EdnsOption::Cookie { client, server }) =
+                    query.edns_options().first().cloned()
+                else {
+                    panic!("no cookie in {query:?}");
+                };
+
+                let reply = reply_with_cookie(
+                    &query,
+                    EdnsOption::Cookie {
+                        client,
+                        server: vec![0xab; 8],
+                    },
+                );
+                stub.send_to(&reply, src).await.unwrap();
+                cookies.push((client, server));
+            }
+            cookies
+        });
+
+        let mut resolver = Resolver::new(Config {
+            root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+            query_timeout: Duration::from_millis(500),
+            ..Config::default()
+        });
+        resolver.port = port;
+
+        let qname = Qname::try_from("example.com").unwrap();
+        for _ in 0..2 {
+            let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+            assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        }
+
+        let cookies = stub.await.unwrap();
+        assert_eq!(cookies[0].0, cookies[1].0);
+        assert!(cookies[0].1.is_empty());
+        assert_eq!(cookies[1].1, [0xab; 8]);
+    }
+
+    #[tokio::test]
+    async fn server_ignoring_cookies_is_answered() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let mut sent_cookie = Vec::new();
+            for _ in 0..2 {
+                let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                let query = parse_message(&buf[..len]);
+                sent_cookie.push(
+                    query
+                        .edns_options()
+                        .iter()
+                        .any(|option| matches!(option, EdnsOption::Cookie { .. })),
+                );
+
+                let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+                stub.send_to(&reply, src).await.unwrap();
+            }
+            sent_cookie
+        });
+
+        let mut resolver = Resolver::new(Config {
+            root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+            query_timeout: Duration::from_millis(500),
+            ..Config::default()
+        });
+        resolver.port = port;
+
+        let qname = Qname::try_from("example.com").unwrap();
+        for _ in 0..2 {
+            let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+            assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        }
+
+        // No cookie round-trips once the server has ignored one
+        assert_eq!(stub.await.unwrap(), [true, false]);
+    }
+
+    #[tokio::test]
+    async fn cookie_less_reply_after_server_cookie_is_rejected() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        // Gives a server cookie, then answers like a spoofer without any
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let mut server_cookies = Vec::new();
+            while let Ok(Ok((len, src))) =
+                timeout(Duration::from_millis(500), stub.recv_from(&mut buf)).await
+            {
+                let query = parse_message(&buf[..len]);
+                let Some(EdnsOption::Cookie { client, server }) =
+                    query.edns_options().first().cloned()
+                else {
+                    panic!("no cookie in {query:?}");
+                };
+
+                let reply = if server_cookies.is_empty() {
+                    reply_with_cookie(
+                        &query,
+                        EdnsOption::Cookie {
+                            client,
+                            server: vec![0xab; 8],
+                        },
+                    )
+                } else {
+                    make_reply(query.header.id, "example.com", Ipv4Addr::new(6, 6, 6, 6))
+                };
+                stub.send_to(&reply, src).await.unwrap();
+                server_cookies.push(server);
+            }
+            server_cookies
+        });
+
+        let mut resolver = Resolver::new(Config {
+            root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+            query_timeout: Duration::from_millis(100),
+            max_retries: 1,
+            ..Config::default()
+        });
+        resolver.port = port;
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        for _ in 0..2 {
+            assert!(resolver.resolve(&qname, QueryType::A).await.is_err());
+        }
+
+        // The cookie-less replies didn't turn cookies off
+        let server_cookies = stub.await.unwrap();
+        assert_eq!(server_cookies.len(), 5);
+        assert!(server_cookies[0].is_empty());
+        assert!(server_cookies[1..].iter().all(|x| x == &[0xab; 8]));
+    }
+
+    #[tokio::test]
+    async fn malformed_cookie_is_rejected() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            // Client cookie cut short
+            let reply = reply_with_cookie(
+                &query,
+                EdnsOption::Unknown {
+                    code: 10,
+                    data: vec![7; 5],
+                },
+            );
+            stub.send_to(&reply, src).await.unwrap();
+        });
+
+        let config = Config {
+            query_timeout: Duration::from_millis(100),
+            max_retries: 0,
+            ..Config::default()
+        };
+        let qname = Qname::try_from("example.com").unwrap();
+        let cookie = EdnsOption::Cookie {
+            client: [7; 8],
+            server: Vec::new(),
+        };
+        let err = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[cookie],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap_err();
+
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "malformed cookie in response");
+    }
+
+    #[tokio::test]
+    async fn lookup_times_out_after_retries() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        let config = Config {
+            query_timeout: Duration::from_millis(50),
+            max_retries: 2,
+            ..Config::default()
+        };
+        let qname = Qname::try_from("example.com").unwrap();
+
+        let started = Instant::now();
+        let err = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap_err();
+        let elapsed = started.elapsed();
+
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(elapsed >= Duration::from_millis(150));
+        assert!(elapsed < Duration::from_secs(1));
+
+        // The query is sent once plus once per retry
+        let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+        for _ in 0..3 {
+            stub.try_recv_from(&mut buf).unwrap();
+        }
+        assert!(stub.try_recv_from(&mut buf).is_err());
+    }
+
+    #[tokio::test]
+    async fn fastest_nameserver_wins() {
+        let mut candidates = Vec::new();
+        for (delay, addr) in [
+            (Duration::from_secs(5), Ipv4Addr::new(6, 6, 6, 6)),
+            (Duration::ZERO, Ipv4Addr::new(1, 2, 3, 4)),
+        ] {
+            let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+            candidates.push((
+                Ipv4Addr::LOCALHOST.into(),
+                stub.local_addr().unwrap().port(),
+            ));
+
+            tokio::spawn(async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                let query = parse_message(&buf[..len]);
+
+                tokio::time::sleep(delay).await;
+                let reply = make_reply(query.header.id, "example.com", addr);
+                stub.send_to(&reply, src).await.unwrap();
+            });
+        }
+
+        let resolver = Resolver::new(Config {
+            query_timeout: Duration::from_secs(10),
+            ..Config::default()
+        });
+        let qname = Qname::try_from("example.com").unwrap();
+
+        let started = Instant::now();
+        let (response, _) = lookup_fastest(
+            &resolver,
+            &qname,
+            QueryType::A,
+            &candidates,
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn faster_nameserver_is_preferred_after_warm_up() {
+        let slow = IpAddr::from(Ipv4Addr::new(127, 0, 0, 1));
+        let fast = IpAddr::from(Ipv4Addr::new(127, 0, 0, 2));
+
+        let mut ports = HashMap::new();
+        for (delay, ns) in [(Duration::from_millis(50), slow), (Duration::ZERO, fast)] {
+            let stub = UdpSocket::bind((ns, 0)).await.unwrap();
+            ports.insert(ns, stub.local_addr().unwrap().port());
+
+            tokio::spawn(async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                while let Ok((len, src)) = stub.recv_from(&mut buf).await {
+                    let query = parse_message(&buf[..len]);
+
+                    tokio::time::sleep(delay).await;
+                    let reply =
+                        make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+                    stub.send_to(&reply, src).await.unwrap();
+                }
+            });
+        }
+
+        let resolver = Resolver::new(Config {
+            parallel_queries: 1,
+            ..Config::default()
+        });
+        let qname = Qname::try_from("example.com").unwrap();
+
+        let mut answered_by = Vec::new();
+        for _ in 0..8 {
+            let candidates: Vec<_> = resolver
+                .upstreams
+                .rank([slow, fast])
+                .into_iter()
+                .map(|ns| (ns, ports[&ns]))
+                .collect();
+            let (_, ns) = lookup_fastest(
+                &resolver,
+                &qname,
+                QueryType::A,
+                &candidates,
+                &[],
+                DnssecFlags::default(),
+            )
+            .await
+            .unwrap();
+            answered_by.push(ns);
+        }
+
+        // Both are tried once while warming up, then the fast one sticks
+        assert!(answered_by[..2].contains(&slow));
+        assert!(answered_by[2..].iter().all(|ns| *ns == fast));
+    }
+
+    #[tokio::test]
+    async fn delegation_loop_terminates() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        // Nameserver which keeps referring to itself
+        let stub = tokio::spawn(async move {
+            let mut queries = 0;
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            while let Ok(Ok((len, src))) =
+                timeout(Duration::from_millis(500), stub.recv_from(&mut buf)).await
+            {
+                queries += 1;
+                let query = parse_message(&buf[..len]);
+
+                let referral = referral("loop.test", "ns.loop.test", Some(Ipv4Addr::LOCALHOST));
+                let reply = DnsMessage::respond_to(&query, referral).to_bytes();
+                stub.send_to(&reply, src).await.unwrap();
+            }
+            queries
+        });
+
+        let resolver = Resolver::new(Config::default());
+        let qname = Qname::try_from("www.loop.test").unwrap();
+        let candidates = vec![(Ipv4Addr::LOCALHOST.into(), port)];
+        let result = follow_delegations(
+            &resolver,
+            &qname,
+            QueryType::A,
+            &[],
+            DnssecFlags::default(),
+            candidates,
+            0,
+            Instant::now() + Duration::from_secs(5),
+            &mut HashSet::new(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(stub.await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn resolver_starts_at_configured_roots() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+        });
+
+        let mut resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        resolver.port = port;
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn ipv6_nameserver_is_reachable() {
+        let stub = UdpSocket::bind(("::1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+        });
+
+        let mut resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv6Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        resolver.port = port;
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let (response, ns) = resolver
+            .resolve_with_options(&qname, QueryType::A, &[], DnssecFlags::default())
+            .await
+            .unwrap();
+        assert_eq!(ns, IpAddr::from(Ipv6Addr::LOCALHOST));
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn glueless_delegation_tries_every_nameserver() {
+        let root = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = root.local_addr().unwrap().port();
+        let zone_ns = UdpSocket::bind(("127.0.0.2", port)).await.unwrap();
+
+        // Delegates glueless.test to a nameserver which doesn't exist and one
+        // on 127.0.0.2, in that order
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            while let Ok((len, src)) = root.recv_from(&mut buf).await {
+                let query = parse_message(&buf[..len]);
+                let qname = query.questions[0].name.to_string();
+                let reply = match qname.as_str() {
+                    "ns2.ok.test" => {
+                        make_reply(query.header.id, &qname, Ipv4Addr::new(127, 0, 0, 2))
+                    }
+                    "www.glueless.test" => {
+                        let mut reply = referral("glueless.test", "ns1.broken.test", None);
+                        let ok = referral("glueless.test", "ns2.ok.test", None);
+                        reply.authorities.extend(ok.authorities);
+                        DnsMessage::respond_to(&query, reply).to_bytes()
+                    }
+                    _ => {
+                        let mut nxdomain = DnsMessage::new();
+                        nxdomain.header.id = query.header.id;
+                        nxdomain.header.flags.response = true;
+                        nxdomain.header.flags.rescode = ResultCode::NxDomain;
+                        nxdomain.questions = query.questions;
+                        nxdomain.update_header();
+                        nxdomain.to_bytes()
+                    }
+                };
+                root.send_to(&reply, src).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            while let Ok((len, src)) = zone_ns.recv_from(&mut buf).await {
+                let query = parse_message(&buf[..len]);
+                let reply = make_reply(
+                    query.header.id,
+                    "www.glueless.test",
+                    Ipv4Addr::new(192, 0, 2, 1),
+                );
+                zone_ns.send_to(&reply, src).await.unwrap();
+            }
+        });
+
+        let mut resolver = Resolver::new(Config {
+            stable_answer_order: true,
+            ..Config::default()
+        })
+        .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+        .with_timeout(Duration::from_millis(500));
+        resolver.port = port;
+
+        let qname = Qname::try_from("www.glueless.test").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert_eq!(
+            response.a_answers().next(),
+            Some(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn glueless_nameserver_with_ipv6_address_only() {
+        let root = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = root.local_addr().unwrap().port();
+        let zone_ns = UdpSocket::bind(("::1", port)).await.unwrap();
+
+        // Delegates v6only.test glueless to a nameserver without A records
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            while let Ok((len, src)) = root.recv_from(&mut buf).await {
+                let query = parse_message(&buf[..len]);
+                let question = query.questions[0].clone();
+                let mut result = DnsMessage::new();
+                match (question.name.to_string().as_str(), question.qtype) {
+                    ("ns.v6only.test", QueryType::Aaaa) => result.answers.push(DnsRecord::Aaaa {
+                        domain: question.name,
+                        addr: Ipv6Addr::LOCALHOST,
+                        ttl: 60,
+                    }),
+                    ("ns.v6only.test", _) => result.authorities.push(DnsRecord::Soa {
+                        domain: Qname::try_from("v6only.test").unwrap(),
+                        primary_ns: question.name,
+                        email: Qname::try_from("hostmaster.v6only.test").unwrap(),
+                        serial: 1,
+                        refresh: 3600,
+                        retry: 600,
+                        expire: 86400,
+                        min_ttl: 300,
+                        ttl: 300,
+                    }),
+                    _ => result = referral("v6only.test", "ns.v6only.test", None),
+                }
+                let reply = DnsMessage::respond_to(&query, result).to_bytes();
+                root.send_to(&reply, src).await.unwrap();
+            }
+        });
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            while let Ok((len, src)) = zone_ns.recv_from(&mut buf).await {
+                let query = parse_message(&buf[..len]);
+                let reply = make_reply(
+                    query.header.id,
+                    "www.v6only.test",
+                    Ipv4Addr::new(192, 0, 2, 1),
+                );
+                zone_ns.send_to(&reply, src).await.unwrap();
+            }
+        });
+
+        let mut resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        resolver.port = port;
+
+        let qname = Qname::try_from("www.v6only.test").unwrap();
+        let (response, ns) = resolver
+            .resolve_with_options(&qname, QueryType::A, &[], DnssecFlags::default())
+            .await
+            .unwrap();
+        assert_eq!(ns, IpAddr::from(Ipv6Addr::LOCALHOST));
+        assert_eq!(
+            response.a_answers().next(),
+            Some(Ipv4Addr::new(192, 0, 2, 1))
+        );
+    }
+
+    #[tokio::test]
+    async fn cname_target_is_promoted_to_answers() {
+        let root = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = root.local_addr().unwrap().port();
+        let zone_ns = UdpSocket::bind(("127.0.0.2", port)).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            loop {
+                let (len, src) = root.recv_from(&mut buf).await.unwrap();
+                let query = parse_message(&buf[..len]);
+
+                let glue = Some(Ipv4Addr::new(127, 0, 0, 2));
+                let referral = referral("example.test", "ns.example.test", glue);
+                let reply = DnsMessage::respond_to(&query, referral).to_bytes();
+                root.send_to(&reply, src).await.unwrap();
+            }
+        });
+
+        // Answers with the CNAME only, its target is left in the authority
+        // section, or in the additional one if outside the zone
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            loop {
+                let (len, src) = zone_ns.recv_from(&mut buf).await.unwrap();
+                let query = parse_message(&buf[..len]);
+
+                let qname = query.questions[0].name.clone();
+                let target = match qname.labels()[0].as_str() {
+                    "www" => Qname::try_from("web.example.test").unwrap(),
+                    _ => Qname::try_from("mail.evil.test").unwrap(),
+                };
+                let mut reply = DnsMessage::new();
+                reply.header.id = query.header.id;
+                reply.header.flags.response = true;
+                reply.questions = query.questions;
+                reply.answers.push(DnsRecord::Cname {
+                    domain: qname,
+                    host: target.clone(),
+                    ttl: 60,
+                });
+                let target_a = DnsRecord::A {
+                    domain: target.clone(),
+                    addr: Ipv4Addr::new(192, 0, 2, 1),
+                    ttl: 60,
+                };
+                if target.is_subdomain_of(&Qname::try_from("example.test").unwrap()) {
+                    reply.authorities.push(target_a);
+                } else {
+                    reply.resources.push(target_a);
+                }
+                reply.update_header();
+                zone_ns.send_to(&reply.to_bytes(), src).await.unwrap();
+            }
+        });
+
+        let mut resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        resolver.port = port;
+
+        let qname = Qname::try_from("www.example.test").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert!(matches!(
+            response.answers[..],
+            [DnsRecord::Cname { .. }, DnsRecord::A { ref domain, addr, .. }]
+                if domain == &Qname::try_from("web.example.test").unwrap()
+                    && addr == Ipv4Addr::new(192, 0, 2, 1)
+        ));
+        assert!(response.authorities.is_empty());
+        response.validate_structure().unwrap();
+
+        // Outside the zone the server has no say about the target
+        let qname = Qname::try_from("mail.example.test").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert!(matches!(response.answers[..], [DnsRecord::Cname { .. }]));
+        assert!(response.resources.is_empty());
+    }
+
+    #[tokio::test]
+    async fn out_of_bailiwick_records_are_dropped() {
+        let root = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = root.local_addr().unwrap().port();
+        let zone_ns = UdpSocket::bind(("127.0.0.2", port)).await.unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = root.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let glue = Some(Ipv4Addr::new(127, 0, 0, 2));
+            let referral = referral("example.test", "ns.example.test", glue);
+            let reply = DnsMessage::respond_to(&query, referral).to_bytes();
+            root.send_to(&reply, src).await.unwrap();
+        });
+
+        // Answers for example.test, and tries to slip in records of evil.test
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = zone_ns.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let evil = Qname::try_from("www.evil.test").unwrap();
+            let mut reply = DnsMessage::new();
+            reply.header.id = query.header.id;
+            reply.header.flags.response = true;
+            reply.questions = query.questions;
+            reply.answers.push(DnsRecord::Cname {
+                domain: Qname::try_from("www.example.test").unwrap(),
+                host: evil.clone(),
+                ttl: 60,
+            });
+            reply.answers.push(DnsRecord::A {
+                domain: evil,
+                addr: Ipv4Addr::new(6, 6, 6, 6),
+                ttl: 60,
+            });
+            reply.authorities.push(DnsRecord::Ns {
+                domain: Qname::try_from("evil.test").unwrap(),
+                host: Qname::try_from("ns.evil.test").unwrap(),
+                ttl: 60,
+            });
+            reply.resources.push(DnsRecord::A {
+                domain: Qname::try_from("ns.evil.test").unwrap(),
+                addr: Ipv4Addr::new(6, 6, 6, 6),
+                ttl: 60,
+            });
+            reply.update_header();
+            zone_ns.send_to(&reply.to_bytes(), src).await.unwrap();
+        });
+
+        let mut resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        resolver.port = port;
+
+        let qname = Qname::try_from("www.example.test").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert!(matches!(
+            response.answers[..],
+            [DnsRecord::Cname { ref domain, .. }] if domain == &qname
+        ));
+        assert!(response.authorities.is_empty());
+        assert!(response.resources.is_empty());
+    }
+
+    #[tokio::test]
+    async fn single_root_from_config_is_first_hop() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+            query
+        });
+
+        let mut resolver = Resolver::new(Config {
+            root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+            query_timeout: Duration::from_millis(500),
+            ..Config::default()
+        });
+        resolver.port = port;
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let query = stub.await.unwrap();
+        assert_eq!(query.questions[0].name, qname);
+    }
+
+    #[tokio::test]
+    async fn truncated_response_is_retried_over_tcp() {
+        let udp_stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = udp_stub.local_addr().unwrap().port();
+        let tcp_stub = tokio::net::TcpListener::bind(("127.0.0.1", port))
+            .await
+            .unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = udp_stub.recv_from(&mut buf).await.unwrap();
+            let mut truncated = parse_message(&buf[..len]);
+            truncated.header.flags.response = true;
+            truncated.header.flags.truncated_message = true;
+            let reply = truncated.to_bytes();
+            udp_stub.send_to(&reply, src).await.unwrap();
+        });
+        tokio::spawn(async move {
+            let (mut stream, _) = tcp_stub.accept().await.unwrap();
+            let len = stream.read_u16().await.unwrap();
+            let mut buf = vec![0u8; len.into()];
+            stream.read_exact(&mut buf).await.unwrap();
+            let query = parse_message(&buf);
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stream
+                .write_all(&(reply.len() as u16).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&reply).await.unwrap();
+        });
+
+        let config = Config {
+            query_timeout: Duration::from_millis(500),
+            ..Config::default()
+        };
+        let qname = Qname::try_from("example.com").unwrap();
+        let response = lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &[],
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(!response.header.flags.truncated_message);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn delegation_is_followed_over_mock_transport() {
+        let root = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let zone_ns = IpAddr::from(Ipv4Addr::new(192, 0, 2, 53));
+
+        let referral = referral(
+            "example.test",
+            "ns.example.test",
+            Some(Ipv4Addr::new(192, 0, 2, 53)),
+        );
+
+        let qname = Qname::try_from("www.example.test").unwrap();
+        let mut answer = DnsMessage::new();
+        answer.header.flags.authoritative_answer = true;
+        answer.answers.push(DnsRecord::A {
+            domain: qname.clone(),
+            addr: Ipv4Addr::new(1, 2, 3, 4),
+            ttl: 60,
+        });
+
+        let queried = Arc::default();
+        let transport = MockTransport {
+            responses: HashMap::from([(root, referral), (zone_ns, answer)]),
+            queried: Arc::clone(&queried),
+        };
+        let resolver = Resolver::new(Config {
+            root_servers: vec![root],
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_transport(Box::new(transport));
+
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(
+            *queried.lock().unwrap(),
+            [SocketAddr::new(root, 53), SocketAddr::new(zone_ns, 53)]
+        );
+    }
+
+    #[tokio::test]
+    async fn nameserver_on_another_port_is_not_visited() {
+        let ns = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let qname = Qname::try_from("www.example.test").unwrap();
+
+        let mut answer = DnsMessage::new();
+        answer.answers.push(DnsRecord::A {
+            domain: qname.clone(),
+            addr: Ipv4Addr::new(1, 2, 3, 4),
+            ttl: 60,
+        });
+
+        let queried = Arc::default();
+        let transport = MockTransport {
+            responses: HashMap::from([(ns, answer)]),
+            queried: Arc::clone(&queried),
+        };
+        let resolver = Resolver::new(Config {
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_transport(Box::new(transport));
+
+        let mut visited = HashSet::from([(qname.clone(), QueryType::A, SocketAddr::new(ns, 5353))]);
+        let (response, _) = follow_delegations(
+            &resolver,
+            &qname,
+            QueryType::A,
+            &[],
+            DnssecFlags::default(),
+            vec![(ns, 53)],
+            0,
+            Instant::now() + Duration::from_secs(5),
+            &mut visited,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(*queried.lock().unwrap(), [SocketAddr::new(ns, 53)]);
+    }
+
+    #[tokio::test]
+    async fn nameservers_past_dead_ones_are_tried() {
+        let dead: Vec<IpAddr> = (1..=3)
+            .map(|i| Ipv4Addr::new(192, 0, 2, i).into())
+            .collect();
+        let alive = IpAddr::from(Ipv4Addr::new(192, 0, 2, 53));
+        let qname = Qname::try_from("www.example.test").unwrap();
+
+        let mut answer = DnsMessage::new();
+        answer.answers.push(DnsRecord::A {
+            domain: qname.clone(),
+            addr: Ipv4Addr::new(1, 2, 3, 4),
+            ttl: 60,
+        });
+
+        let queried = Arc::default();
+        let transport = MockTransport {
+            responses: HashMap::from([(alive, answer)]),
+            queried: Arc::clone(&queried),
+        };
+        let resolver = Resolver::new(Config {
+            parallel_queries: 3,
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_transport(Box::new(transport));
+
+        let candidates = dead.iter().chain([&alive]).map(|&ns| (ns, 53)).collect();
+        let (response, ns) = follow_delegations(
+            &resolver,
+            &qname,
+            QueryType::A,
+            &[],
+            DnssecFlags::default(),
+            candidates,
+            0,
+            Instant::now() + Duration::from_secs(5),
+            &mut HashSet::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(ns, alive);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(queried.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn nodata_ends_the_walk() {
+        let root = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        // The zone's nameservers come along with the SOA, yet there is
+        // nothing further down to ask them about
+        let mut nodata = referral(
+            "example.test",
+            "ns.example.test",
+            Some(Ipv4Addr::new(192, 0, 2, 53)),
+        );
+        nodata.header.flags.authoritative_answer = true;
+        nodata.authorities.insert(
+            0,
+            DnsRecord::Soa {
+                domain: Qname::try_from("example.test").unwrap(),
+                primary_ns: Qname::try_from("ns.example.test").unwrap(),
+                email: Qname::try_from("hostmaster.example.test").unwrap(),
+                serial: 1,
+                refresh: 3600,
+                retry: 600,
+                expire: 86400,
+                min_ttl: 300,
+                ttl: 300,
+            },
+        );
+
+        let queried = Arc::default();
+        let transport = MockTransport {
+            responses: HashMap::from([(root, nodata)]),
+            queried: Arc::clone(&queried),
+        };
+        let resolver = Resolver::new(Config {
+            root_servers: vec![root],
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_transport(Box::new(transport));
+
+        let qname = Qname::try_from("www.example.test").unwrap();
+        let response = resolver.resolve(&qname, QueryType::Aaaa).await.unwrap();
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert!(response.is_nodata());
+        assert_eq!(*queried.lock().unwrap(), [SocketAddr::new(root, 53)]);
+    }
+
+    #[tokio::test]
+    async fn dname_implies_cname() {
+        let owner = Qname::try_from("example.com").unwrap();
+        let target = Qname::try_from(format!("{}.example.net", "x".repeat(60))).unwrap();
+
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        tokio::spawn({
+            let (owner, target) = (owner.clone(), target.clone());
+            async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                loop {
+                    let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                    let mut reply = parse_message(&buf[..len]);
+                    reply.header.flags.response = true;
+                    reply.answers.push(DnsRecord::Dname {
+                        domain: owner.clone(),
+                        target: target.clone(),
+                        ttl: 300,
+                    });
+                    reply.update_header();
+                    let reply = reply.to_bytes();
+                    stub.send_to(&reply, src).await.unwrap();
+                }
+            }
+        });
+
+        let mut resolver = Resolver::new(Config {
+            root_servers: vec![Ipv4Addr::LOCALHOST.into()],
+            query_timeout: Duration::from_millis(500),
+            ..Config::default()
+        });
+        resolver.port = port;
+
+        let qname = Qname::try_from("www.example.com").unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        response.validate_structure().unwrap();
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        match &response.answers[..] {
+            [DnsRecord::Dname { .. }, DnsRecord::Cname {
+                domain,
+                host,
+                ttl: 300,
+            }] => {
+                assert_eq!(domain, &qname);
+                assert_eq!(host.to_string(), format!("www.{target}"));
+            }
+            other => panic!("{other:?}"),
+        }
+
+        // Fits below the owner, but not below the longer target
+        let label = "y".repeat(62);
+        let qname = Qname::try_from(format!(
+            "{label}.{label}.{label}.{}.example.com",
+            "z".repeat(40)
+        ))
+        .unwrap();
+        let response = resolver.resolve(&qname, QueryType::A).await.unwrap();
+        assert_eq!(response.header.flags.rescode, ResultCode::NxDomain);
+        assert_eq!(response.answers.len(), 1);
+    }
+}
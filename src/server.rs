@@ -0,0 +1,2659 @@
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Instant};
+use tracing::{debug, error, info, info_span, trace, warn, Instrument};
+
+use crate::cache::{Cache, CacheBackend};
+use crate::config::{Config, ResolveMode};
+use crate::doh::{DohClient, DohError};
+use crate::dot::{DotClient, DotError};
+use crate::listener::UdpListener;
+use crate::mdns::{self, MDNS_GROUPS};
+use crate::packet::byte_buffer::MAX_DNS_MSG_SIZE;
+use crate::packet::edns::{EdnsOption, DNSSEC_OK, EDE_OTHER_ERROR};
+use crate::packet::message::{DnsMessage, DnsMessageError};
+use crate::packet::query_class::QClass;
+use crate::packet::query_type::QueryType;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record::DnsRecord;
+use crate::packet::{Opcode, ResultCode, DNS_HEADER_LEN};
+use crate::policy;
+use crate::prefetch::PrefetchQueue;
+use crate::querylog::{QueryLog, QueryLogEntry};
+use crate::ratelimit::RateLimiter;
+use crate::resolver::{self, DnssecFlags, Resolver};
+use crate::stats::{Stats, StatsSnapshot};
+use crate::transport::Transport;
+
+/// State shared by all query handlers.
+pub struct Server {
+    pub(crate) config: Config,
+    pub(crate) cache: Cache,
+    /// Limits cache-miss queries separately, so cached traffic keeps flowing
+    /// while the upstream path is saturated.
+    recursions: Semaphore,
+    pub(crate) resolver: Resolver,
+    /// Forwarder used instead of `resolver` if configured.
+    forwarder: Option<Forwarder>,
+    /// Where `.local` queries are sent when mDNS is enabled.
+    pub(crate) mdns_groups: Vec<SocketAddr>,
+    rate_limiter: RateLimiter,
+    stats: Arc<Stats>,
+    /// Responses rotated so far with `round_robin_answers`.
+    rotation: AtomicUsize,
+    query_log: Option<Arc<dyn QueryLog>>,
+    prefetches: PrefetchQueue,
+}
+
+/// Setting up a [`Server`] failed.
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("failed to set up DNS-over-HTTPS forwarder: {0}")]
+    Doh(#[from] DohError),
+    #[error("failed to set up DNS-over-TLS forwarder: {0}")]
+    Dot(#[from] DotError),
+}
+
+impl Server {
+    pub fn new(config: Config) -> Result<Self, ServerError> {
+        let stats = Arc::new(Stats::default());
+        Ok(Self {
+            recursions: Semaphore::new(config.max_recursions),
+            cache: Cache::new(),
+            resolver: Resolver::new(config.clone()).with_stats(stats.clone()),
+            forwarder: Forwarder::new(&config)?,
+            mdns_groups: MDNS_GROUPS.to_vec(),
+            rate_limiter: RateLimiter::new(&config),
+            stats,
+            rotation: AtomicUsize::new(0),
+            query_log: None,
+            prefetches: PrefetchQueue::new(),
+            config,
+        })
+    }
+
+    /// Keep cached responses in `backend` instead of in memory.
+    pub fn with_cache_backend(mut self, backend: Box<dyn CacheBackend>) -> Self {
+        self.cache = Cache::with_backend(backend);
+        self
+    }
+
+    /// Hand an entry for every answered query to `log`.
+    pub fn with_query_log(mut self, log: Arc<dyn QueryLog>) -> Self {
+        self.query_log = Some(log);
+        self
+    }
+
+    /// Counters of the queries handled so far.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Save the cached responses to `path`, to be loaded again after a
+    /// restart. Returns how many were saved.
+    pub async fn save_cache(&self, path: &Path) -> io::Result<usize> {
+        self.cache.save(path).await
+    }
+
+    /// Fill the cache with the responses saved to `path` which haven't
+    /// expired since. Returns how many were loaded.
+    pub async fn load_cache(&self, path: &Path) -> io::Result<usize> {
+        self.cache.load(path).await
+    }
+
+    /// Bind the listener for client queries to the configured address.
+    pub async fn bind(&self) -> io::Result<UdpListener> {
+        UdpListener::bind(self.config.listen_addr).await
+    }
+
+    /// Bind the listener for client queries over TCP to the configured
+    /// address.
+    pub async fn bind_tcp(&self) -> io::Result<TcpListener> {
+        TcpListener::bind(self.config.listen_addr).await
+    }
+
+    /// Largest UDP message received or sent, never below the 512 bytes every
+    /// client can take.
+    fn udp_buffer_size(&self) -> usize {
+        self.config.max_udp_response_size.max(MAX_DNS_MSG_SIZE)
+    }
+
+    /// Size the UDP response to `request` has to fit, the smaller of what
+    /// the client advertised and the configured maximum.
+    fn udp_response_size(&self, request: &DnsMessage) -> usize {
+        let advertised = match request.get_opt() {
+            Some(DnsRecord::Opt {
+                udp_payload_size, ..
+            }) => usize::from(*udp_payload_size),
+            _ => MAX_DNS_MSG_SIZE,
+        };
+        advertised.clamp(MAX_DNS_MSG_SIZE, self.udp_buffer_size())
+    }
+}
+
+#[derive(Debug, Error)]
+enum ResolveError {
+    #[error("too many concurrent recursions")]
+    Overloaded,
+    #[error("recursion is not allowed")]
+    Refused,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Upstream server every query is forwarded to, over some transport.
+struct Forwarder {
+    transport: Box<dyn Transport>,
+    server: (IpAddr, u16),
+    /// Name of the protocol, as shown in diagnostics.
+    protocol: &'static str,
+}
+
+impl Forwarder {
+    /// Forwarder of the configured resolve mode, `None` when resolving
+    /// recursively.
+    fn new(config: &Config) -> Result<Option<Self>, ServerError> {
+        let forwarder = match &config.resolve_mode {
+            ResolveMode::Recursive => None,
+            ResolveMode::DnsOverHttps(url) => Some(Self {
+                transport: Box::new(DohClient::new(url, config.query_timeout)?),
+                // Addressed by the URL instead
+                server: (Ipv4Addr::UNSPECIFIED.into(), 443),
+                protocol: "doh",
+            }),
+            ResolveMode::DnsOverTls { addr, server_name } => Some(Self {
+                transport: Box::new(DotClient::new(
+                    *addr,
+                    server_name.as_deref(),
+                    config.query_timeout,
+                )?),
+                server: (addr.ip(), addr.port()),
+                protocol: "dot",
+            }),
+        };
+        Ok(forwarder)
+    }
+}
+
+/// Where the answer to a question came from.
+enum Source {
+    Zone,
+    Cache,
+    Nameserver(IpAddr),
+    /// Forwarder over the named protocol.
+    Forwarder(&'static str),
+    Synthesized,
+}
+
+/// Answer a question from the cache or by recursion, passing `options` on to
+/// the nameservers. Unless `recursion_desired` is set, a cache miss is only
+/// answered with the referral of a root nameserver.
+///
+/// With any `dnssec` bit set the cache is bypassed, as cached responses may
+/// lack the signatures or have been validated, and the bits are passed on to
+/// the nameservers.
+async fn resolve(
+    server: &Server,
+    question: &DnsQuestion,
+    options: &[EdnsOption],
+    recursion_desired: bool,
+    dnssec: DnssecFlags,
+) -> Result<(DnsMessage, Source), ResolveError> {
+    let config = &server.config;
+
+    // Besides the Internet only questions about the server are answered
+    match question.qclass {
+        QClass::In => {}
+        QClass::Ch => {
+            let response = policy::chaos_response(config, question).ok_or(ResolveError::Refused)?;
+            return Ok((response, Source::Synthesized));
+        }
+        _ => return Err(ResolveError::Refused),
+    }
+
+    let refused_zone = config
+        .refused_zones
+        .iter()
+        .any(|zone| question.name.is_subdomain_of(zone));
+    if refused_zone {
+        return Err(ResolveError::Refused);
+    }
+
+    if let Some(response) = config.zone.answer(question) {
+        return Ok((response, Source::Zone));
+    }
+
+    if question.qtype == QueryType::Any && config.minimal_any_responses {
+        return Ok((policy::minimal_any_response(question), Source::Synthesized));
+    }
+
+    if (config.allow_recursion || config.serve_cache_without_recursion) && !dnssec.any() {
+        if let Some(hit) = server.cache.get(&question.name, question.qtype).await {
+            server.stats.record_cache_hit();
+            let popular = matches!(config.prefetch_hits, Some(hits) if hit.hits >= hits);
+            if hit.expiring && popular && config.allow_recursion {
+                server.prefetches.push(question);
+            }
+            return Ok((hit.response, Source::Cache));
+        }
+        server.stats.record_cache_miss();
+    }
+
+    if !config.allow_recursion || config.refused_qtypes.contains(&question.qtype) {
+        return Err(ResolveError::Refused);
+    }
+
+    let _permit = timeout(server.config.recursion_wait, server.recursions.acquire())
+        .await
+        .map_err(|_| ResolveError::Overloaded)?
+        .map_err(io::Error::other)?;
+
+    let started = Instant::now();
+    let (response, source) =
+        lookup_upstream(server, question, options, recursion_desired, dnssec).await?;
+    server.stats.record_resolution(started.elapsed());
+    // A referral is no answer to the question
+    if recursion_desired && !dnssec.any() {
+        server
+            .cache
+            .insert(&question.name, question.qtype, &response)
+            .await;
+    }
+
+    Ok((response, source))
+}
+
+/// Refresh the cached responses queued for prefetching, each in a task of
+/// its own. Returns right away if another task is already doing so.
+pub(crate) async fn run_prefetches(server: Arc<Server>) {
+    let Some(mut questions) = server.prefetches.take_receiver() else {
+        return;
+    };
+
+    while let Some(question) = questions.recv().await {
+        let server = server.clone();
+        let refresh = async move {
+            prefetch(&server, &question).await;
+            server.prefetches.done(&question);
+        };
+        tokio::spawn(refresh.instrument(info_span!("prefetch")));
+    }
+}
+
+/// Resolve `question` again and replace its cached response. Prefetching is
+/// optional, so it is skipped if no recursion slot is free right away.
+async fn prefetch(server: &Server, question: &DnsQuestion) {
+    let Ok(_permit) = server.recursions.try_acquire() else {
+        debug!(
+            "no recursion slot to prefetch {} {}",
+            question.name, question.qtype
+        );
+        return;
+    };
+
+    match lookup_upstream(server, question, &[], true, DnssecFlags::default()).await {
+        Ok((response, _)) => {
+            server
+                .cache
+                .insert(&question.name, question.qtype, &response)
+                .await;
+        }
+        Err(err) => debug!(
+            "failed to prefetch {} {}: {err}",
+            question.name, question.qtype
+        ),
+    }
+}
+
+/// Ask whoever is configured to answer cache misses.
+async fn lookup_upstream(
+    server: &Server,
+    question: &DnsQuestion,
+    options: &[EdnsOption],
+    recursion_desired: bool,
+    dnssec: DnssecFlags,
+) -> Result<(DnsMessage, Source), ResolveError> {
+    let (mut response, source) = if server.config.mdns && mdns::is_local(&question.name) {
+        let (response, responder) = mdns::lookup(
+            &server.config,
+            &question.name,
+            question.qtype,
+            &server.mdns_groups,
+        )
+        .await
+        .inspect_err(|_| server.stats.record_upstream_error())?;
+        (response, Source::Nameserver(responder))
+    } else if let Some(forwarder) = &server.forwarder {
+        let response = resolver::lookup(
+            &*forwarder.transport,
+            &question.name,
+            question.qtype,
+            forwarder.server,
+            options,
+            dnssec,
+        )
+        .await
+        .inspect_err(|_| server.stats.record_upstream_error())?;
+        (response, Source::Forwarder(forwarder.protocol))
+    } else if !recursion_desired {
+        let (response, nameserver) = server
+            .resolver
+            .referral(&question.name, question.qtype, options, dnssec)
+            .await?;
+        (response, Source::Nameserver(nameserver))
+    } else {
+        // The resolver counts its failures itself, one per nameserver
+        let (response, nameserver) = server
+            .resolver
+            .resolve_with_options(&question.name, question.qtype, options, dnssec)
+            .await?;
+        (response, Source::Nameserver(nameserver))
+    };
+
+    // Before caching, so the TTLs counted down there are the clamped ones
+    response.clamp_ttls(server.config.min_ttl, server.config.max_ttl);
+    Ok((response, source))
+}
+
+/// Build an empty response with `rescode` to a query which couldn't be
+/// handled. The ID is kept so the client can match it, without one there is
+/// nothing to answer.
+fn error_response(msg_buf: &[u8], rescode: ResultCode, response: &mut [u8]) -> io::Result<usize> {
+    let id = match msg_buf {
+        [hi, lo, ..] => u16::from_be_bytes([*hi, *lo]),
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "query too short to answer",
+            ))
+        }
+    };
+
+    let mut packet = DnsMessage::new();
+    packet.header.id = id;
+    packet.header.flags.response = true;
+    packet.header.flags.rescode = rescode;
+
+    packet.serialize_into(response).map_err(io::Error::other)
+}
+
+/// Answer the query from `client` in `msg_buf` into `response`, returning
+/// the length of the answer. Records which don't fit are left out, with the
+/// truncated flag set if an answer or authority record is missing. Over
+/// `udp` the answer is further limited to the size negotiated with the
+/// client.
+pub(crate) async fn handle_query(
+    server: &Server,
+    client: IpAddr,
+    msg_buf: &[u8],
+    response: &mut [u8],
+    udp: bool,
+) -> io::Result<usize> {
+    let timestamp = SystemTime::now();
+    let received = Instant::now();
+    server.stats.record_query();
+
+    let log_query = |question: Option<&DnsQuestion>, rcode, answers, cache_hit| {
+        if let Some(log) = &server.query_log {
+            log.log(QueryLogEntry {
+                timestamp,
+                client,
+                question: question.cloned(),
+                rcode,
+                answers,
+                latency: received.elapsed(),
+                cache_hit,
+            });
+        }
+    };
+
+    let request = match DnsMessage::from_bytes(msg_buf) {
+        Ok(request) => request,
+        Err(err) => {
+            debug!("malformed query: {err}");
+            server.stats.record_response(ResultCode::FormErr);
+            log_query(None, ResultCode::FormErr, 0, false);
+            return error_response(msg_buf, ResultCode::FormErr, response);
+        }
+    };
+
+    let response = if udp {
+        let max_size = server.udp_response_size(&request).min(response.len());
+        &mut response[..max_size]
+    } else {
+        response
+    };
+
+    // Records are added as the questions get resolved
+    let mut packet = DnsMessage::respond_to(&request, DnsMessage::new());
+    packet.header.flags.recursion_available = server.config.allow_recursion;
+
+    // OPT record is only allowed in the response if the client sent one,
+    // the DNSSEC OK bit is echoed back
+    let mut opt = request.get_opt().map(|_| DnsRecord::Opt {
+        udp_payload_size: u16::try_from(server.udp_buffer_size()).unwrap_or(u16::MAX),
+        extended_rcode: 0,
+        version: 0,
+        flags: if request.dnssec_ok() { DNSSEC_OK } else { 0 },
+        options: Vec::new(),
+    });
+
+    let relayed: Vec<_> = request
+        .edns_options()
+        .iter()
+        .filter(|option| option.is_relayed())
+        .cloned()
+        .collect();
+
+    // Only standard queries are implemented, the questions of any other
+    // opcode are echoed back unanswered, as are those of a query claiming to
+    // be truncated or, if strict, with the reserved bit set
+    let questions: &[DnsQuestion] = if request.header.flags.opcode != Opcode::Query {
+        packet.header.flags.rescode = ResultCode::NoTimp;
+        &[]
+    } else if (request.header.flags.truncated_message && !server.config.ignore_truncated_queries)
+        || (request.header.flags.z && server.config.reject_z_queries)
+    {
+        packet.header.flags.rescode = ResultCode::FormErr;
+        &[]
+    } else {
+        if request.questions.is_empty() {
+            packet.header.flags.rescode = ResultCode::FormErr;
+        }
+        &request.questions
+    };
+
+    // Every question is resolved on its own and the records are merged in
+    // question order. The response takes the result code of the first
+    // question which didn't succeed. If any question couldn't be resolved at
+    // all, the whole response is SERVFAIL without records, as a partial
+    // answer would be indistinguishable from a complete one.
+    let mut failure = None;
+    let mut diagnostics = Vec::new();
+    // Only if every question is answered from the local zone
+    let mut authoritative = !questions.is_empty();
+    // Only if every question is answered from the cache
+    let mut cache_hit = !questions.is_empty();
+    // Whether records were left out for exceeding `max_section_records`
+    let mut capped = false;
+
+    for question in questions {
+        let span = info_span!("question", name = %question.name, qtype = %question.qtype);
+        debug!(parent: &span, "received query");
+
+        let started = Instant::now();
+        let resolved = resolve(
+            server,
+            question,
+            &relayed,
+            request.header.flags.recursion_desired,
+            DnssecFlags::of(&request),
+        )
+        .instrument(span.clone())
+        .await;
+        let _entered = span.enter();
+
+        if server.config.echo_diagnostics {
+            let summary = match &resolved {
+                Ok((_, Source::Zone)) => "zone".to_string(),
+                Ok((_, Source::Cache)) => "cache=hit".to_string(),
+                Ok((_, Source::Nameserver(ns))) => format!("cache=miss; upstream={ns}"),
+                Ok((_, Source::Forwarder(protocol))) => format!("cache=miss; upstream={protocol}"),
+                Ok((_, Source::Synthesized)) => "synthesized".to_string(),
+                Err(err) => format!("error={err}"),
+            };
+            let text = format!("{summary}; elapsed={}ms", started.elapsed().as_millis());
+            diagnostics.push(DnsRecord::Txt {
+                domain: question.name.clone(),
+                strings: vec![text.into_bytes()],
+                ttl: 0,
+            });
+        }
+
+        if !matches!(resolved, Ok((_, Source::Cache))) {
+            cache_hit = false;
+        }
+
+        match resolved {
+            Ok((mut result, source)) => {
+                // Nameservers may well be authoritative for their answers,
+                // but relayed by us they aren't
+                let from_zone = matches!(source, Source::Zone);
+                if !(from_zone && result.is_authoritative_for(&question.name)) {
+                    authoritative = false;
+                }
+
+                if !from_zone && policy::filter_answers(&server.config, question, &mut result) {
+                    info!("blocked answer");
+                }
+
+                if packet.header.flags.rescode == ResultCode::NoError {
+                    packet.header.flags.rescode = result.header.flags.rescode;
+                }
+
+                // OPT is hop-by-hop, only options unknown to us are relayed,
+                // the record itself is dropped below
+                if let Some(DnsRecord::Opt { options, .. }) = opt.as_mut() {
+                    for option in result.edns_options() {
+                        if option.is_relayed() && !options.contains(option) {
+                            options.push(option.clone());
+                        }
+                    }
+                }
+
+                let cap = server.config.max_section_records;
+                for rec in result.answers {
+                    if packet.answers.len() >= cap {
+                        capped = true;
+                        break;
+                    }
+                    trace!(?rec, "answer");
+                    packet.answers.push(rec);
+                }
+                for rec in result.authorities {
+                    if packet.authorities.len() >= cap {
+                        capped = true;
+                        break;
+                    }
+                    trace!(?rec, "authority");
+                    packet.authorities.push(rec);
+                }
+                for rec in result.resources {
+                    if matches!(rec, DnsRecord::Opt { .. }) {
+                        continue;
+                    }
+                    if packet.resources.len() >= cap {
+                        capped = true;
+                        break;
+                    }
+                    trace!(?rec, "resource");
+                    packet.resources.push(rec);
+                }
+            }
+            Err(err) => {
+                debug!("failed to resolve: {err}");
+                authoritative = false;
+                failure = Some(err);
+            }
+        }
+    }
+    packet.header.flags.authoritative_answer = authoritative;
+
+    // A failed response has no records to be missing
+    if capped && failure.is_none() {
+        debug!("too many records, response is cut short");
+        packet.header.flags.truncated_message = true;
+    }
+
+    if let Some(err) = failure {
+        packet.header.flags.rescode = match &err {
+            ResolveError::Refused => ResultCode::Refused,
+            _ => {
+                warn!("answering with SERVFAIL: {err}");
+                ResultCode::ServFail
+            }
+        };
+        packet.answers.clear();
+        packet.authorities.clear();
+        packet.resources.clear();
+
+        // Tell the client why, so it can back off
+        if let (ResolveError::Overloaded, Some(DnsRecord::Opt { options, .. })) =
+            (err, opt.as_mut())
+        {
+            options.push(EdnsOption::ExtendedError {
+                info_code: EDE_OTHER_ERROR,
+                extra_text: "server overloaded".into(),
+            });
+        }
+    }
+
+    // Questions resolved through the same CNAME chain share records
+    packet.dedup();
+
+    if server.config.round_robin_answers {
+        let offset = server.rotation.fetch_add(1, Ordering::Relaxed);
+        policy::rotate_answers(&mut packet.answers, offset);
+    }
+
+    if server.config.minimal_responses {
+        packet.minimize();
+    }
+    packet.update_header();
+
+    if let Err(err) = packet.validate_structure() {
+        warn!("refusing to send incoherent response, answering with SERVFAIL: {err}");
+        packet.header.flags.rescode = ResultCode::ServFail;
+        packet.answers.clear();
+        packet.authorities.clear();
+        packet.resources.clear();
+        packet.update_header();
+    }
+
+    // Diagnostics are added last, so they also explain failed responses
+    packet.resources.extend(diagnostics);
+    packet.update_header();
+
+    server.stats.record_response(packet.header.flags.rescode);
+
+    let opt_size = opt.as_ref().map_or(0, |opt| opt.serialized_size() as usize);
+    packet.resources.extend(opt);
+    packet.update_header();
+
+    let written = match packet.serialize_into(response) {
+        Err(DnsMessageError::BufferTooSmall(max_size)) => {
+            // OPT was added last, it stays whatever else is dropped
+            let opt = (opt_size > 0).then(|| packet.resources.pop()).flatten();
+            packet.truncate(max_size.saturating_sub(opt_size));
+            packet.resources.extend(opt);
+            packet.update_header();
+            packet.serialize_into(response).map_err(io::Error::other)
+        }
+        result => result.map_err(io::Error::other),
+    };
+
+    log_query(
+        request.questions.first(),
+        packet.header.flags.rescode,
+        packet.answers.len(),
+        cache_hit,
+    );
+    written
+}
+
+/// Response buffers kept around by [`serve_until`] for the next responses.
+const MAX_SPARE_BUFFERS: usize = 64;
+
+/// Receive queries and answer each of them in its own task, so a slow
+/// recursive lookup never holds up other clients.
+pub async fn serve(socket: Arc<UdpListener>, server: Arc<Server>) -> io::Result<()> {
+    serve_until(socket, server, std::future::pending()).await
+}
+
+/// Like [`serve`], but stop receiving queries once `shutdown` completes.
+/// Queries already received still get `shutdown_timeout` to be answered,
+/// whatever is left then is dropped.
+pub async fn serve_until(
+    socket: Arc<UdpListener>,
+    server: Arc<Server>,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    tokio::pin!(shutdown);
+    let mut in_flight = JoinSet::new();
+    tokio::spawn(run_prefetches(server.clone()));
+
+    let buf_size = server.udp_buffer_size();
+    let mut msg_buf = vec![0u8; buf_size];
+    // Buffers of sent responses, serialized into again by later handlers
+    let spare_buffers: Arc<Mutex<Vec<Vec<u8>>>> = Arc::default();
+
+    loop {
+        let received = tokio::select! {
+            _ = &mut shutdown => break,
+            // Finished responders have to be collected, or the set grows forever
+            Some(_) = in_flight.join_next() => continue,
+            received = socket.recv_from(&mut msg_buf) => received,
+        };
+        let (len, src, dst) = match received {
+            Ok(received) => received,
+            Err(err) => {
+                warn!("failed to receive query: {err}");
+                continue;
+            }
+        };
+
+        // Answering a flood would only help to reflect it at someone else
+        if !server.rate_limiter.allow(src.ip()) {
+            trace!(%src, "rate limited");
+            continue;
+        }
+
+        let msg_buf: Arc<[u8]> = msg_buf[..len].into();
+        let socket = socket.clone();
+        let spare_buffers = spare_buffers.clone();
+
+        // The handler runs in a task of its own, so a panic while answering
+        // one query is caught by its join handle instead of the server.
+        let span = info_span!("query", %src);
+        let handler = tokio::spawn({
+            let server = server.clone();
+            let msg_buf = msg_buf.clone();
+            let spare = spare_buffers.lock().unwrap().pop();
+            async move {
+                let mut response = spare.unwrap_or_else(|| vec![0u8; buf_size]);
+                let len = handle_query(&server, src.ip(), &msg_buf, &mut response, true).await?;
+                io::Result::Ok((response, len))
+            }
+            .instrument(span.clone())
+        });
+
+        let responder = async move {
+            let (response, response_len) = match handler.await {
+                Ok(Ok(result)) => result,
+                Ok(Err(err)) => {
+                    warn!("error during handling request: {err}");
+                    return;
+                }
+                Err(err) => {
+                    error!("handler failed, answering with SERVFAIL: {err}");
+                    let mut response = vec![0u8; DNS_HEADER_LEN];
+                    match error_response(&msg_buf, ResultCode::ServFail, &mut response) {
+                        Ok(response_len) => (response, response_len),
+                        Err(_) => return,
+                    }
+                }
+            };
+
+            if let Err(err) = socket.send_to(&response[..response_len], src, dst).await {
+                warn!("failed to send result: {err}");
+            }
+
+            let mut spare_buffers = spare_buffers.lock().unwrap();
+            if response.len() == buf_size && spare_buffers.len() < MAX_SPARE_BUFFERS {
+                spare_buffers.push(response);
+            }
+        };
+        in_flight.spawn(responder.instrument(span));
+    }
+
+    let pending = in_flight.len();
+    info!("shutting down, {pending} queries in flight");
+    let drain = async { while in_flight.join_next().await.is_some() {} };
+    let _ = timeout(server.config.shutdown_timeout, drain).await;
+    info!(
+        "answered {} of {pending} queries in flight",
+        pending - in_flight.len()
+    );
+
+    Ok(())
+}
+
+/// Answer queries over TCP (RFC 7766), each connection in a task of its own.
+pub async fn serve_tcp(listener: TcpListener, server: Arc<Server>) -> io::Result<()> {
+    let connections = Arc::new(Semaphore::new(server.config.max_tcp_connections.max(1)));
+    loop {
+        let permit = connections
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed");
+        let (stream, src) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                warn!("failed to accept TCP connection: {err}");
+                continue;
+            }
+        };
+
+        let server = server.clone();
+        let connection = async move {
+            if let Err(err) = handle_tcp_connection(&server, stream, src.ip()).await {
+                debug!("closing TCP connection: {err}");
+            }
+            drop(permit);
+        };
+        tokio::spawn(connection.instrument(info_span!("tcp", %src)));
+    }
+}
+
+/// Answer the queries of a connection in turn, until the client closes it or
+/// fails to send a whole query within `tcp_timeout`.
+async fn handle_tcp_connection(
+    server: &Server,
+    mut stream: TcpStream,
+    client: IpAddr,
+) -> io::Result<()> {
+    // Only allocated once the client sends something, so idle connections
+    // hold on to little memory
+    let mut msg_buf = Vec::new();
+    let mut framed = Vec::new();
+
+    loop {
+        // The length is up to the client, which may never send that much
+        let len = match timeout(
+            server.config.tcp_timeout,
+            read_tcp_message(&mut stream, &mut msg_buf),
+        )
+        .await
+        {
+            Ok(Ok(Some(len))) => len,
+            Ok(Ok(None)) => return Ok(()),
+            Ok(Err(err)) => return Err(err),
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no complete query in time",
+                ))
+            }
+        };
+
+        // Room for the largest message the length prefix allows
+        framed.resize(2 + usize::from(u16::MAX), 0);
+        let response_len =
+            handle_query(server, client, &msg_buf[..len], &mut framed[2..], false).await?;
+        framed[..2].copy_from_slice(&(response_len as u16).to_be_bytes());
+        stream.write_all(&framed[..2 + response_len]).await?;
+    }
+}
+
+/// Read a message prefixed with its length into `buf`, grown to the length
+/// given, returning its length, or `None` if the connection was closed
+/// instead.
+async fn read_tcp_message(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<Option<usize>> {
+    let len = match stream.read_u16().await {
+        Ok(len) => usize::from(len),
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    if len < DNS_HEADER_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("query length {len} out of bounds"),
+        ));
+    }
+
+    if buf.len() < len {
+        buf.resize(len, 0);
+    }
+    stream.read_exact(&mut buf[..len]).await?;
+    Ok(Some(len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::packet::qname::Qname;
+    use crate::policy::BlockAction;
+    use crate::resolver::{lookup, ROOT_SERVERS};
+    use crate::testing::{make_query, make_reply, parse_message, query, referral, to_msg_buf};
+    use crate::transport::UdpTransport;
+    use crate::zone::Zone;
+
+    /// Answer a query into a buffer of its own.
+    async fn handle_query(
+        server: &Server,
+        msg_buf: [u8; MAX_DNS_MSG_SIZE],
+        len: usize,
+    ) -> io::Result<Vec<u8>> {
+        let mut response = vec![0u8; server.udp_buffer_size()];
+        let client = Ipv4Addr::LOCALHOST.into();
+        let len = super::handle_query(server, client, &msg_buf[..len], &mut response, true).await?;
+        response.truncate(len);
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn cache_hits_bypass_recursion_limit() {
+        let server = Server::new(Config {
+            max_recursions: 1,
+            recursion_wait: Duration::from_millis(10),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        // Occupy the only recursion slot.
+        let _permit = server.recursions.acquire().await.unwrap();
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let (msg_buf, len) = make_query("uncached.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn minimal_responses_drop_authority_and_additional() {
+        let mut cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        cached.authorities.push(DnsRecord::Ns {
+            domain: Qname::try_from("example.com").unwrap(),
+            host: Qname::try_from("ns1.example.com").unwrap(),
+            ttl: 60,
+        });
+        cached.resources.push(DnsRecord::A {
+            domain: Qname::try_from("ns1.example.com").unwrap(),
+            addr: Ipv4Addr::new(192, 0, 2, 53),
+            ttl: 60,
+        });
+        cached.update_header();
+        let qname = Qname::try_from("example.com").unwrap();
+
+        for minimal_responses in [false, true] {
+            let server = Server::new(Config {
+                minimal_responses,
+                ..Config::default()
+            })
+            .unwrap();
+            server.cache.insert(&qname, QueryType::A, &cached).await;
+
+            let mut request = query("example.com", QueryType::A);
+            request.resources.push(DnsRecord::Opt {
+                udp_payload_size: 1232,
+                extended_rcode: 0,
+                version: 0,
+                flags: 0,
+                options: Vec::new(),
+            });
+            request.update_header();
+            let (msg_buf, len) = to_msg_buf(&request);
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+            assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+            assert!(response.get_opt().is_some());
+            let relayed = if minimal_responses { 0 } else { 1 };
+            assert_eq!(response.authorities.len(), relayed);
+            assert_eq!(response.resources.len(), relayed + 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn referral_without_recursion_desired() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        // Refers to a nameserver which is never asked
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let mut queries = 0;
+            while let Ok(Ok((len, src))) =
+                timeout(Duration::from_millis(300), stub.recv_from(&mut buf)).await
+            {
+                let query = parse_message(&buf[..len]);
+                let glue = Some(Ipv4Addr::new(192, 0, 2, 53));
+                let referral = referral("com", "a.gtld-servers.net", glue);
+                let reply = DnsMessage::respond_to(&query, referral).to_bytes();
+                stub.send_to(&reply, src).await.unwrap();
+                queries += 1;
+            }
+            queries
+        });
+
+        let mut server = Server::new(Config {
+            minimal_responses: true,
+            ..Config::default()
+        })
+        .unwrap();
+        server.resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(100));
+        server.resolver.port = port;
+
+        let mut request = query("example.com", QueryType::A);
+        request.header.flags.recursion_desired = false;
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert!(!response.header.flags.recursion_desired);
+        assert!(response.header.flags.recursion_available);
+        assert!(response.answers.is_empty());
+        assert_eq!(response.authorities.len(), 1);
+        assert_eq!(response.authorities[0].qtype(), QueryType::Ns);
+        assert_eq!(response.resources.len(), 1);
+        assert_eq!(stub.await.unwrap(), 1);
+
+        // Not mistaken for an answer later on
+        let qname = Qname::try_from("example.com").unwrap();
+        assert!(server.cache.get(&qname, QueryType::A).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn slow_delegation_chain_hits_resolve_timeout() {
+        // Chain of nameservers on 127.0.0.1 to 127.0.0.4, each taking 200ms
+        // to refer to the next one
+        let first = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = first.local_addr().unwrap().port();
+        let mut stubs = vec![first];
+        for i in 2..=4 {
+            stubs.push(
+                UdpSocket::bind((Ipv4Addr::new(127, 0, 0, i), port))
+                    .await
+                    .unwrap(),
+            );
+        }
+        for (i, stub) in (2..).zip(stubs) {
+            tokio::spawn(async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                while let Ok((len, src)) = stub.recv_from(&mut buf).await {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                    let query = parse_message(&buf[..len]);
+                    let glue = Some(Ipv4Addr::new(127, 0, 0, i));
+                    let referral = referral("example.com", "ns.example.com", glue);
+                    let reply = DnsMessage::respond_to(&query, referral).to_bytes();
+                    stub.send_to(&reply, src).await.unwrap();
+                }
+            });
+        }
+
+        let mut server = Server::new(Config::default()).unwrap();
+        server.resolver = Resolver::new(Config {
+            resolve_timeout: Duration::from_millis(500),
+            ..Config::default()
+        })
+        .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()]);
+        server.resolver.port = port;
+
+        let started = Instant::now();
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+        assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(500), "{elapsed:?}");
+        assert!(elapsed < Duration::from_millis(700), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn queries_are_handled_concurrently() {
+        // Without free recursion slots every query waits the whole
+        // `recursion_wait` before failing.
+        let server = Arc::new(
+            Server::new(Config {
+                max_recursions: 0,
+                recursion_wait: Duration::from_millis(200),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        let socket = UdpListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        tokio::spawn(serve(Arc::new(socket), server));
+
+        let client = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let started = Instant::now();
+
+        for i in 0..50 {
+            let (msg_buf, len) = make_query(&format!("host{i}.example.com"), QueryType::A);
+            client.send_to(&msg_buf[..len], server_addr).await.unwrap();
+        }
+
+        let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+        for _ in 0..50 {
+            let (len, _) = client.recv_from(&mut buf).await.unwrap();
+            let response = parse_message(&buf[..len]);
+            assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+        }
+
+        // Handled one by one this would take 10 seconds.
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn overload_servfail_carries_extended_error() {
+        let server = Server::new(Config {
+            max_recursions: 0,
+            recursion_wait: Duration::from_millis(10),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut request = query("example.com", QueryType::A);
+        request.resources.push(DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+        request.update_header();
+
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+
+        let Some(DnsRecord::Opt { options, .. }) = response.get_opt() else {
+            panic!("no OPT record in response");
+        };
+        assert_eq!(
+            options,
+            &[EdnsOption::ExtendedError {
+                info_code: EDE_OTHER_ERROR,
+                extra_text: "server overloaded".into(),
+            }]
+        );
+
+        // Without EDNS in the query there is nowhere to put the error
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+        assert!(response.get_opt().is_none());
+    }
+
+    #[tokio::test]
+    async fn answers_every_question() {
+        let server = Server::new(Config::default()).unwrap();
+
+        for (name, addr) in [
+            ("a.example.com", Ipv4Addr::new(192, 0, 2, 1)),
+            ("b.example.com", Ipv4Addr::new(192, 0, 2, 2)),
+        ] {
+            let cached = parse_message(&make_reply(1, name, addr));
+            let qname = Qname::try_from(name).unwrap();
+            server.cache.insert(&qname, QueryType::A, &cached).await;
+        }
+
+        let mut request = query("a.example.com", QueryType::A);
+        request.questions.push(DnsQuestion::new(
+            Qname::try_from("b.example.com").unwrap(),
+            QueryType::A,
+        ));
+        request.update_header();
+
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.header.questions, 2);
+        assert_eq!(
+            response.questions[0].name,
+            Qname::try_from("a.example.com").unwrap()
+        );
+        assert_eq!(
+            response.questions[1].name,
+            Qname::try_from("b.example.com").unwrap()
+        );
+
+        let addrs: Vec<_> = response
+            .answers
+            .iter()
+            .filter_map(|record| match record {
+                DnsRecord::A { addr, .. } => Some(*addr),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            addrs,
+            [Ipv4Addr::new(192, 0, 2, 1), Ipv4Addr::new(192, 0, 2, 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn cache_is_served_without_recursion() {
+        let server = Server::new(Config {
+            allow_recursion: false,
+            serve_cache_without_recursion: true,
+            ..Config::default()
+        })
+        .unwrap();
+
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert!(!response.header.flags.recursion_available);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        let (msg_buf, len) = make_query("uncached.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::Refused);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn refused_qtypes_are_not_recursed() {
+        let server = Server::new(Config {
+            refused_qtypes: vec![QueryType::Mx],
+            ..Config::default()
+        })
+        .unwrap();
+
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+        let mut cached_mx = cached.clone();
+        cached_mx.questions[0].qtype = QueryType::Mx;
+        cached_mx.answers = vec![DnsRecord::Mx {
+            domain: qname.clone(),
+            priority: 10,
+            host: Qname::try_from("mail.example.com").unwrap(),
+            ttl: 60,
+        }];
+        server.cache.insert(&qname, QueryType::Mx, &cached_mx).await;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        // Cached answers are served regardless
+        let (msg_buf, len) = make_query("example.com", QueryType::Mx);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.answers.len(), 1);
+
+        let (msg_buf, len) = make_query("uncached.com", QueryType::Mx);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::Refused);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn any_query_returns_every_record() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+            assert_eq!(query.questions[0].qtype, QueryType::Any);
+
+            let name = query.questions[0].name.clone();
+            let mut reply = parse_message(&make_reply(
+                query.header.id,
+                "example.com",
+                Ipv4Addr::new(1, 2, 3, 4),
+            ));
+            reply.questions = query.questions;
+            reply.answers.push(DnsRecord::Mx {
+                domain: name.clone(),
+                priority: 10,
+                host: Qname::try_from("mail.example.com").unwrap(),
+                ttl: 60,
+            });
+            reply.answers.push(DnsRecord::Txt {
+                domain: name,
+                strings: vec![b"v=spf1 -all".to_vec()],
+                ttl: 60,
+            });
+            reply.update_header();
+
+            let (reply, len) = to_msg_buf(&reply);
+            stub.send_to(&reply[..len], src).await.unwrap();
+        });
+
+        let mut server = Server::new(Config::default()).unwrap();
+        server.resolver = Resolver::new(Config::default())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::Any);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.questions[0].qtype, QueryType::Any);
+        let qtypes: Vec<_> = response.answers.iter().map(DnsRecord::qtype).collect();
+        assert_eq!(qtypes, [QueryType::A, QueryType::Mx, QueryType::Txt]);
+
+        // Answered without asking anyone
+        let server = Server::new(Config {
+            minimal_any_responses: true,
+            ..Config::default()
+        })
+        .unwrap();
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.answers.len(), 1);
+        assert!(matches!(
+            &response.answers[0],
+            DnsRecord::Hinfo { cpu, os, .. } if cpu == "RFC8482" && os.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn local_names_are_resolved_over_mdns() {
+        // Stands in for the multicast group, answering from another address
+        // like a responder on the network would
+        let group = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let responder = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let group_addr = group.local_addr().unwrap();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = group.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let reply = make_reply(
+                query.header.id,
+                "printer.local",
+                Ipv4Addr::new(192, 168, 1, 9),
+            );
+            responder.send_to(&reply, src).await.unwrap();
+        });
+
+        let mut server = Server::new(Config {
+            mdns: true,
+            query_timeout: Duration::from_millis(500),
+            ..Config::default()
+        })
+        .unwrap();
+        server.mdns_groups = vec![group_addr];
+
+        let (msg_buf, len) = make_query("printer.local", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(
+            response.a_answers().next(),
+            Some(Ipv4Addr::new(192, 168, 1, 9))
+        );
+    }
+
+    #[tokio::test]
+    async fn zone_is_answered_authoritatively() {
+        let server = Server::new(Config {
+            zone: Zone::parse("printer.internal A 300 10.0.0.7").unwrap(),
+            allow_recursion: false,
+            serve_cache_without_recursion: true,
+            ..Config::default()
+        })
+        .unwrap();
+
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let (msg_buf, len) = make_query("printer.internal", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert!(response.header.flags.authoritative_answer);
+        assert_eq!(
+            response.a_answers().next(),
+            Some(Ipv4Addr::new(10, 0, 0, 7))
+        );
+
+        // The name exists, so there's nothing to recurse for
+        let (msg_buf, len) = make_query("printer.internal", QueryType::Aaaa);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert!(response.header.flags.authoritative_answer);
+        assert!(response.answers.is_empty());
+
+        // Not in the zone
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert!(!response.header.flags.authoritative_answer);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn relayed_answer_is_not_authoritative() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let mut reply = parse_message(&make_reply(
+                query.header.id,
+                "example.com",
+                Ipv4Addr::new(1, 2, 3, 4),
+            ));
+            reply.header.flags.authoritative_answer = true;
+            stub.send_to(&reply.to_bytes(), src).await.unwrap();
+        });
+
+        let config = Config {
+            dns_cookies: false,
+            ..Config::default()
+        };
+        let mut server = Server::new(config.clone()).unwrap();
+        server.resolver = Resolver::new(config)
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!(response.header.flags.recursion_available);
+        assert!(!response.header.flags.authoritative_answer);
+
+        // Relayed again from the cache
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert!(!response.header.flags.authoritative_answer);
+    }
+
+    #[tokio::test]
+    async fn unreachable_forwarder_is_servfail() {
+        let listener = tokio::net::TcpListener::bind(("127.0.0.1", 0))
+            .await
+            .unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        drop(listener);
+
+        let server = Server::new(Config {
+            resolve_mode: ResolveMode::DnsOverHttps(url),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn chaos_version_query() {
+        let server = Server::new(Config {
+            server_version: Some("test 1.0".into()),
+            ..Config::default()
+        })
+        .unwrap();
+
+        let chaos_query = |name| {
+            let mut request = query(name, QueryType::Txt);
+            request.questions[0].qclass = QClass::Ch;
+            to_msg_buf(&request)
+        };
+
+        let (msg_buf, len) = chaos_query("version.bind");
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.questions[0].qclass, QClass::Ch);
+        assert!(matches!(
+            &response.answers[..],
+            [DnsRecord::Unknown { qtype: QueryType::Txt, class: QClass::Ch, rdata, .. }]
+                if rdata == b"\x08test 1.0"
+        ));
+
+        // Not configured
+        let (msg_buf, len) = chaos_query("hostname.bind");
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::Refused);
+
+        // Never resolved
+        let (msg_buf, len) = chaos_query("example.com");
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::Refused);
+    }
+
+    #[tokio::test]
+    async fn malformed_query_gets_formerr() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut msg_buf = [0u8; MAX_DNS_MSG_SIZE];
+        msg_buf[..3].copy_from_slice(&[0x12, 0x34, 0xff]);
+
+        let response = parse_message(&handle_query(&server, msg_buf, 3).await.unwrap());
+        assert_eq!(response.header.id, 0x1234);
+        assert!(response.header.flags.response);
+        assert_eq!(response.header.flags.rescode, ResultCode::FormErr);
+
+        // Truncated in the middle of the question
+        let (mut msg_buf, len) = make_query("example.com", QueryType::A);
+        msg_buf[len - 3..len].fill(0);
+        let response = parse_message(&handle_query(&server, msg_buf, len - 3).await.unwrap());
+        assert_eq!(response.header.id, 1234);
+        assert_eq!(response.header.flags.rescode, ResultCode::FormErr);
+
+        // Not even an ID to answer to
+        assert!(handle_query(&server, msg_buf, 1).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn truncated_query_is_rejected_unless_ignored() {
+        for ignore_truncated_queries in [false, true] {
+            let server = Server::new(Config {
+                ignore_truncated_queries,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+            let qname = Qname::try_from("example.com").unwrap();
+            server.cache.insert(&qname, QueryType::A, &cached).await;
+
+            let mut request = query("example.com", QueryType::A);
+            request.header.flags.truncated_message = true;
+            let (msg_buf, len) = to_msg_buf(&request);
+
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+            assert_eq!(response.questions.len(), 1);
+            if ignore_truncated_queries {
+                assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+                assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+            } else {
+                assert_eq!(response.header.flags.rescode, ResultCode::FormErr);
+                assert!(response.answers.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn binds_to_configured_address() {
+        // Find a free port
+        let port = UdpSocket::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let listen_addr = SocketAddr::new(Ipv4Addr::LOCALHOST.into(), port);
+        let server = Server::new(Config {
+            listen_addr,
+            query_timeout: Duration::from_millis(100),
+            max_retries: 0,
+            ..Config::default()
+        })
+        .unwrap();
+        let listener = server.bind().await.unwrap();
+        assert_eq!(listener.local_addr().unwrap(), listen_addr);
+
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+        let serving = tokio::spawn(serve(Arc::new(listener), Arc::new(server)));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(listen_addr).await.unwrap();
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        client.send(&msg_buf[..len]).await.unwrap();
+
+        let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+        let len = timeout(Duration::from_secs(1), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = parse_message(&buf[..len]);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+        serving.abort();
+    }
+
+    #[tokio::test]
+    async fn shutdown_drains_queries_in_flight() {
+        // Every query waits the whole `recursion_wait` before failing
+        let server = Arc::new(
+            Server::new(Config {
+                max_recursions: 0,
+                recursion_wait: Duration::from_millis(200),
+                ..Config::default()
+            })
+            .unwrap(),
+        );
+        let socket = UdpListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let server_addr = socket.local_addr().unwrap();
+        let (shutdown, shutdown_rx) = tokio::sync::oneshot::channel::<()>();
+        let serving = tokio::spawn(serve_until(Arc::new(socket), server, async {
+            let _ = shutdown_rx.await;
+        }));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server_addr).await.unwrap();
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        client.send(&msg_buf[..len]).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        shutdown.send(()).unwrap();
+        timeout(Duration::from_secs(1), serving)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+        let len = timeout(Duration::from_secs(1), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = parse_message(&buf[..len]);
+        assert_eq!(response.header.flags.rescode, ResultCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn stats_count_queries() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        // Only the first query is answered
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+            loop {
+                stub.recv_from(&mut buf).await.unwrap();
+            }
+        });
+
+        let mut server = Server::new(Config {
+            max_retries: 0,
+            ..Config::default()
+        })
+        .unwrap();
+        server.resolver = Resolver::new(server.config.clone())
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(100))
+            .with_stats(server.stats.clone());
+        server.resolver.port = port;
+
+        for name in ["example.com", "example.com", "example.net"] {
+            let (msg_buf, len) = make_query(name, QueryType::A);
+            handle_query(&server, msg_buf, len).await.unwrap();
+        }
+        let mut msg_buf = [0u8; MAX_DNS_MSG_SIZE];
+        msg_buf[..3].copy_from_slice(&[0x12, 0x34, 0xff]);
+        handle_query(&server, msg_buf, 3).await.unwrap();
+
+        let stats = server.stats();
+        assert_eq!(stats.queries, 4);
+        assert_eq!(stats.noerror, 2);
+        assert_eq!(stats.servfail, 1);
+        assert_eq!(stats.formerr, 1);
+        assert_eq!(stats.cache_hits, 1);
+        assert_eq!(stats.cache_misses, 2);
+        assert_eq!(stats.resolutions, 1);
+        assert!(stats.average_resolution_time() > Duration::ZERO);
+        assert_eq!(stats.upstream_errors, 1);
+        assert!(stats
+            .to_string()
+            .starts_with("queries=4 noerror=2 formerr=1 servfail=1 "));
+    }
+
+    async fn tcp_server(config: Config) -> SocketAddr {
+        let server = Server::new(config).unwrap();
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve_tcp(listener, Arc::new(server)));
+        addr
+    }
+
+    #[tokio::test]
+    async fn tcp_queries_are_answered() {
+        let addr = tcp_server(Config::default()).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        // Several queries on the same connection
+        for _ in 0..2 {
+            let query = query("example.com", QueryType::A).to_bytes();
+            let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+            framed.extend_from_slice(&query);
+            stream.write_all(&framed).await.unwrap();
+
+            let len = stream.read_u16().await.unwrap();
+            let mut buf = vec![0u8; len.into()];
+            stream.read_exact(&mut buf).await.unwrap();
+            let response = parse_message(&buf);
+            assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_query_over_512_bytes_is_answered() {
+        let addr = tcp_server(Config::default()).await;
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+
+        let mut request = query("example.com", QueryType::A);
+        request.resources.push(DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            // Padding
+            options: vec![EdnsOption::Unknown {
+                code: 12,
+                data: vec![0; 600],
+            }],
+        });
+        request.update_header();
+        let query = request.to_bytes();
+        assert!(query.len() > MAX_DNS_MSG_SIZE);
+
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&query);
+        stream.write_all(&framed).await.unwrap();
+
+        let len = stream.read_u16().await.unwrap();
+        let mut buf = vec![0u8; len.into()];
+        stream.read_exact(&mut buf).await.unwrap();
+        let response = parse_message(&buf);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn tcp_slow_clients_are_dropped() {
+        let addr = tcp_server(Config {
+            tcp_timeout: Duration::from_millis(200),
+            ..Config::default()
+        })
+        .await;
+
+        // A length in bounds but only part of the body, and one below the
+        // header size
+        for (prefix, min_elapsed) in [
+            (&[0x01, 0xf4, 0, 0][..], Duration::from_millis(200)),
+            (&[0xff, 0xff][..], Duration::from_millis(200)),
+            (&[0x00, 0x04, 0, 0, 0, 0][..], Duration::ZERO),
+        ] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            let started = Instant::now();
+            stream.write_all(prefix).await.unwrap();
+
+            // Closed with unread data, the connection is reset instead
+            let mut buf = [0u8; 16];
+            let closed = timeout(Duration::from_secs(1), stream.read(&mut buf))
+                .await
+                .unwrap();
+            assert!(
+                matches!(&closed, Ok(0))
+                    || matches!(&closed, Err(err) if err.kind() == io::ErrorKind::ConnectionReset),
+                "{prefix:?}: {closed:?}"
+            );
+            assert!(started.elapsed() >= min_elapsed, "{prefix:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn tcp_connections_over_the_limit_wait() {
+        let addr = tcp_server(Config {
+            max_tcp_connections: 1,
+            ..Config::default()
+        })
+        .await;
+
+        let query = query("example.com", QueryType::A).to_bytes();
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&query);
+
+        let first = TcpStream::connect(addr).await.unwrap();
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second.write_all(&framed).await.unwrap();
+        assert!(timeout(Duration::from_millis(200), second.read_u16())
+            .await
+            .is_err());
+
+        drop(first);
+        let len = timeout(Duration::from_secs(1), second.read_u16())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut buf = vec![0u8; len.into()];
+        second.read_exact(&mut buf).await.unwrap();
+        let response = parse_message(&buf);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+    }
+
+    #[tokio::test]
+    async fn serve_survives_handler_errors() {
+        let listener = UdpListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let server_addr = listener.local_addr().unwrap();
+
+        let server = Server::new(Config::default()).unwrap();
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let serving = tokio::spawn(serve(Arc::new(listener), Arc::new(server)));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(server_addr).await.unwrap();
+
+        // Too short to even answer, the handler fails
+        client.send(&[0xff]).await.unwrap();
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        client.send(&msg_buf[..len]).await.unwrap();
+
+        let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+        let len = timeout(Duration::from_secs(1), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let response = parse_message(&buf[..len]);
+        assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+        assert!(!serving.is_finished());
+
+        serving.abort();
+    }
+
+    #[tokio::test]
+    async fn response_is_truncated_to_buffer() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        for i in 0..10 {
+            cached.answers.push(DnsRecord::A {
+                domain: Qname::try_from("example.com").unwrap(),
+                addr: Ipv4Addr::new(192, 0, 2, i),
+                ttl: 60,
+            });
+        }
+        cached.update_header();
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let client = Ipv4Addr::LOCALHOST.into();
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let mut response = [0u8; 100];
+        let len = super::handle_query(&server, client, &msg_buf[..len], &mut response, true)
+            .await
+            .unwrap();
+
+        let response = parse_message(&response[..len]);
+        assert!(response.header.flags.truncated_message);
+        assert!(!response.answers.is_empty());
+        assert!(response.answers.len() < 11);
+
+        // Not even the question fits
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let mut response = [0u8; DNS_HEADER_LEN];
+        assert!(
+            super::handle_query(&server, client, &msg_buf[..len], &mut response, true)
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn udp_response_grows_to_advertised_size() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        for i in 0..40 {
+            cached.answers.push(DnsRecord::A {
+                domain: Qname::try_from("example.com").unwrap(),
+                addr: Ipv4Addr::new(192, 0, 2, i),
+                ttl: 60,
+            });
+        }
+        cached.update_header();
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let mut request = query("example.com", QueryType::A);
+        request.resources.push(DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options: Vec::new(),
+        });
+        request.update_header();
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = handle_query(&server, msg_buf, len).await.unwrap();
+        assert!((MAX_DNS_MSG_SIZE..=1232).contains(&response.len()));
+        let response = parse_message(&response);
+        assert!(!response.header.flags.truncated_message);
+        assert_eq!(response.answers.len(), 41);
+
+        // Without EDNS the client can only take 512 bytes
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = handle_query(&server, msg_buf, len).await.unwrap();
+        assert!(response.len() <= MAX_DNS_MSG_SIZE);
+        assert!(parse_message(&response).header.flags.truncated_message);
+    }
+
+    #[tokio::test]
+    async fn records_beyond_section_cap_are_dropped() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let mut reply = parse_message(&make_reply(
+                query.header.id,
+                "example.com",
+                Ipv4Addr::new(192, 0, 2, 0),
+            ));
+            for i in 1..8 {
+                reply.answers.push(DnsRecord::A {
+                    domain: Qname::try_from("example.com").unwrap(),
+                    addr: Ipv4Addr::new(192, 0, 2, i),
+                    ttl: 60,
+                });
+            }
+            reply.update_header();
+            stub.send_to(&reply.to_bytes(), src).await.unwrap();
+        });
+
+        let mut server = Server::new(Config {
+            max_section_records: 5,
+            ..Config::default()
+        })
+        .unwrap();
+        server.resolver = Resolver::new(Config {
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+        .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert!(response.header.flags.truncated_message);
+        assert_eq!(
+            response.a_answers().collect::<Vec<_>>(),
+            (0..5)
+                .map(|i| Ipv4Addr::new(192, 0, 2, i))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn ttl_above_max_is_capped() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            // 30 days
+            let mut reply = parse_message(&make_reply(
+                query.header.id,
+                "example.com",
+                Ipv4Addr::new(1, 2, 3, 4),
+            ));
+            reply.answers[0] = reply.answers[0].with_ttl(2_592_000);
+            stub.send_to(&reply.to_bytes(), src).await.unwrap();
+        });
+
+        let mut server = Server::new(Config {
+            max_ttl: 3600,
+            ..Config::default()
+        })
+        .unwrap();
+        server.resolver = Resolver::new(Config {
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+        .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.answers[0].ttl(), 3600);
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let hit = server.cache.get(&qname, QueryType::A).await.unwrap();
+        assert_eq!(hit.response.answers[0].ttl(), 3600);
+        assert!(!hit.expiring);
+    }
+
+    #[tokio::test]
+    async fn answered_queries_are_logged() {
+        #[derive(Default)]
+        struct Collect(std::sync::Mutex<Vec<QueryLogEntry>>);
+        impl QueryLog for Collect {
+            fn log(&self, entry: QueryLogEntry) {
+                self.0.lock().unwrap().push(entry);
+            }
+        }
+
+        let log = Arc::new(Collect::default());
+        let server = Server::new(Config::default())
+            .unwrap()
+            .with_query_log(log.clone());
+        let qname = Qname::try_from("example.com").unwrap();
+        let cached = parse_message(&make_reply(0, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        handle_query(&server, msg_buf, len).await.unwrap();
+        handle_query(&server, msg_buf, 3).await.unwrap();
+
+        let entries = log.0.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].client, IpAddr::from(Ipv4Addr::LOCALHOST));
+        assert_eq!(entries[0].question.as_ref().unwrap().name, qname);
+        assert_eq!(entries[0].rcode, ResultCode::NoError);
+        assert_eq!(entries[0].answers, 1);
+        assert!(entries[0].cache_hit);
+
+        assert!(entries[1].question.is_none());
+        assert_eq!(entries[1].rcode, ResultCode::FormErr);
+    }
+
+    #[tokio::test]
+    async fn round_robin_rotates_addresses() {
+        let server = Server::new(Config {
+            round_robin_answers: true,
+            ..Config::default()
+        })
+        .unwrap();
+
+        let mut cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(192, 0, 2, 1)));
+        for i in 2..=3 {
+            cached.answers.push(DnsRecord::A {
+                domain: Qname::try_from("example.com").unwrap(),
+                addr: Ipv4Addr::new(192, 0, 2, i),
+                ttl: 60,
+            });
+        }
+        cached.update_header();
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let mut firsts = Vec::new();
+        for _ in 0..4 {
+            let (msg_buf, len) = make_query("example.com", QueryType::A);
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+            assert_eq!(response.answers.len(), 3);
+            firsts.push(response.a_answers().next().unwrap().octets()[3]);
+        }
+        assert_eq!(firsts, [1, 2, 3, 1]);
+    }
+
+    #[tokio::test]
+    async fn refused_zones_are_refused() {
+        let server = Server::new(Config {
+            refused_zones: vec![Qname::try_from("example.com").unwrap()],
+            ..Config::default()
+        })
+        .unwrap();
+
+        let cached = parse_message(&make_reply(1, "www.example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("www.example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+        let cached = parse_message(&make_reply(1, "example.net", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.net").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        for name in ["example.com", "www.example.com"] {
+            let (msg_buf, len) = make_query(name, QueryType::A);
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+            assert_eq!(response.header.flags.rescode, ResultCode::Refused, "{name}");
+            assert!(response.answers.is_empty());
+        }
+
+        let (msg_buf, len) = make_query("example.net", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+    }
+
+    #[tokio::test]
+    async fn iquery_opcode_is_not_implemented() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let mut request = query("example.com", QueryType::A);
+        request.header.flags.opcode = Opcode::IQuery;
+        let (msg_buf, len) = to_msg_buf(&request);
+
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.opcode, Opcode::IQuery);
+        assert_eq!(response.header.flags.rescode, ResultCode::NoTimp);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn status_opcode_is_not_implemented() {
+        let server = Server::new(Config::default()).unwrap();
+
+        // Would be answered from the cache if it were treated as a query
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let mut request = query("example.com", QueryType::A);
+        request.header.flags.opcode = Opcode::Status;
+        let (msg_buf, len) = to_msg_buf(&request);
+
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.id, request.header.id);
+        assert!(response.header.flags.response);
+        assert_eq!(response.header.flags.opcode, Opcode::Status);
+        assert_eq!(response.header.flags.rescode, ResultCode::NoTimp);
+        assert_eq!(response.questions.len(), 1);
+        assert_eq!(response.questions[0].name, qname);
+        assert!(response.answers.is_empty());
+    }
+
+    #[tokio::test]
+    async fn diagnostics_are_echoed_when_enabled() {
+        let diagnostics = |response: &DnsMessage| -> Vec<Vec<u8>> {
+            response
+                .resources
+                .iter()
+                .filter_map(|rec| match rec {
+                    DnsRecord::Txt { strings, .. } => Some(strings.concat()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        for echo_diagnostics in [false, true] {
+            let server = Server::new(Config {
+                echo_diagnostics,
+                ..Config::default()
+            })
+            .unwrap();
+
+            let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+            let qname = Qname::try_from("example.com").unwrap();
+            server.cache.insert(&qname, QueryType::A, &cached).await;
+
+            let (msg_buf, len) = make_query("example.com", QueryType::A);
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+            assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+
+            let texts = diagnostics(&response);
+            if echo_diagnostics {
+                assert_eq!(texts.len(), 1);
+                assert!(texts[0].starts_with(b"cache=hit; elapsed="));
+            } else {
+                assert!(texts.is_empty());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn cname_answer_keeps_original_question() {
+        let server = Server::new(Config::default()).unwrap();
+
+        let qname = Qname::try_from("www.example.com").unwrap();
+        let canonical = Qname::try_from("example.com").unwrap();
+
+        let mut cached = DnsMessage::new();
+        cached.header.flags.response = true;
+        cached.answers.push(DnsRecord::Cname {
+            domain: qname.clone(),
+            host: canonical.clone(),
+            ttl: 300,
+        });
+        cached.answers.push(DnsRecord::A {
+            domain: canonical,
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let (msg_buf, len) = make_query("www.example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.questions.len(), 1);
+        assert_eq!(response.questions[0].name, qname);
+        assert_eq!(response.questions[0].qtype, QueryType::A);
+        assert!(matches!(response.answers[0], DnsRecord::Cname { .. }));
+        assert_eq!(
+            response.a_answers().next(),
+            Some(Ipv4Addr::new(192, 0, 2, 1))
+        );
+        response.validate_structure().unwrap();
+    }
+
+    #[tokio::test]
+    async fn stable_order_gives_identical_responses() {
+        let server = Server::new(Config {
+            stable_answer_order: true,
+            ..Config::default()
+        })
+        .unwrap();
+
+        let qname = Qname::try_from("example.com").unwrap();
+        let mut cached = DnsMessage::new();
+        cached.header.flags.response = true;
+        for x in 1..=4 {
+            cached.answers.push(DnsRecord::A {
+                domain: qname.clone(),
+                addr: Ipv4Addr::new(192, 0, 2, x),
+                ttl: 300,
+            });
+        }
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let first = handle_query(&server, msg_buf, len).await.unwrap();
+        for _ in 0..10 {
+            assert_eq!(handle_query(&server, msg_buf, len).await.unwrap(), first);
+        }
+
+        let roots = || ROOT_SERVERS.into_iter();
+        for _ in 0..10 {
+            assert_eq!(
+                server.resolver.upstreams.rank(roots()),
+                roots().collect::<Vec<_>>()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn unknown_edns_options_are_relayed() {
+        let client_option = EdnsOption::Unknown {
+            code: 65001,
+            data: vec![1, 2, 3],
+        };
+        let upstream_option = EdnsOption::Unknown {
+            code: 65002,
+            data: vec![4, 5],
+        };
+        let cookie = EdnsOption::Unknown {
+            code: 10,
+            data: vec![0; 8],
+        };
+        let opt = |options| DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags: 0,
+            options,
+        };
+
+        // Towards the nameserver
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+            query
+        });
+
+        let config = Config::default();
+        let qname = Qname::try_from("example.com").unwrap();
+        let options = [client_option.clone()];
+        lookup(
+            &UdpTransport::new(&config),
+            &qname,
+            QueryType::A,
+            (Ipv4Addr::LOCALHOST.into(), port),
+            &options,
+            DnssecFlags::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(stub.await.unwrap().edns_options(), &options);
+
+        // Back to the client
+        let server = Server::new(Config::default()).unwrap();
+        let mut cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        cached
+            .resources
+            .push(opt(vec![upstream_option.clone(), cookie]));
+        cached.update_header();
+        server.cache.insert(&qname, QueryType::A, &cached).await;
+
+        let mut request = query("example.com", QueryType::A);
+        request.resources.push(opt(vec![client_option]));
+        request.update_header();
+
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.edns_options(), &[upstream_option]);
+    }
+
+    #[tokio::test]
+    async fn reserved_bit_is_cleared_or_rejected() {
+        let mut request = query("example.com", QueryType::A);
+        request.header.flags.z = true;
+        let cached = parse_message(&make_reply(1, "example.com", Ipv4Addr::new(1, 2, 3, 4)));
+        let qname = Qname::try_from("example.com").unwrap();
+
+        for reject_z_queries in [false, true] {
+            let server = Server::new(Config {
+                reject_z_queries,
+                ..Config::default()
+            })
+            .unwrap();
+            server.cache.insert(&qname, QueryType::A, &cached).await;
+
+            let (msg_buf, len) = to_msg_buf(&request);
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+            assert!(!response.header.flags.z);
+            if reject_z_queries {
+                assert_eq!(response.header.flags.rescode, ResultCode::FormErr);
+                assert!(response.answers.is_empty());
+            } else {
+                assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+                assert_eq!(response.answers.len(), 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn checking_disabled_is_passed_upstream() {
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        let stub = tokio::spawn(async move {
+            let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+            let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+            let query = parse_message(&buf[..len]);
+            let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+            stub.send_to(&reply, src).await.unwrap();
+            query
+        });
+
+        let config = Config {
+            dns_cookies: false,
+            ..Config::default()
+        };
+        let mut server = Server::new(config.clone()).unwrap();
+        server.resolver = Resolver::new(config)
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let mut request = query("example.com", QueryType::A);
+        request.header.flags.checking_disabled = true;
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+        assert!(stub.await.unwrap().header.flags.checking_disabled);
+        assert_eq!(response.answers.len(), 1);
+        // Not validated, so not to be handed to clients which want that
+        let qname = Qname::try_from("example.com").unwrap();
+        assert!(server.cache.get(&qname, QueryType::A).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn dnssec_ok_is_passed_upstream() {
+        let opt = |flags| DnsRecord::Opt {
+            udp_payload_size: 1232,
+            extended_rcode: 0,
+            version: 0,
+            flags,
+            options: Vec::new(),
+        };
+        let rrsig = DnsRecord::Rrsig {
+            domain: Qname::try_from("example.com").unwrap(),
+            type_covered: QueryType::A,
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 60,
+            expiration: 1_700_604_800,
+            inception: 1_700_000_000,
+            key_tag: 4534,
+            signer: Qname::try_from("example.com").unwrap(),
+            signature: vec![0xde, 0xad],
+            ttl: 60,
+        };
+
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        let stub = tokio::spawn({
+            let rrsig = rrsig.clone();
+            async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                let query = parse_message(&buf[..len]);
+
+                let mut reply = parse_message(&make_reply(
+                    query.header.id,
+                    "example.com",
+                    Ipv4Addr::new(1, 2, 3, 4),
+                ));
+                reply.answers.push(rrsig);
+                reply.resources.push(opt(DNSSEC_OK));
+                reply.update_header();
+                stub.send_to(&reply.to_bytes(), src).await.unwrap();
+                query
+            }
+        });
+
+        let mut server = Server::new(Config {
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .unwrap();
+        server.resolver = Resolver::new(Config {
+            dns_cookies: false,
+            ..Config::default()
+        })
+        .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+        .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let mut request = query("example.com", QueryType::A);
+        request.resources.push(opt(DNSSEC_OK));
+        request.update_header();
+        let (msg_buf, len) = to_msg_buf(&request);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+
+        assert!(stub.await.unwrap().dnssec_ok());
+        assert!(response.dnssec_ok());
+        assert_eq!(response.answers.len(), 2);
+        assert_eq!(response.answers[1], rrsig);
+
+        // Bypasses the cache both ways
+        let qname = Qname::try_from("example.com").unwrap();
+        assert!(server.cache.get(&qname, QueryType::A).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn popular_expiring_entry_is_prefetched_once() {
+        use std::sync::atomic::AtomicUsize;
+
+        use futures::future::{self, BoxFuture};
+
+        use crate::cache::{CacheKey, CachedEntry};
+
+        /// Backend with a popular entry 55 of its 60 seconds old, counting
+        /// how often it is replaced.
+        struct Aging(Arc<AtomicUsize>);
+        impl CacheBackend for Aging {
+            fn get<'a>(&'a self, _: &'a CacheKey) -> BoxFuture<'a, Option<CachedEntry>> {
+                Box::pin(future::ready(Some(CachedEntry {
+                    response: parse_message(&make_reply(
+                        1,
+                        "example.com",
+                        Ipv4Addr::new(1, 2, 3, 4),
+                    )),
+                    age: Duration::from_secs(55),
+                    hits: 10,
+                })))
+            }
+
+            fn put<'a>(&'a self, _: CacheKey, _: DnsMessage, _: Duration) -> BoxFuture<'a, ()> {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Box::pin(future::ready(()))
+            }
+        }
+
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        let lookups = Arc::new(AtomicUsize::new(0));
+        tokio::spawn({
+            let lookups = lookups.clone();
+            async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                loop {
+                    let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                    lookups.fetch_add(1, Ordering::SeqCst);
+                    let query = parse_message(&buf[..len]);
+                    let reply =
+                        make_reply(query.header.id, "example.com", Ipv4Addr::new(5, 6, 7, 8));
+                    stub.send_to(&reply, src).await.unwrap();
+                }
+            }
+        });
+
+        let config = Config {
+            dns_cookies: false,
+            ..Config::default()
+        };
+        let puts = Arc::new(AtomicUsize::new(0));
+        let mut server = Server::new(config.clone())
+            .unwrap()
+            .with_cache_backend(Box::new(Aging(puts.clone())));
+        server.resolver = Resolver::new(config)
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+        let server = Arc::new(server);
+
+        // Answered from the cache right away, the refresh is only queued
+        for _ in 0..5 {
+            let (msg_buf, len) = make_query("example.com", QueryType::A);
+            let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+            assert_eq!(response.a_answers().next(), Some(Ipv4Addr::new(1, 2, 3, 4)));
+            assert_eq!(response.answers[0].ttl(), 5);
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 0);
+
+        tokio::spawn(run_prefetches(server.clone()));
+        timeout(Duration::from_secs(2), async {
+            while puts.load(Ordering::SeqCst) == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(puts.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn cache_backend_is_asked_before_recursing() {
+        use futures::future::{self, BoxFuture};
+
+        use crate::cache::{CacheKey, CachedEntry};
+
+        /// Backend which never has an entry, recording what it is asked.
+        #[derive(Default)]
+        struct Recorder(Arc<std::sync::Mutex<Vec<String>>>);
+        impl CacheBackend for Recorder {
+            fn get<'a>(&'a self, key: &'a CacheKey) -> BoxFuture<'a, Option<CachedEntry>> {
+                let (qname, qtype) = key;
+                self.0.lock().unwrap().push(format!("get {qname} {qtype}"));
+                Box::pin(future::ready(None))
+            }
+
+            fn put<'a>(
+                &'a self,
+                key: CacheKey,
+                response: DnsMessage,
+                ttl: Duration,
+            ) -> BoxFuture<'a, ()> {
+                let (qname, qtype) = key;
+                let answers = response.answers.len();
+                let ttl = ttl.as_secs();
+                self.0
+                    .lock()
+                    .unwrap()
+                    .push(format!("put {qname} {qtype} answers={answers} ttl={ttl}"));
+                Box::pin(future::ready(()))
+            }
+        }
+
+        let stub = UdpSocket::bind(("127.0.0.1", 0)).await.unwrap();
+        let port = stub.local_addr().unwrap().port();
+        let calls = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let stub = tokio::spawn({
+            let calls = calls.clone();
+            async move {
+                let mut buf = [0u8; MAX_DNS_MSG_SIZE];
+                let (len, src) = stub.recv_from(&mut buf).await.unwrap();
+                calls.lock().unwrap().push("recurse".to_string());
+                let query = parse_message(&buf[..len]);
+                let reply = make_reply(query.header.id, "example.com", Ipv4Addr::new(1, 2, 3, 4));
+                stub.send_to(&reply, src).await.unwrap();
+            }
+        });
+
+        let config = Config {
+            dns_cookies: false,
+            ..Config::default()
+        };
+        let mut server = Server::new(config.clone())
+            .unwrap()
+            .with_cache_backend(Box::new(Recorder(calls.clone())));
+        server.resolver = Resolver::new(config)
+            .with_root_servers(vec![Ipv4Addr::LOCALHOST.into()])
+            .with_timeout(Duration::from_millis(500));
+        server.resolver.port = port;
+
+        let (msg_buf, len) = make_query("example.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        stub.await.unwrap();
+
+        assert_eq!(response.answers.len(), 1);
+        assert_eq!(
+            *calls.lock().unwrap(),
+            [
+                "get example.com A",
+                "recurse",
+                "put example.com A answers=1 ttl=60",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn answer_in_blocked_net_is_rewritten() {
+        let blocked_answer_nets = vec!["192.0.2.0/24".parse().unwrap()];
+        async fn cache_answer(server: &Server, name: &str, addr: Ipv4Addr) {
+            let cached = parse_message(&make_reply(1, name, addr));
+            let qname = Qname::try_from(name).unwrap();
+            server.cache.insert(&qname, QueryType::A, &cached).await;
+        }
+
+        let server = Server::new(Config {
+            blocked_answer_nets: blocked_answer_nets.clone(),
+            ..Config::default()
+        })
+        .unwrap();
+        cache_answer(&server, "evil.com", Ipv4Addr::new(192, 0, 2, 66)).await;
+        cache_answer(&server, "good.com", Ipv4Addr::new(198, 51, 100, 1)).await;
+
+        let (msg_buf, len) = make_query("evil.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NxDomain);
+        assert!(response.answers.is_empty());
+
+        let (msg_buf, len) = make_query("good.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(
+            response.a_answers().next(),
+            Some(Ipv4Addr::new(198, 51, 100, 1))
+        );
+
+        let sinkhole = Ipv4Addr::new(10, 0, 0, 1);
+        let server = Server::new(Config {
+            blocked_answer_nets,
+            blocked_answer_action: BlockAction::Sinkhole {
+                v4: sinkhole,
+                v6: Ipv6Addr::LOCALHOST,
+            },
+            ..Config::default()
+        })
+        .unwrap();
+        cache_answer(&server, "evil.com", Ipv4Addr::new(192, 0, 2, 66)).await;
+
+        let (msg_buf, len) = make_query("evil.com", QueryType::A);
+        let response = parse_message(&handle_query(&server, msg_buf, len).await.unwrap());
+        assert_eq!(response.header.flags.rescode, ResultCode::NoError);
+        assert_eq!(response.a_answers().collect::<Vec<_>>(), [sinkhole]);
+    }
+}
@@ -0,0 +1,170 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::ops::Deref;
+use std::sync::Mutex;
+
+use rand::Rng;
+use tokio::net::UdpSocket;
+
+/// Queries a socket is used for before it is closed, so the set of source
+/// ports in use keeps changing.
+const MAX_USES: u32 = 64;
+
+struct Idle {
+    socket: UdpSocket,
+    uses: u32,
+}
+
+/// UDP sockets for outbound queries, bound once and reused.
+///
+/// Binding a fresh socket per lookup costs a `socket`, a `bind` and a
+/// `close` call, which under recursion adds up to dozens of syscalls per
+/// client query. Reusing sockets leaves only the sends and receives.
+///
+/// Every socket sits on its own random ephemeral port, and an idle one is
+/// picked at random for each query, so source ports stay unpredictable.
+pub(crate) struct SocketPool {
+    v4: Mutex<Vec<Idle>>,
+    v6: Mutex<Vec<Idle>>,
+    capacity: usize,
+}
+
+impl SocketPool {
+    /// Pool keeping up to `capacity` idle sockets per address family.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            v4: Mutex::new(Vec::new()),
+            v6: Mutex::new(Vec::new()),
+            capacity,
+        }
+    }
+
+    fn idle(&self, v6: bool) -> &Mutex<Vec<Idle>> {
+        if v6 {
+            &self.v6
+        } else {
+            &self.v4
+        }
+    }
+
+    /// Hand out a socket able to reach `server`, binding a new one if none
+    /// is idle.
+    pub async fn get(&self, server: IpAddr) -> io::Result<PooledSocket<'_>> {
+        let v6 = server.is_ipv6();
+
+        let idle = {
+            let mut idle = self.idle(v6).lock().unwrap();
+            if idle.is_empty() {
+                None
+            } else {
+                let i = rand::thread_rng().gen_range(0..idle.len());
+                Some(idle.swap_remove(i))
+            }
+        };
+        let (socket, uses) = match idle {
+            Some(Idle { socket, uses }) => (socket, uses),
+            None => (bind(server).await?, 0),
+        };
+
+        Ok(PooledSocket {
+            socket: Some(socket),
+            uses: uses + 1,
+            v6,
+            pool: self,
+        })
+    }
+}
+
+/// Bind a socket on a random port of the unspecified address of the family
+/// of `server`.
+pub(crate) async fn bind(server: IpAddr) -> io::Result<UdpSocket> {
+    let local: IpAddr = match server {
+        IpAddr::V4(_) => Ipv4Addr::UNSPECIFIED.into(),
+        IpAddr::V6(_) => Ipv6Addr::UNSPECIFIED.into(),
+    };
+    UdpSocket::bind((local, 0)).await
+}
+
+/// Socket borrowed from a [`SocketPool`], returned to it on drop.
+pub(crate) struct PooledSocket<'a> {
+    socket: Option<UdpSocket>,
+    uses: u32,
+    v6: bool,
+    pool: &'a SocketPool,
+}
+
+impl Deref for PooledSocket<'_> {
+    type Target = UdpSocket;
+
+    fn deref(&self) -> &UdpSocket {
+        self.socket.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledSocket<'_> {
+    fn drop(&mut self) {
+        let Some(socket) = self.socket.take() else {
+            return;
+        };
+        if self.uses >= MAX_USES {
+            return;
+        }
+
+        // Late responses to an abandoned query would only be skipped by the
+        // next one anyway
+        let mut buf = [0u8; 1];
+        while socket.try_recv_from(&mut buf).is_ok() {}
+
+        let mut idle = self.pool.idle(self.v6).lock().unwrap();
+        if idle.len() < self.pool.capacity {
+            idle.push(Idle {
+                socket,
+                uses: self.uses,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{SocketPool, MAX_USES};
+
+    const SERVER: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    #[tokio::test]
+    async fn sockets_are_distinct_and_recycled() {
+        let pool = SocketPool::new(2);
+
+        let first = pool.get(SERVER).await.unwrap();
+        let second = pool.get(SERVER).await.unwrap();
+        let ports = HashSet::from([
+            first.local_addr().unwrap().port(),
+            second.local_addr().unwrap().port(),
+        ]);
+        assert_eq!(ports.len(), 2);
+
+        drop(first);
+        drop(second);
+        for _ in 0..10 {
+            let socket = pool.get(SERVER).await.unwrap();
+            assert!(ports.contains(&socket.local_addr().unwrap().port()));
+        }
+    }
+
+    #[tokio::test]
+    async fn worn_out_sockets_are_replaced() {
+        let pool = SocketPool::new(1);
+
+        let port = pool.get(SERVER).await.unwrap().local_addr().unwrap().port();
+        for _ in 1..MAX_USES {
+            let socket = pool.get(SERVER).await.unwrap();
+            assert_eq!(socket.local_addr().unwrap().port(), port);
+        }
+
+        // Closed after its last use
+        assert!(pool.v4.lock().unwrap().is_empty());
+    }
+}
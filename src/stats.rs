@@ -0,0 +1,151 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::time::Duration;
+
+use crate::packet::ResultCode;
+
+/// Result codes counted on their own, anything else is counted as other.
+const COUNTED_RCODES: [ResultCode; 6] = [
+    ResultCode::NoError,
+    ResultCode::FormErr,
+    ResultCode::ServFail,
+    ResultCode::NxDomain,
+    ResultCode::NoTimp,
+    ResultCode::Refused,
+];
+
+/// Counters of the queries handled so far.
+///
+/// They are independent of each other and only ever incremented, so relaxed
+/// atomics are enough and handlers never wait on one another to update them.
+/// A snapshot may thus be off by the queries being answered while it's taken.
+#[derive(Debug, Default)]
+pub struct Stats {
+    queries: AtomicU64,
+    /// Responses by result code, in the order of `COUNTED_RCODES` plus one
+    /// for all others.
+    responses: [AtomicU64; COUNTED_RCODES.len() + 1],
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    resolutions: AtomicU64,
+    resolution_micros: AtomicU64,
+    upstream_errors: AtomicU64,
+}
+
+impl Stats {
+    pub(crate) fn record_query(&self) {
+        self.queries.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_response(&self, rescode: ResultCode) {
+        let i = COUNTED_RCODES
+            .iter()
+            .position(|&x| x == rescode)
+            .unwrap_or(COUNTED_RCODES.len());
+        self.responses[i].fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_cache_hit(&self) {
+        self.cache_hits.fetch_add(1, Relaxed);
+    }
+
+    pub(crate) fn record_cache_miss(&self) {
+        self.cache_misses.fetch_add(1, Relaxed);
+    }
+
+    /// Count a question answered by a nameserver or forwarder after `elapsed`.
+    pub(crate) fn record_resolution(&self, elapsed: Duration) {
+        self.resolutions.fetch_add(1, Relaxed);
+        self.resolution_micros
+            .fetch_add(elapsed.as_micros() as u64, Relaxed);
+    }
+
+    /// Count a query to a nameserver or forwarder which failed.
+    pub(crate) fn record_upstream_error(&self) {
+        self.upstream_errors.fetch_add(1, Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let responses = |rescode| {
+            let i = COUNTED_RCODES.iter().position(|&x| x == rescode).unwrap();
+            self.responses[i].load(Relaxed)
+        };
+
+        StatsSnapshot {
+            queries: self.queries.load(Relaxed),
+            noerror: responses(ResultCode::NoError),
+            formerr: responses(ResultCode::FormErr),
+            servfail: responses(ResultCode::ServFail),
+            nxdomain: responses(ResultCode::NxDomain),
+            notimp: responses(ResultCode::NoTimp),
+            refused: responses(ResultCode::Refused),
+            other_rcodes: self.responses[COUNTED_RCODES.len()].load(Relaxed),
+            cache_hits: self.cache_hits.load(Relaxed),
+            cache_misses: self.cache_misses.load(Relaxed),
+            resolutions: self.resolutions.load(Relaxed),
+            resolution_time: Duration::from_micros(self.resolution_micros.load(Relaxed)),
+            upstream_errors: self.upstream_errors.load(Relaxed),
+        }
+    }
+}
+
+/// Values of [`Stats`] at one point in time.
+///
+/// Displayed as space-separated `key=value` pairs, which log collectors
+/// parse as they are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StatsSnapshot {
+    /// Queries received, including malformed ones.
+    pub queries: u64,
+    pub noerror: u64,
+    pub formerr: u64,
+    pub servfail: u64,
+    pub nxdomain: u64,
+    pub notimp: u64,
+    pub refused: u64,
+    pub other_rcodes: u64,
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+    /// Questions answered by a nameserver or forwarder.
+    pub resolutions: u64,
+    /// Time spent on all `resolutions` together.
+    pub resolution_time: Duration,
+    /// Queries to nameservers or forwarders which failed.
+    pub upstream_errors: u64,
+}
+
+impl StatsSnapshot {
+    pub fn average_resolution_time(&self) -> Duration {
+        match u32::try_from(self.resolutions) {
+            Ok(0) => Duration::ZERO,
+            Ok(resolutions) => self.resolution_time / resolutions,
+            Err(_) => Duration::from_secs_f64(
+                self.resolution_time.as_secs_f64() / self.resolutions as f64,
+            ),
+        }
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queries={} noerror={} formerr={} servfail={} nxdomain={} notimp={} refused={} \
+             other_rcodes={} cache_hits={} cache_misses={} resolutions={} \
+             avg_resolution_ms={:.1} upstream_errors={}",
+            self.queries,
+            self.noerror,
+            self.formerr,
+            self.servfail,
+            self.nxdomain,
+            self.notimp,
+            self.refused,
+            self.other_rcodes,
+            self.cache_hits,
+            self.cache_misses,
+            self.resolutions,
+            self.average_resolution_time().as_secs_f64() * 1000.0,
+            self.upstream_errors,
+        )
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use futures::future::BoxFuture;
+
+use crate::packet::byte_buffer::MAX_DNS_MSG_SIZE;
+use crate::packet::message::DnsMessage;
+use crate::packet::qname::Qname;
+use crate::packet::query_type::QueryType;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record::DnsRecord;
+use crate::transport::Transport;
+
+pub(crate) fn make_reply(id: u16, name: &str, addr: Ipv4Addr) -> Vec<u8> {
+    let name = Qname::try_from(name).unwrap();
+
+    let mut packet = DnsMessage::new();
+    packet.header.id = id;
+    packet.header.flags.response = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(name.clone(), QueryType::A));
+    packet.answers.push(DnsRecord::A {
+        domain: name,
+        addr,
+        ttl: 60,
+    });
+    packet.update_header();
+
+    packet.to_bytes()
+}
+
+pub(crate) fn query(name: &str, qtype: QueryType) -> DnsMessage {
+    let mut packet = DnsMessage::new();
+    packet.header.id = 1234;
+    packet.header.flags.recursion_desired = true;
+    packet
+        .questions
+        .push(DnsQuestion::new(Qname::try_from(name).unwrap(), qtype));
+    packet.update_header();
+    packet
+}
+
+pub(crate) fn make_query(name: &str, qtype: QueryType) -> ([u8; MAX_DNS_MSG_SIZE], usize) {
+    to_msg_buf(&query(name, qtype))
+}
+
+pub(crate) fn to_msg_buf(packet: &DnsMessage) -> ([u8; MAX_DNS_MSG_SIZE], usize) {
+    let bytes = packet.to_bytes();
+    let mut msg_buf = [0u8; MAX_DNS_MSG_SIZE];
+    msg_buf[..bytes.len()].copy_from_slice(&bytes);
+    (msg_buf, bytes.len())
+}
+
+pub(crate) fn parse_message(bytes: &[u8]) -> DnsMessage {
+    DnsMessage::from_bytes(bytes).unwrap()
+}
+
+/// Transport answering from canned responses per nameserver, without
+/// any sockets. Records which nameservers were asked.
+pub(crate) struct MockTransport {
+    pub responses: HashMap<IpAddr, DnsMessage>,
+    pub queried: Arc<Mutex<Vec<SocketAddr>>>,
+}
+
+impl Transport for MockTransport {
+    fn query<'a>(
+        &'a self,
+        request: &'a DnsMessage,
+        server: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<DnsMessage>> {
+        self.queried.lock().unwrap().push(server);
+        let result = match self.responses.get(&server.ip()) {
+            Some(response) => {
+                let mut response = response.clone();
+                response.header.id = request.header.id;
+                response.header.flags.response = true;
+                response.questions = request.questions.clone();
+                response.update_header();
+                Ok(response)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "nameserver did not respond",
+            )),
+        };
+        Box::pin(futures::future::ready(result))
+    }
+}
+
+/// Response delegating `zone` to the nameserver `ns`, with an A record of
+/// `glue` for it if given. Meant to be sent with [`DnsMessage::respond_to`]
+/// or by a [`MockTransport`].
+pub(crate) fn referral(zone: &str, ns: &str, glue: Option<Ipv4Addr>) -> DnsMessage {
+    let ns = Qname::try_from(ns).unwrap();
+
+    let mut packet = DnsMessage::new();
+    packet.header.flags.response = true;
+    packet.authorities.push(DnsRecord::Ns {
+        domain: Qname::try_from(zone).unwrap(),
+        host: ns.clone(),
+        ttl: 60,
+    });
+    if let Some(addr) = glue {
+        packet.resources.push(DnsRecord::A {
+            domain: ns,
+            addr,
+            ttl: 60,
+        });
+    }
+    packet.update_header();
+    packet
+}
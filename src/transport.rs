@@ -0,0 +1,229 @@
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use futures::future::BoxFuture;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, timeout_at, Instant};
+use tracing::debug;
+
+use crate::config::Config;
+use crate::cookies;
+use crate::packet::byte_buffer::MAX_DNS_MSG_SIZE;
+use crate::packet::message::DnsMessage;
+use crate::packet::DNS_HEADER_LEN;
+use crate::resolver::is_same_question;
+use crate::socket_pool::SocketPool;
+
+/// Way of getting a query to a nameserver and its response back.
+///
+/// Implementations only hand out responses matching the ID and question of
+/// the request, anything else is skipped or turned into an error.
+pub trait Transport: Send + Sync {
+    fn query<'a>(
+        &'a self,
+        request: &'a DnsMessage,
+        server: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<DnsMessage>>;
+}
+
+/// Queries over UDP from a pool of sockets, retried over TCP if the
+/// response is truncated.
+///
+/// With `randomize_qname_case`, the case of the question name is randomized
+/// on the wire and has to be echoed exactly. If the request holds a cookie,
+/// responses have to echo it.
+pub struct UdpTransport {
+    sockets: SocketPool,
+    tcp: TcpTransport,
+    max_retries: usize,
+    query_timeout: Duration,
+    randomize_qname_case: bool,
+}
+
+impl UdpTransport {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            sockets: SocketPool::new(config.outbound_sockets),
+            tcp: TcpTransport::new(config.query_timeout),
+            max_retries: config.max_retries,
+            query_timeout: config.query_timeout,
+            randomize_qname_case: config.randomize_qname_case,
+        }
+    }
+
+    async fn exchange(&self, request: &DnsMessage, server: SocketAddr) -> io::Result<DnsMessage> {
+        let socket = self.sockets.get(server.ip()).await?;
+        let mut req_buffer = request.to_bytes();
+
+        // The question name comes right after the header, uncompressed, in the
+        // response just as in the query
+        let cased_qname = match request.questions.first() {
+            Some(question) if self.randomize_qname_case => {
+                let wire = question.name.to_wire_random_case();
+                req_buffer[DNS_HEADER_LEN..DNS_HEADER_LEN + wire.len()].copy_from_slice(&wire);
+                Some(wire)
+            }
+            _ => None,
+        };
+
+        let mut res_buffer = [0u8; MAX_DNS_MSG_SIZE];
+        let mut question_mismatch = false;
+        let mut cookie_error = None;
+
+        for _ in 0..=self.max_retries {
+            socket.send_to(&req_buffer, server).await?;
+
+            let deadline = Instant::now() + self.query_timeout;
+
+            // Anything arriving on the socket may be spoofed or a late reply to some
+            // previous query, so keep reading until we get a datagram which is really
+            // an answer to our question or run out of time.
+            loop {
+                let (len, src) = match timeout_at(deadline, socket.recv_from(&mut res_buffer)).await
+                {
+                    Ok(received) => received?,
+                    Err(_) => break,
+                };
+
+                if src != server {
+                    continue;
+                }
+
+                let res_buffer = &res_buffer[..len];
+                let response = match DnsMessage::from_bytes(res_buffer) {
+                    Ok(response) => response,
+                    Err(_) => continue,
+                };
+
+                if response.header.id != request.header.id {
+                    continue;
+                }
+
+                // Matching ID is not enough, the question has to be echoed back as well.
+                if !is_same_question(&response.questions, &request.questions) {
+                    question_mismatch = true;
+                    continue;
+                }
+                if let Some(wire) = &cased_qname {
+                    if res_buffer.get(DNS_HEADER_LEN..DNS_HEADER_LEN + wire.len()) != Some(wire) {
+                        question_mismatch = true;
+                        continue;
+                    }
+                }
+
+                if let Err(err) = cookies::check_echo(request.edns_options(), &response) {
+                    debug!(%server, "discarding response: {err}");
+                    cookie_error = Some(err);
+                    continue;
+                }
+
+                // Part of the answer didn't fit into the datagram
+                if response.header.flags.truncated_message {
+                    debug!(%server, "response is truncated, retrying over TCP");
+                    return self.tcp.exchange(request, server).await;
+                }
+
+                return Ok(response);
+            }
+        }
+
+        if question_mismatch {
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "response question does not match the query",
+            ))
+        } else if let Some(err) = cookie_error {
+            Err(io::Error::new(io::ErrorKind::InvalidData, err))
+        } else {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "nameserver did not respond",
+            ))
+        }
+    }
+}
+
+impl Transport for UdpTransport {
+    fn query<'a>(
+        &'a self,
+        request: &'a DnsMessage,
+        server: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<DnsMessage>> {
+        Box::pin(self.exchange(request, server))
+    }
+}
+
+/// Queries over a TCP connection of their own, where messages are prefixed
+/// with their length (RFC 1035, section 4.2.2).
+pub struct TcpTransport {
+    timeout: Duration,
+}
+
+impl TcpTransport {
+    /// Transport giving up on a query after `timeout`, connecting included.
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+
+    async fn exchange(&self, request: &DnsMessage, server: SocketAddr) -> io::Result<DnsMessage> {
+        let exchange = async {
+            let mut stream = TcpStream::connect(server).await?;
+            exchange_framed(&mut stream, &request.to_bytes()).await
+        };
+        let res_buffer = timeout(self.timeout, exchange).await.unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "nameserver did not respond over TCP",
+            ))
+        })?;
+
+        let response = match DnsMessage::from_bytes(&res_buffer) {
+            Ok(response) => response,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "malformed response over TCP",
+                ))
+            }
+        };
+
+        if response.header.id != request.header.id
+            || !is_same_question(&response.questions, &request.questions)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "response question does not match the query",
+            ));
+        }
+        Ok(response)
+    }
+}
+
+impl Transport for TcpTransport {
+    fn query<'a>(
+        &'a self,
+        request: &'a DnsMessage,
+        server: SocketAddr,
+    ) -> BoxFuture<'a, io::Result<DnsMessage>> {
+        Box::pin(self.exchange(request, server))
+    }
+}
+
+/// Write `request` to a stream transport prefixed with its length, and read
+/// back the response framed the same way.
+pub(crate) async fn exchange_framed<S>(stream: &mut S, request: &[u8]) -> io::Result<Vec<u8>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut framed = (request.len() as u16).to_be_bytes().to_vec();
+    framed.extend_from_slice(request);
+    stream.write_all(&framed).await?;
+    stream.flush().await?;
+
+    let len = stream.read_u16().await?;
+    let mut response = vec![0u8; len.into()];
+    stream.read_exact(&mut response).await?;
+    Ok(response)
+}
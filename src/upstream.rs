@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::seq::SliceRandom;
+use tokio::time::Instant;
+
+use crate::config::Config;
+
+/// Weight of a new round-trip time sample in the smoothed estimate.
+const RTT_WEIGHT: f64 = 0.3;
+
+/// Factor the estimate of a nameserver decays by whenever another one is
+/// preferred, so slow servers are eventually probed again.
+const RTT_DECAY: f64 = 0.98;
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    skip_until: Option<Instant>,
+    /// Smoothed round-trip time, unknown until the server first answers.
+    srtt: Option<Duration>,
+}
+
+/// Failure and latency tracking of upstream nameservers.
+///
+/// After `upstream_failure_threshold` consecutive failures a nameserver is
+/// skipped for a cooldown, which doubles with every further failure up to
+/// `upstream_max_backoff`. Once the cooldown is over the server is probed
+/// again by the next query which selects it.
+///
+/// Round-trip times are smoothed per nameserver, and faster servers are
+/// preferred over slower ones.
+pub struct UpstreamHealth {
+    servers: Mutex<HashMap<IpAddr, Health>>,
+    failure_threshold: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+    stable_order: bool,
+}
+
+impl UpstreamHealth {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            servers: Mutex::new(HashMap::new()),
+            failure_threshold: config.upstream_failure_threshold,
+            backoff: config.upstream_backoff,
+            max_backoff: config.upstream_max_backoff,
+            stable_order: config.stable_answer_order,
+        }
+    }
+
+    /// Whether the nameserver is not cooling down after failures.
+    pub fn is_available(&self, server: IpAddr) -> bool {
+        match self.servers.lock().unwrap().get(&server) {
+            Some(Health {
+                skip_until: Some(skip_until),
+                ..
+            }) => *skip_until <= Instant::now(),
+            _ => true,
+        }
+    }
+
+    /// Order nameservers by preference: available ones first, fastest
+    /// first, then those cooling down, as querying a bad server beats not
+    /// querying at all. Servers which never answered yet rank as the fastest
+    /// so they get measured, ties are broken randomly.
+    pub fn rank(&self, candidates: impl IntoIterator<Item = IpAddr>) -> Vec<IpAddr> {
+        let (mut available, mut cooling): (Vec<_>, Vec<_>) = candidates
+            .into_iter()
+            .partition(|server| self.is_available(*server));
+
+        if !self.stable_order {
+            let mut rng = rand::thread_rng();
+            available.shuffle(&mut rng);
+            cooling.shuffle(&mut rng);
+
+            let mut servers = self.servers.lock().unwrap();
+            available.sort_by_key(|server| servers.get(server).and_then(|x| x.srtt));
+
+            for server in available.iter().skip(1) {
+                if let Some(srtt) = servers.get_mut(server).and_then(|x| x.srtt.as_mut()) {
+                    *srtt = srtt.mul_f64(RTT_DECAY);
+                }
+            }
+        }
+
+        available.extend(cooling);
+        available
+    }
+
+    /// The server answered after `rtt`.
+    pub fn record_success(&self, server: IpAddr, rtt: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        let health = servers.entry(server).or_default();
+
+        health.consecutive_failures = 0;
+        health.skip_until = None;
+        health.srtt = Some(match health.srtt {
+            Some(srtt) => srtt.mul_f64(1.0 - RTT_WEIGHT) + rtt.mul_f64(RTT_WEIGHT),
+            None => rtt,
+        });
+    }
+
+    /// The server didn't answer within `rtt`, because another one was
+    /// faster. Its estimate is raised to at least that.
+    pub fn record_slower_than(&self, server: IpAddr, rtt: Duration) {
+        let mut servers = self.servers.lock().unwrap();
+        let health = servers.entry(server).or_default();
+
+        if health.srtt.map_or(true, |srtt| srtt < rtt) {
+            health.srtt = Some(rtt);
+        }
+    }
+
+    pub fn record_failure(&self, server: IpAddr) {
+        let mut servers = self.servers.lock().unwrap();
+        let health = servers.entry(server).or_default();
+
+        health.consecutive_failures += 1;
+        if health.consecutive_failures < self.failure_threshold {
+            return;
+        }
+
+        let exponent = (health.consecutive_failures - self.failure_threshold).min(16);
+        let cooldown = self
+            .backoff
+            .saturating_mul(1 << exponent)
+            .min(self.max_backoff);
+        health.skip_until = Some(Instant::now() + cooldown);
+    }
+}
+
+/// Candidates in random order, or as given if the order must be stable.
+pub fn shuffled<T>(stable_order: bool, candidates: impl IntoIterator<Item = T>) -> Vec<T> {
+    let mut candidates: Vec<_> = candidates.into_iter().collect();
+    if !stable_order {
+        candidates.shuffle(&mut rand::thread_rng());
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    use super::UpstreamHealth;
+    use crate::config::Config;
+
+    #[tokio::test(start_paused = true)]
+    async fn failing_server_is_skipped_during_cooldown() {
+        let health = UpstreamHealth::new(&Config {
+            upstream_failure_threshold: 2,
+            upstream_backoff: Duration::from_secs(1),
+            upstream_max_backoff: Duration::from_secs(3),
+            ..Config::default()
+        });
+        let bad = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let good = IpAddr::from(Ipv4Addr::new(192, 0, 2, 2));
+
+        health.record_failure(bad);
+        assert!(health.is_available(bad));
+
+        health.record_failure(bad);
+        assert!(!health.is_available(bad));
+        for _ in 0..10 {
+            assert_eq!(health.rank([bad, good]), [good, bad]);
+        }
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(health.is_available(bad));
+
+        // Failed probe doubles the cooldown
+        health.record_failure(bad);
+        tokio::time::advance(Duration::from_millis(1500)).await;
+        assert!(!health.is_available(bad));
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(health.is_available(bad));
+
+        // ...up to the maximum
+        health.record_failure(bad);
+        tokio::time::advance(Duration::from_secs(3)).await;
+        assert!(health.is_available(bad));
+
+        // Nothing better to choose from
+        health.record_failure(bad);
+        assert_eq!(health.rank([bad]), [bad]);
+
+        health.record_success(bad, Duration::from_millis(10));
+        assert!(health.is_available(bad));
+    }
+
+    #[test]
+    fn faster_server_is_preferred() {
+        let health = UpstreamHealth::new(&Config::default());
+        let slow = IpAddr::from(Ipv4Addr::new(192, 0, 2, 1));
+        let fast = IpAddr::from(Ipv4Addr::new(192, 0, 2, 2));
+        let unknown = IpAddr::from(Ipv4Addr::new(192, 0, 2, 3));
+
+        health.record_success(slow, Duration::from_millis(200));
+        health.record_success(fast, Duration::from_millis(10));
+        assert_eq!(health.rank([slow, unknown, fast]), [unknown, fast, slow]);
+
+        // Lost a race against the fast server
+        health.record_slower_than(unknown, Duration::from_millis(10));
+        assert_eq!(health.rank([unknown, fast]), [fast, unknown]);
+
+        for _ in 0..10 {
+            assert_eq!(health.rank([slow, fast]), [fast, slow]);
+        }
+
+        // Passed over long enough, the slow server gets another chance
+        let probed = (0..200).any(|_| health.rank([slow, fast])[0] == slow);
+        assert!(probed);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stable_order_keeps_candidate_order() {
+        let health = UpstreamHealth::new(&Config {
+            upstream_failure_threshold: 1,
+            stable_answer_order: true,
+            ..Config::default()
+        });
+        let servers = [1, 2, 3].map(|x| IpAddr::from(Ipv4Addr::new(192, 0, 2, x)));
+
+        for _ in 0..10 {
+            assert_eq!(health.rank(servers), servers);
+        }
+
+        health.record_failure(servers[0]);
+        for _ in 0..10 {
+            assert_eq!(health.rank(servers), [servers[1], servers[2], servers[0]]);
+        }
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::Path;
+
+use thiserror::Error;
+
+use crate::packet::message::DnsMessage;
+use crate::packet::qname::Qname;
+use crate::packet::query_type::QueryType;
+use crate::packet::question::DnsQuestion;
+use crate::packet::record::DnsRecord;
+
+#[derive(Debug, Error)]
+pub enum ZoneError {
+    #[error("line {line}: {reason}")]
+    Syntax { line: usize, reason: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Records served authoritatively instead of being resolved.
+///
+/// The zone file has one record per line in the form `name type ttl rdata`,
+/// for example `printer.internal A 300 10.0.0.7`. Supported types are A,
+/// AAAA, CNAME and TXT, whose rdata is the rest of the line, optionally
+/// quoted. Empty lines and lines starting with `#` or `;` are skipped.
+///
+/// The ttl may be left out, as in `printer.internal A 10.0.0.7`. Such
+/// records get the ttl of the last `$TTL` line before them, or without one
+/// the ttl of the record before them (RFC 2308, section 4).
+#[derive(Debug, Clone, Default)]
+pub struct Zone {
+    records: HashMap<(Qname, QueryType), Vec<DnsRecord>>,
+    names: HashSet<Qname>,
+}
+
+impl Zone {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ZoneError> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ZoneError> {
+        let mut zone = Zone::default();
+        let mut default_ttl = None;
+        let mut last_ttl = None;
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let syntax_error = |reason| ZoneError::Syntax {
+                line: i + 1,
+                reason,
+            };
+
+            let (name, rest) = next_field(line);
+            if name.eq_ignore_ascii_case("$TTL") {
+                let ttl = parse_ttl(rest).map_err(syntax_error)?;
+                default_ttl = Some(ttl);
+                continue;
+            }
+
+            let record = parse_record(line, default_ttl.or(last_ttl)).map_err(syntax_error)?;
+            last_ttl = Some(record.ttl());
+            let domain = record.domain().clone();
+            zone.names.insert(domain.clone());
+            zone.records
+                .entry((domain, record.qtype()))
+                .or_default()
+                .push(record);
+        }
+
+        Ok(zone)
+    }
+
+    /// Answer `question` from the zone, `None` if the name isn't in it. A
+    /// name which is present without records of the requested type gets an
+    /// empty answer, unless it is an alias.
+    pub fn answer(&self, question: &DnsQuestion) -> Option<DnsMessage> {
+        let name = &question.name;
+        if !self.names.contains(name) {
+            return None;
+        }
+
+        let answers = self
+            .records
+            .get(&(name.clone(), question.qtype))
+            .or_else(|| self.records.get(&(name.clone(), QueryType::Cname)))
+            .cloned()
+            .unwrap_or_default();
+
+        let mut response = DnsMessage::new();
+        response.header.flags.response = true;
+        response.header.flags.authoritative_answer = true;
+        response.questions.push(question.clone());
+        response.answers = answers;
+        response.update_header();
+        Some(response)
+    }
+}
+
+/// Split off the first whitespace separated field of `line`.
+fn next_field(line: &str) -> (&str, &str) {
+    let line = line.trim_start();
+    let end = line.find(char::is_whitespace).unwrap_or(line.len());
+    (&line[..end], line[end..].trim())
+}
+
+fn parse_ttl(ttl: &str) -> Result<u32, String> {
+    ttl.parse().map_err(|_| format!("bad ttl {ttl}"))
+}
+
+/// Parse a record line, whose ttl defaults to `default_ttl` if left out.
+fn parse_record(line: &str, default_ttl: Option<u32>) -> Result<DnsRecord, String> {
+    let (name, rest) = next_field(line);
+    let (qtype, rest) = next_field(rest);
+    let (ttl, rdata) = match next_field(rest) {
+        (ttl, rdata) if !rdata.is_empty() && ttl.bytes().all(|x| x.is_ascii_digit()) => {
+            (parse_ttl(ttl)?, rdata)
+        }
+        _ => match default_ttl {
+            Some(ttl) => (ttl, rest),
+            None => return Err("expected name, type, ttl and rdata".into()),
+        },
+    };
+    if rdata.is_empty() {
+        return Err("expected name, type, ttl and rdata".into());
+    }
+
+    let domain = Qname::try_from(name).map_err(|err| format!("bad name {name}: {err}"))?;
+
+    let record = match qtype.to_ascii_uppercase().as_str() {
+        "A" => DnsRecord::A {
+            domain,
+            addr: rdata
+                .parse::<Ipv4Addr>()
+                .map_err(|_| format!("bad IPv4 address {rdata}"))?,
+            ttl,
+        },
+        "AAAA" => DnsRecord::Aaaa {
+            domain,
+            addr: rdata
+                .parse::<Ipv6Addr>()
+                .map_err(|_| format!("bad IPv6 address {rdata}"))?,
+            ttl,
+        },
+        "CNAME" => DnsRecord::Cname {
+            domain,
+            host: Qname::try_from(rdata).map_err(|err| format!("bad name {rdata}: {err}"))?,
+            ttl,
+        },
+        "TXT" => {
+            let text = rdata
+                .strip_prefix('"')
+                .and_then(|x| x.strip_suffix('"'))
+                .unwrap_or(rdata);
+            DnsRecord::Txt {
+                domain,
+                strings: vec![text.as_bytes().to_vec()],
+                ttl,
+            }
+        }
+        _ => return Err(format!("unsupported record type {qtype}")),
+    };
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{Zone, ZoneError};
+    use crate::packet::qname::Qname;
+    use crate::packet::query_type::QueryType;
+    use crate::packet::question::DnsQuestion;
+    use crate::packet::record::DnsRecord;
+
+    const ZONE: &str = r#"
+        # internal hosts
+        printer.internal    A     300 10.0.0.7
+        printer.internal    AAAA  300 fd00::7
+        www.internal        CNAME 300 printer.internal
+        printer.internal    TXT   300 "color laser"
+    "#;
+
+    fn answer(zone: &Zone, name: &str, qtype: QueryType) -> Option<Vec<DnsRecord>> {
+        let question = DnsQuestion::new(Qname::try_from(name).unwrap(), qtype);
+        zone.answer(&question).map(|response| {
+            assert!(response.header.flags.authoritative_answer);
+            response.answers
+        })
+    }
+
+    #[test]
+    fn lookups() {
+        let zone = Zone::parse(ZONE).unwrap();
+
+        let answers = answer(&zone, "Printer.Internal", QueryType::A).unwrap();
+        assert!(matches!(
+            answers[..],
+            [DnsRecord::A { addr, ttl: 300, .. }] if addr == Ipv4Addr::new(10, 0, 0, 7)
+        ));
+
+        let answers = answer(&zone, "printer.internal", QueryType::Txt).unwrap();
+        assert!(matches!(
+            &answers[..],
+            [DnsRecord::Txt { strings, .. }] if strings == &[b"color laser".to_vec()]
+        ));
+
+        let answers = answer(&zone, "www.internal", QueryType::A).unwrap();
+        assert_eq!(answers[0].qtype(), QueryType::Cname);
+
+        // Name exists, type doesn't
+        let answers = answer(&zone, "printer.internal", QueryType::Mx).unwrap();
+        assert!(answers.is_empty());
+
+        assert!(answer(&zone, "example.com", QueryType::A).is_none());
+    }
+
+    #[test]
+    fn ttl_defaults() {
+        let zone = Zone::parse(
+            r#"
+            first.internal   A   600 10.0.0.1
+            second.internal  A   10.0.0.2
+            $TTL 60
+            third.internal   A   10.0.0.3
+            third.internal   TXT 300
+            fourth.internal  A   120 10.0.0.4
+            fifth.internal   A   10.0.0.5
+            "#,
+        )
+        .unwrap();
+
+        for (name, qtype, expected) in [
+            ("first.internal", QueryType::A, 600),
+            // Inherited from the record before
+            ("second.internal", QueryType::A, 600),
+            ("third.internal", QueryType::A, 60),
+            // A lone number is the rdata
+            ("third.internal", QueryType::Txt, 60),
+            ("fourth.internal", QueryType::A, 120),
+            // $TTL wins over the record before
+            ("fifth.internal", QueryType::A, 60),
+        ] {
+            let answers = answer(&zone, name, qtype).unwrap();
+            assert_eq!(answers[0].ttl(), expected, "{name} {qtype}");
+        }
+
+        let answers = answer(&zone, "third.internal", QueryType::Txt).unwrap();
+        assert!(matches!(
+            &answers[..],
+            [DnsRecord::Txt { strings, .. }] if strings == &[b"300".to_vec()]
+        ));
+    }
+
+    #[test]
+    fn syntax_errors() {
+        for (text, line) in [
+            ("host A 300", 1),
+            ("\nhost A 300 10.0.0.300", 2),
+            ("host A ttl 10.0.0.1", 1),
+            ("host MX 300 10 mail", 1),
+            ("host A 10.0.0.1", 1),
+            ("$TTL 1h\nhost A 10.0.0.1", 1),
+            ("$TTL 60\nhost A", 2),
+        ] {
+            match Zone::parse(text) {
+                Err(ZoneError::Syntax { line: x, .. }) => assert_eq!(x, line, "{text}"),
+                other => panic!("{text}: {other:?}"),
+            }
+        }
+    }
+}
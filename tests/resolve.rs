@@ -0,0 +1,52 @@
+use std::net::{Ipv4Addr, UdpSocket};
+use std::process::Command;
+use std::thread;
+
+use custom_dns_server::packet::message::DnsMessage;
+use custom_dns_server::packet::record::DnsRecord;
+
+/// Answer every query on `socket` with an A record of 192.0.2.1 for the
+/// name asked about.
+fn stub_root(socket: UdpSocket) {
+    let mut buf = [0u8; 512];
+    loop {
+        let Ok((len, src)) = socket.recv_from(&mut buf) else {
+            return;
+        };
+        let query = DnsMessage::from_bytes(&buf[..len]).unwrap();
+
+        let mut result = DnsMessage::new();
+        result.header.flags.authoritative_answer = true;
+        result.answers.push(DnsRecord::A {
+            domain: query.questions[0].name.clone(),
+            addr: Ipv4Addr::new(192, 0, 2, 1),
+            ttl: 300,
+        });
+        let reply = DnsMessage::respond_to(&query, result);
+        socket.send_to(&reply.to_bytes(), src).unwrap();
+    }
+}
+
+#[test]
+fn resolve_prints_answer() {
+    let socket = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+    let port = socket.local_addr().unwrap().port();
+    thread::spawn(move || stub_root(socket));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_custom-dns-server"))
+        .args(["resolve", "www.example.com", "A", "--root", "127.0.0.1"])
+        .args(["--nameserver-port", &port.to_string()])
+        .output()
+        .unwrap();
+
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(";; ->>HEADER<<- opcode: QUERY, status: NOERROR, id: "),
+        "{stdout}"
+    );
+    assert!(
+        stdout.contains(";; ANSWER SECTION:\nwww.example.com.\t300\tIN\tA\t192.0.2.1\n"),
+        "{stdout}"
+    );
+}